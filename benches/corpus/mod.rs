@@ -0,0 +1,48 @@
+// The data the benchmarks run on: the files given on the command line, or otherwise a fixed
+// corpus checked into the repository, so results can be compared between commits.
+
+use std::path::{Path, PathBuf};
+
+pub fn files() -> Vec<(String, Vec<u8>)> {
+    let files: Vec<(String, Vec<u8>)> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .map(|name| {
+            let data = std::fs::read(&name).unwrap();
+            (name, data)
+        })
+        .collect();
+    if !files.is_empty() {
+        return files;
+    }
+
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    vec![
+        (
+            "test_data.bin".into(),
+            std::fs::read(root.join("asm_unpackers/test_data.bin")).unwrap(),
+        ),
+        (
+            "screens".into(),
+            read_dir(&root.join("z80_unpacker/example/screens"), "scr"),
+        ),
+        // the first 32kb of the sources of upkr 0.2.3, as text data
+        (
+            "source.txt".into(),
+            std::fs::read(root.join("benches/data/source.txt")).unwrap(),
+        ),
+    ]
+}
+
+fn read_dir(path: &Path, extension: &str) -> Vec<u8> {
+    let mut paths: Vec<_> = std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .flat_map(|path| std::fs::read(path).unwrap())
+        .collect()
+}
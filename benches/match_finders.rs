@@ -0,0 +1,95 @@
+// Compares the suffix array and hash chain match finders: the time to build each finder and
+// query the matches at every position, and the time and compressed size of a greedy parse
+// using only the longest match.
+//
+// Run with `cargo bench --bench match_finders`, optionally followed by `-- <files>` to
+// benchmark other data than the fixed corpus in the repository.
+
+mod corpus;
+
+#[allow(dead_code)]
+#[path = "../src/match_finder.rs"]
+mod match_finder;
+
+#[allow(dead_code)]
+#[path = "../src/hash_chain_finder.rs"]
+mod hash_chain_finder;
+
+use hash_chain_finder::HashChainFinder;
+use match_finder::{FindMatches, MatchFinder};
+use std::time::{Duration, Instant};
+
+fn main() {
+    let files = corpus::files();
+
+    let config = upkr::Config::default();
+    println!(
+        "{:<16} {:>9}  {:<12} {:>10} {:>10} {:>9}",
+        "file", "size", "finder", "all pos", "greedy", "packed"
+    );
+    for (name, data) in &files {
+        let results = [
+            (
+                "suffix array",
+                run(data, &config, || MatchFinder::new(data)),
+            ),
+            (
+                "hash chain",
+                run(data, &config, || {
+                    HashChainFinder::new(data, config.max_offset)
+                }),
+            ),
+        ];
+        for (finder_name, (all_positions, greedy, packed_size)) in results {
+            println!(
+                "{:<16} {:>9}  {:<12} {:>8.1}ms {:>8.1}ms {:>9}",
+                name,
+                data.len(),
+                finder_name,
+                all_positions.as_secs_f64() * 1000.,
+                greedy.as_secs_f64() * 1000.,
+                packed_size
+            );
+        }
+    }
+}
+
+// Returns the time to query all matches at every position, the time of a greedy parse and
+// its compressed size.
+fn run<F: FindMatches>(
+    data: &[u8],
+    config: &upkr::Config,
+    new_finder: impl Fn() -> F,
+) -> (Duration, Duration, usize) {
+    let start = Instant::now();
+    let mut finder = new_finder();
+    let mut num_matches = 0;
+    for pos in 0..data.len() {
+        num_matches += finder.matches(pos).count();
+    }
+    std::hint::black_box(num_matches);
+    let all_positions = start.elapsed();
+
+    let start = Instant::now();
+    let mut finder = new_finder();
+    let mut ops = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        match finder.matches(pos).next() {
+            Some(m) => {
+                ops.push(upkr::Op::Match {
+                    offset: (pos - m.pos) as u32,
+                    len: m.length as u32,
+                });
+                pos += m.length;
+            }
+            None => {
+                ops.push(upkr::Op::Literal(data[pos]));
+                pos += 1;
+            }
+        }
+    }
+    let greedy = start.elapsed();
+
+    (all_positions, greedy, upkr::encode_ops(&ops, config).len())
+}
@@ -0,0 +1,83 @@
+// Measures the time, peak heap usage and number of allocations of the optimal parser at
+// different compression levels, which are dominated by the handling of arrivals and coder
+// states at the higher levels.
+//
+// Run with `cargo bench --bench parsing`, optionally followed by `-- <files>` to benchmark
+// other data than the fixed corpus in the repository.
+
+mod corpus;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const LEVELS: [u8; 4] = [2, 5, 7, 9];
+
+// Counts the allocations and tracks the peak of the allocated bytes.
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+            grow(new_size);
+        }
+        new_ptr
+    }
+}
+
+fn grow(size: usize) {
+    let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(allocated, Ordering::Relaxed);
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn main() {
+    let config = upkr::Config::default();
+    println!(
+        "{:<16} {:>9} {:>6} {:>10} {:>9} {:>11} {:>9}",
+        "file", "size", "level", "time", "peak mem", "allocations", "packed"
+    );
+    for (name, data) in corpus::files() {
+        for level in LEVELS {
+            let allocated = ALLOCATED.load(Ordering::Relaxed);
+            PEAK.store(allocated, Ordering::Relaxed);
+            ALLOCATIONS.store(0, Ordering::Relaxed);
+            let start = Instant::now();
+            let packed_data = upkr::pack(&data, level, &config, None);
+            let time = start.elapsed();
+            let peak = PEAK.load(Ordering::Relaxed) - allocated;
+            println!(
+                "{:<16} {:>9} {:>6} {:>8.1}ms {:>7}kb {:>11} {:>9}",
+                name,
+                data.len(),
+                level,
+                time.as_secs_f64() * 1000.,
+                peak / 1024,
+                ALLOCATIONS.load(Ordering::Relaxed),
+                packed_data.len()
+            );
+        }
+    }
+}
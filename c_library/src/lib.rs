@@ -28,11 +28,8 @@ pub extern "C" fn upkr_uncompress(output_buffer: *mut u8, output_buffer_size: us
     let output_buffer = unsafe { std::slice::from_raw_parts_mut(output_buffer, output_buffer_size)};
     let input_buffer = unsafe { std::slice::from_raw_parts(input_buffer, input_size)};
 
-    match upkr::unpack(input_buffer, &config(), output_buffer.len()) {
-        Ok(unpacked_data) => {
-            output_buffer[..unpacked_data.len()].copy_from_slice(&unpacked_data);
-            unpacked_data.len() as isize
-        }
+    match upkr::unpack_into(input_buffer, output_buffer, &config()) {
+        Ok(size) => size as isize,
         Err(upkr::UnpackError::OverSize { size, .. }) => size as isize,
         Err(other) => {
             eprintln!("[upkr] compressed data corrupt: {}", other);
@@ -9,6 +9,11 @@
                  on very old CPUs.
     The encoder and decoder need to be configured to use the same varianet.
 
+    Setting UPKR_LITERAL_CONTEXT_BITS to N (1-8) selects the contexts of the is match
+    bit and the literals by the top N bits of the previous byte, matching
+    `upkr --literal-context-bits N`. This multiplies the number of literal contexts
+    by 2^N. It defaults to 0, a single set of literal contexts.
+
     upkr compressed data is a rANS byte-/bit-stream encoding a series of literal
     byte values and back-references as probability encoded bits.
 
@@ -22,8 +27,10 @@
     that shared the same context while encoding also share the same context while decoding.
     The contexts are:
     - is match
-    - has offset
     - literal bit N (0-7) with already decoded highest bits of literal == M (255 total)
+      (these first 256 contexts exist once per previous byte bucket with
+      UPKR_LITERAL_CONTEXT_BITS)
+    - has offset
     - offset bit N (one less than max offset bits)
     - has offset bit N (max offset bits)
     - length bit N (one less then max length bits)
@@ -55,8 +62,13 @@ typedef unsigned char u8;
 typedef unsigned short u16;
 typedef unsigned long u32;
 
+#ifndef UPKR_LITERAL_CONTEXT_BITS
+#define UPKR_LITERAL_CONTEXT_BITS 0
+#endif
+#define UPKR_LITERAL_CONTEXTS ((1 + 255) << UPKR_LITERAL_CONTEXT_BITS)
+
 u8* upkr_data_ptr;
-u8 upkr_probs[1 + 255 + 1 + 2*32 + 2*32]; 
+u8 upkr_probs[UPKR_LITERAL_CONTEXTS + 1 + 2*32 + 2*32];
 #ifdef UPKR_BITSTREAM
 u16 upkr_state;
 u8 upkr_current_byte;
@@ -126,17 +138,23 @@ void* upkr_unpack(void* destination, void* compressed_data) {
     int prev_was_match = 0;
     int offset = 0;
     for(;;) {
+        int literal_base = 0;
+#if UPKR_LITERAL_CONTEXT_BITS > 0
+        // one set of is match and literal contexts per top bits of the previous byte
+        if(write_ptr != (u8*)destination)
+            literal_base = (write_ptr[-1] >> (8 - UPKR_LITERAL_CONTEXT_BITS)) * 256;
+#endif
         // is match
-        if(upkr_decode_bit(0)) {
+        if(upkr_decode_bit(literal_base)) {
             // has offset
-            if(prev_was_match || upkr_decode_bit(256)) {
-                offset = upkr_decode_length(257) - 1;
+            if(prev_was_match || upkr_decode_bit(UPKR_LITERAL_CONTEXTS)) {
+                offset = upkr_decode_length(UPKR_LITERAL_CONTEXTS + 1) - 1;
                 if(offset == 0) {
                     // a 0 offset signals the end of the compressed data
                     break;
                 }
             }
-            int length = upkr_decode_length(257 + 64);
+            int length = upkr_decode_length(UPKR_LITERAL_CONTEXTS + 1 + 64);
             while(length--) {
                 *write_ptr = write_ptr[-offset];
                 ++write_ptr;
@@ -148,7 +166,7 @@ void* upkr_unpack(void* destination, void* compressed_data) {
             // context index. The set top bit ends up at bit position 8 and is not stored.
             int byte = 1;
             while(byte < 256) {
-                int bit = upkr_decode_bit(byte);
+                int bit = upkr_decode_bit(literal_base + byte);
                 byte = (byte << 1) + bit;
             }
             *write_ptr++ = byte;
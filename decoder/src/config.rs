@@ -0,0 +1,182 @@
+use core::fmt;
+
+/// A configuration of which compression format variation to use.
+///
+/// Use `Config::default()` for the standard upkr format.
+///
+/// Compression format variants exist to help with micro-optimizations in uncompression
+/// code on specific platforms.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Shift in bits from a bitstream into the rANS state, rather than whole bytes.
+    /// This decreases the size of the rNAS state to 16 bits which is very useful on
+    /// 8 bit platforms.
+    pub use_bitstream: bool,
+    /// The number of parity contexts (1-15, usually 1, 2 or 4). This can improve compression
+    /// on data that consists of regular groups of 2 or 4 bytes. One example is 32bit ARM
+    /// code, where each instruction is 4 bytes, so `parity_contexts = 4` improves compression
+    /// quite a bit. Defaults to `1`.
+    pub parity_contexts: usize,
+    /// The number of top bits of the previous byte (0-8) used to select the contexts for
+    /// literals and the is match bit. Each bit doubles the number of literal contexts,
+    /// which can improve compression on text and tile data, but also makes packing slower.
+    /// Defaults to `0`.
+    pub literal_context_bits: u8,
+
+    /// Invert the encoding of bits in the rANS coder. `bit = state_lo >= prob` instead of
+    /// `bit = state_lo < prob`.
+    pub invert_bit_encoding: bool,
+    /// The boolean value which encodes a match. Defaults to `true`.
+    pub is_match_bit: bool,
+    /// The boolean value which encodes a new offset (rather than re-using the previous offset).
+    /// Defaults to `true`.
+    pub new_offset_bit: bool,
+    /// The boolean value which encodes that there are more bits comming for length/offset values.
+    /// Defaults to `true`.
+    pub continue_value_bit: bool,
+
+    /// Reverses the bits in the bitstream.
+    pub bitstream_is_big_endian: bool,
+    /// A slightly less accurate, but slightly simpler variation of the prob update in the
+    /// rANS coder, Used for the z80 uncompressor.
+    pub simplified_prob_update: bool,
+
+    /// Disables support for re-using the last offset in the compression format.
+    /// This might save a few bytes when working with very small data.
+    pub no_repeated_offsets: bool,
+    /// Standard upkr encodes the EOF marker in the offset. This encodes it in the match length
+    /// instead.
+    pub eof_in_length: bool,
+
+    /// The number of bits of precision of the context probabilities. Values up to 12 are
+    /// supported, more precision helps on large and very redundant data. Defaults to `8`.
+    pub prob_bits: u8,
+    /// The shift of the adaptation of the literal contexts to the coded bits. Each bit
+    /// moves the probability by about `1/2^literal_update_rate` of the remaining range, so
+    /// smaller values adapt faster, which tends to suit small data. Like the other update
+    /// rates, it has to be at least `2` and less than `prob_bits`. Defaults to `4`.
+    pub literal_update_rate: u8,
+    /// The shift of the adaptation of the is match and new offset contexts. Defaults to `4`.
+    pub flag_update_rate: u8,
+    /// The shift of the adaptation of the offset and length contexts. Defaults to `4`.
+    pub length_update_rate: u8,
+
+    /// The maximum match offset value to encode when compressing.
+    pub max_offset: usize,
+    /// The maximum match length value to encode when compressing.
+    pub max_length: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            use_bitstream: false,
+            parity_contexts: 1,
+            literal_context_bits: 0,
+
+            invert_bit_encoding: false,
+            is_match_bit: true,
+            new_offset_bit: true,
+            continue_value_bit: true,
+
+            bitstream_is_big_endian: false,
+            simplified_prob_update: false,
+
+            no_repeated_offsets: false,
+            eof_in_length: false,
+
+            prob_bits: 8,
+            literal_update_rate: 4,
+            flag_update_rate: 4,
+            length_update_rate: 4,
+
+            max_offset: usize::MAX,
+            max_length: usize::MAX,
+        }
+    }
+}
+
+/// The error returned by `Config::validate` for a config the coder can't work with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// `Config::parity_contexts` is `0` or above `15`
+    ParityContexts,
+    /// `Config::literal_context_bits` is above `8`
+    LiteralContextBits,
+    /// `Config::prob_bits` is above `12`
+    ProbBits,
+    /// An update rate is below `2` or not less than `Config::prob_bits`
+    UpdateRate,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ConfigError::ParityContexts => "parity_contexts has to be between 1 and 15",
+            ConfigError::LiteralContextBits => "literal_context_bits has to be at most 8",
+            ConfigError::ProbBits => "prob_bits has to be at most 12",
+            ConfigError::UpdateRate => {
+                "the update rates have to be at least 2 and less than prob_bits"
+            }
+        })
+    }
+}
+
+impl core::error::Error for ConfigError {}
+
+impl Config {
+    /// Checks that the coder can work with this config.
+    ///
+    /// The unpacking functions return an error for configs failing this check, the
+    /// packing functions of the `upkr` crate panic.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(1..=15).contains(&self.parity_contexts) {
+            return Err(ConfigError::ParityContexts);
+        }
+        if self.literal_context_bits > 8 {
+            return Err(ConfigError::LiteralContextBits);
+        }
+        if self.prob_bits > 12 {
+            return Err(ConfigError::ProbBits);
+        }
+        if self
+            .update_rates()
+            .iter()
+            .any(|&rate| rate < 2 || rate >= self.prob_bits)
+        {
+            return Err(ConfigError::UpdateRate);
+        }
+        Ok(())
+    }
+
+    /// The shortest match length that can be encoded with this config.
+    pub fn min_length(&self) -> usize {
+        if self.eof_in_length { 2 } else { 1 }
+    }
+
+    /// The probability representing certainty, `1 << prob_bits`.
+    pub fn one_prob(&self) -> u32 {
+        1 << self.prob_bits
+    }
+
+    /// The update rates of the literal, flag and length contexts.
+    pub fn update_rates(&self) -> [u8; 3] {
+        [
+            self.literal_update_rate,
+            self.flag_update_rate,
+            self.length_update_rate,
+        ]
+    }
+
+    /// The number of contexts for literals and the is match bit. The contexts for offsets
+    /// and lengths follow them.
+    pub fn num_literal_contexts(&self) -> usize {
+        (256 * self.parity_contexts) << self.literal_context_bits
+    }
+
+    /// The first literal context for a literal at `position` following `prev_byte`.
+    pub fn literal_base(&self, position: usize, prev_byte: u8) -> usize {
+        let bucket = prev_byte as usize >> (8 - self.literal_context_bits);
+        (bucket * self.parity_contexts + position % self.parity_contexts) * 256
+    }
+}
@@ -0,0 +1,88 @@
+use crate::Config;
+
+/// The classes of contexts, which adapt at the rates set in the `Config`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContextClass {
+    /// The bits of literals, see `Config::literal_update_rate`
+    Literal,
+    /// The is match and new offset bits, see `Config::flag_update_rate`
+    Flag,
+    /// The bits of offsets and lengths, see `Config::length_update_rate`
+    Length,
+}
+
+/// The adaptive probabilities of all contexts used to code the bits of a stream.
+///
+/// The probabilities are stored in `C`, which can be a fixed-size array as well as
+/// a `Vec<u16>`.
+#[derive(Clone)]
+pub struct ContextState<C> {
+    contexts: C,
+    invert_bit_encoding: bool,
+    simplified_prob_update: bool,
+    prob_bits: u8,
+    update_rates: [u8; 3],
+}
+
+/// A single context of a `ContextState`.
+pub struct Context<'a> {
+    prob: &'a mut u16,
+    invert_bit_encoding: bool,
+    simplified_prob_update: bool,
+    prob_bits: u8,
+    update_rate: u8,
+}
+
+impl<C: AsMut<[u16]>> ContextState<C> {
+    /// Creates a new state, resetting all probabilities in `contexts`.
+    pub fn new(mut contexts: C, config: &Config) -> ContextState<C> {
+        contexts.as_mut().fill(1 << (config.prob_bits - 1));
+        ContextState {
+            contexts,
+            invert_bit_encoding: config.invert_bit_encoding,
+            simplified_prob_update: config.simplified_prob_update,
+            prob_bits: config.prob_bits,
+            update_rates: config.update_rates(),
+        }
+    }
+
+    /// Returns the context at `index`, adapting at the rate of `class`.
+    pub fn context_mut(&mut self, index: usize, class: ContextClass) -> Context<'_> {
+        Context {
+            prob: &mut self.contexts.as_mut()[index],
+            invert_bit_encoding: self.invert_bit_encoding,
+            simplified_prob_update: self.simplified_prob_update,
+            prob_bits: self.prob_bits,
+            update_rate: self.update_rates[class as usize],
+        }
+    }
+}
+
+impl<'a> Context<'a> {
+    /// The current probability of a `1` bit (before `invert_bit_encoding` is applied).
+    pub fn prob(&self) -> u16 {
+        *self.prob
+    }
+
+    /// Adapts the probability to the coded `bit`.
+    pub fn update(&mut self, bit: bool) {
+        let old = *self.prob as u32;
+        let one_prob = 1u32 << self.prob_bits;
+        let update_rate = self.update_rate as u32;
+        let update_add = 1u32 << (update_rate - 1);
+
+        *self.prob = if self.simplified_prob_update {
+            let offset = if bit ^ self.invert_bit_encoding {
+                one_prob >> update_rate
+            } else {
+                0
+            };
+
+            (offset + old - ((old + update_add) >> update_rate)) as u16
+        } else if bit ^ self.invert_bit_encoding {
+            (old + ((one_prob - old + update_add) >> update_rate)) as u16
+        } else {
+            (old - ((old + update_add) >> update_rate)) as u16
+        };
+    }
+}
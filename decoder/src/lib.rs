@@ -0,0 +1,19 @@
+#![no_std]
+#![deny(missing_docs)]
+
+//! Decompression of the upkr format and variants, without `std` or allocations.
+//!
+//! This is the decoder used by the `upkr` crate, split out for use in firmware and
+//! on microcontrollers. The context probabilities are kept in a fixed-size array
+//! which is sized from `Config::parity_contexts` and `Config::literal_context_bits` at
+//! compile time using `num_contexts`.
+
+mod config;
+mod context_state;
+mod lz;
+mod rans;
+
+pub use config::{Config, ConfigError};
+pub use context_state::{Context, ContextClass, ContextState};
+pub use lz::{Op, OpDecoder, UnpackError, num_contexts, unpack, unpack_to_cells};
+pub use rans::{ByteSource, ONE_PROB, PROB_BITS, RansDecoder, UnexpectedEOF};
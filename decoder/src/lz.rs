@@ -0,0 +1,317 @@
+use crate::{Config, ConfigError};
+use crate::context_state::{ContextClass, ContextState};
+use crate::rans::{ByteSource, RansDecoder, UnexpectedEOF};
+use core::{cell::Cell, fmt};
+
+/// A single LZ operation of the compressed stream.
+#[derive(Copy, Clone, Debug)]
+pub enum Op {
+    /// A literal byte
+    Literal(u8),
+    /// A copy of `len` bytes from `offset` bytes back
+    Match {
+        /// the distance to the start of the copied bytes
+        offset: u32,
+        /// the number of bytes to copy
+        len: u32,
+    },
+}
+
+/// Returns the number of contexts needed for `parity_contexts` and `literal_context_bits`.
+///
+/// This is a `const fn`, so it can be used to size the context array of `unpack`
+/// at compile time.
+pub const fn num_contexts(parity_contexts: usize, literal_context_bits: u8) -> usize {
+    (((1 + 255) * parity_contexts) << literal_context_bits) + 1 + 64 + 64
+}
+
+/// The error type of the decoding functions
+#[derive(Debug)]
+pub enum UnpackError {
+    /// a match offset pointing beyond the start of the unpacked data was encountered
+    OffsetOutOfRange {
+        /// the match offset
+        offset: usize,
+        /// the current position in the uncompressed stream
+        position: usize,
+    },
+    /// The output buffer was too small
+    OverSize {
+        /// the size of the uncompressed data
+        size: usize,
+        /// the size of the output buffer
+        limit: usize,
+    },
+    /// The end of the packed data was reached without an encoded EOF marker
+    UnexpectedEOF,
+    /// An offset or length value was found that exceeded 32bit
+    ValueOverflow,
+    /// The config failed `Config::validate`
+    InvalidConfig(ConfigError),
+}
+
+impl fmt::Display for UnpackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnpackError::OffsetOutOfRange { offset, position } => {
+                write!(f, "match offset out of range: {} > {}", offset, position)
+            }
+            UnpackError::OverSize { size, limit } => {
+                write!(f, "Unpacked data over size limit: {} > {}", size, limit)
+            }
+            UnpackError::UnexpectedEOF => f.write_str("Unexpected end of input data"),
+            UnpackError::ValueOverflow => f.write_str("Overflow while reading value"),
+            UnpackError::InvalidConfig(err) => write!(f, "Invalid config: {}", err),
+        }
+    }
+}
+
+impl core::error::Error for UnpackError {}
+
+impl From<UnexpectedEOF> for UnpackError {
+    fn from(_: UnexpectedEOF) -> UnpackError {
+        UnpackError::UnexpectedEOF
+    }
+}
+
+impl From<ConfigError> for UnpackError {
+    fn from(err: ConfigError) -> UnpackError {
+        UnpackError::InvalidConfig(err)
+    }
+}
+
+/// Uncompress a piece of compressed data into a caller-provided buffer
+///
+/// The context probabilities are kept in an array of `CONTEXTS` `u16`s on the stack,
+/// which has to be `num_contexts(config.parity_contexts, config.literal_context_bits)`.
+/// No memory is allocated.
+///
+/// Returns either the size of the uncompressed data, or an `UnpackError`
+///
+/// # Parameters
+///
+/// - `packed_data`: the compressed data
+/// - `buffer`: the buffer to uncompress into. When the uncompressed data doesn't fit,
+///   the buffer is filled and `UnpackError::OverSize` is returned with the full size
+/// - `config`: the exact compression format config used to compress the data
+///
+/// # Example
+/// ```rust
+/// use upkr_decoder::{Config, num_contexts, unpack};
+///
+/// let packed_data = [
+///     0xb5, 0xde, 0xa7, 0x5a, 0x63, 0x33, 0x1f, 0x79, 0xe0, 0x6f, 0xd2, 0x20, 0x9d, 0x74,
+///     0xb4, 0xdc, 0x97, 0x57, 0xd9, 0x16, 0x50, 0x65,
+/// ];
+/// let mut buffer = [0; 64];
+/// let size =
+///     unpack::<{ num_contexts(1, 0) }>(&packed_data, &mut buffer, &Config::default()).unwrap();
+/// assert_eq!(&buffer[..size], b"Hello, World! Yellow world!");
+/// ```
+pub fn unpack<const CONTEXTS: usize>(
+    packed_data: &[u8],
+    buffer: &mut [u8],
+    config: &Config,
+) -> Result<usize, UnpackError> {
+    config.validate()?;
+    assert!(
+        CONTEXTS == num_contexts(config.parity_contexts, config.literal_context_bits),
+        "CONTEXTS doesn't match config.parity_contexts and config.literal_context_bits"
+    );
+    let decoder = RansDecoder::new(packed_data, config)?;
+    unpack_to_cells(
+        decoder,
+        OpDecoder::new([0; CONTEXTS], config),
+        Cell::from_mut(buffer).as_slice_of_cells(),
+        config,
+    )
+}
+
+/// Uncompress the ops of `op_decoder` into `output`.
+///
+/// As the output is a slice of `Cell`s, it can overlap with the packed data read by
+/// `decoder` for in-place uncompression.
+///
+/// Returns either the size of the uncompressed data, or an `UnpackError`. When the
+/// uncompressed data doesn't fit, `output` is filled and `UnpackError::OverSize` is
+/// returned with the full size. With `config.literal_context_bits` the data can't be
+/// decoded past the end of `output`, so the returned size is only a lower bound.
+pub fn unpack_to_cells<S: ByteSource, C: AsMut<[u16]>>(
+    mut decoder: RansDecoder<S>,
+    mut op_decoder: OpDecoder<C>,
+    output: &[Cell<u8>],
+    config: &Config,
+) -> Result<usize, UnpackError> {
+    loop {
+        let position = op_decoder.position();
+        let prev_byte = match position.checked_sub(1).map(|index| output.get(index)) {
+            None => 0,
+            Some(Some(cell)) => cell.get(),
+            Some(None) if config.literal_context_bits == 0 => 0,
+            Some(None) => {
+                return Err(UnpackError::OverSize {
+                    size: position,
+                    limit: output.len(),
+                });
+            }
+        };
+        let Some(op) = op_decoder.next_op(&mut decoder, prev_byte, config)? else {
+            break;
+        };
+        let end = op_decoder.position();
+        match op {
+            Op::Literal(byte) => {
+                if let Some(cell) = output.get(end - 1) {
+                    cell.set(byte);
+                }
+            }
+            Op::Match { offset, len } => {
+                for i in (end - len as usize)..end.min(output.len()) {
+                    output[i].set(output[i - offset as usize].get());
+                }
+            }
+        }
+    }
+
+    let size = op_decoder.position();
+    if size > output.len() {
+        return Err(UnpackError::OverSize {
+            size,
+            limit: output.len(),
+        });
+    }
+    Ok(size)
+}
+
+/// Decodes the op stream of packed data one `Op` at a time.
+#[derive(Clone)]
+pub struct OpDecoder<C> {
+    contexts: ContextState<C>,
+    offset: usize,
+    position: usize,
+    prev_was_match: bool,
+}
+
+impl<C: AsMut<[u16]>> OpDecoder<C> {
+    /// Creates a new decoder, keeping the context probabilities in `contexts`.
+    ///
+    /// `contexts` needs to hold at least
+    /// `num_contexts(config.parity_contexts, config.literal_context_bits)` probabilities.
+    pub fn new(mut contexts: C, config: &Config) -> OpDecoder<C> {
+        assert!(
+            contexts.as_mut().len()
+                >= num_contexts(config.parity_contexts, config.literal_context_bits)
+        );
+        OpDecoder {
+            contexts: ContextState::new(contexts, config),
+            offset: usize::MAX,
+            position: 0,
+            prev_was_match: false,
+        }
+    }
+
+    /// Starts decoding at `position`, with the preceding data as a preset dictionary.
+    pub fn with_position(mut self, position: usize) -> OpDecoder<C> {
+        self.position = position;
+        self
+    }
+
+    /// Continues with the next segment of a stream packed with `pack_multi` after an EOF
+    /// marker. The offset and match state restart like at the start of the stream, while
+    /// the contexts and the position are kept.
+    pub fn next_segment(&mut self) {
+        self.offset = usize::MAX;
+        self.prev_was_match = false;
+    }
+
+    /// The position in the uncompressed stream of the next op.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Decodes the next op, returning `None` once the EOF marker is reached.
+    ///
+    /// `prev_byte` is the last uncompressed byte before `position()`, or `0` at the start.
+    /// It is only used with `config.literal_context_bits`.
+    pub fn next_op<S: ByteSource>(
+        &mut self,
+        decoder: &mut RansDecoder<S>,
+        prev_byte: u8,
+        config: &Config,
+    ) -> Result<Option<Op>, UnpackError> {
+        fn decode_length<S: ByteSource, C: AsMut<[u16]>>(
+            decoder: &mut RansDecoder<S>,
+            contexts: &mut ContextState<C>,
+            mut context_index: usize,
+            config: &Config,
+        ) -> Result<usize, UnpackError> {
+            let mut length = 0;
+            let mut bit_pos = 0;
+            while decoder.decode_with_context(
+                &mut contexts.context_mut(context_index, ContextClass::Length),
+            )? == config.continue_value_bit
+            {
+                length |= (decoder.decode_with_context(
+                    &mut contexts.context_mut(context_index + 1, ContextClass::Length),
+                )? as usize)
+                    << bit_pos;
+                bit_pos += 1;
+                if bit_pos >= 32 {
+                    return Err(UnpackError::ValueOverflow);
+                }
+                context_index += 2;
+            }
+            Ok(length | (1 << bit_pos))
+        }
+
+        let contexts = &mut self.contexts;
+        let literal_base = config.literal_base(self.position, prev_byte);
+        let num_literal_contexts = config.num_literal_contexts();
+        if decoder
+            .decode_with_context(&mut contexts.context_mut(literal_base, ContextClass::Flag))?
+            == config.is_match_bit
+        {
+            if config.no_repeated_offsets
+                || self.prev_was_match
+                || decoder.decode_with_context(
+                    &mut contexts.context_mut(num_literal_contexts, ContextClass::Flag),
+                )? == config.new_offset_bit
+            {
+                self.offset = decode_length(decoder, contexts, num_literal_contexts + 1, config)?
+                    - if config.eof_in_length { 0 } else { 1 };
+                if self.offset == 0 {
+                    return Ok(None);
+                }
+            }
+            let length = decode_length(decoder, contexts, num_literal_contexts + 65, config)?;
+            if config.eof_in_length && length == 1 {
+                return Ok(None);
+            }
+            if self.offset > self.position {
+                return Err(UnpackError::OffsetOutOfRange {
+                    offset: self.offset,
+                    position: self.position,
+                });
+            }
+            self.position += length;
+            self.prev_was_match = true;
+            Ok(Some(Op::Match {
+                offset: self.offset as u32,
+                len: length as u32,
+            }))
+        } else {
+            let mut context_index = 1;
+            let mut byte = 0;
+            for i in (0..8).rev() {
+                let bit = decoder.decode_with_context(
+                    &mut contexts.context_mut(literal_base + context_index, ContextClass::Literal),
+                )?;
+                context_index = (context_index << 1) | bit as usize;
+                byte |= (bit as u8) << i;
+            }
+            self.position += 1;
+            self.prev_was_match = false;
+            Ok(Some(Op::Literal(byte)))
+        }
+    }
+}
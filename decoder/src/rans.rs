@@ -0,0 +1,157 @@
+use crate::{Config, context_state::Context};
+use core::{cell::Cell, fmt};
+
+/// The default number of bits of precision of the context probabilities,
+/// see `Config::prob_bits`.
+pub const PROB_BITS: u32 = 8;
+/// The probability representing certainty with the default `PROB_BITS`.
+pub const ONE_PROB: u32 = 1 << PROB_BITS;
+
+/// A source of packed bytes for the `RansDecoder`.
+pub trait ByteSource {
+    /// Returns the next byte, or `None` at the end of the input.
+    fn next_byte(&mut self) -> Option<u8>;
+}
+
+impl ByteSource for &[u8] {
+    fn next_byte(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.split_first()?;
+        *self = rest;
+        Some(byte)
+    }
+}
+
+impl ByteSource for &[Cell<u8>] {
+    fn next_byte(&mut self) -> Option<u8> {
+        let (byte, rest) = self.split_first()?;
+        *self = rest;
+        Some(byte.get())
+    }
+}
+
+/// The rANS entropy decoder, reading packed data from a `ByteSource`.
+#[derive(Clone)]
+pub struct RansDecoder<S> {
+    source: S,
+    pos: usize,
+    state: u32,
+    use_bitstream: bool,
+    byte: u8,
+    bits_left: u8,
+    invert_bit_encoding: bool,
+    bitstream_is_big_endian: bool,
+    prob_bits: u8,
+}
+
+/// The error returned when the packed data ends before decoding is finished.
+#[derive(Debug)]
+pub struct UnexpectedEOF;
+
+impl fmt::Display for UnexpectedEOF {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Unexpected end of input")
+    }
+}
+
+impl core::error::Error for UnexpectedEOF {}
+
+impl<S: ByteSource> RansDecoder<S> {
+    /// Creates a decoder, reading the initial state from `source`.
+    pub fn new(source: S, config: &Config) -> Result<RansDecoder<S>, UnexpectedEOF> {
+        let mut decoder = RansDecoder::new_lazy(source, config);
+        decoder.refill()?;
+        Ok(decoder)
+    }
+
+    /// Creates a decoder which doesn't read any input before the first bit is decoded.
+    pub fn new_lazy(source: S, config: &Config) -> RansDecoder<S> {
+        RansDecoder {
+            source,
+            pos: 0,
+            state: 0,
+            use_bitstream: config.use_bitstream,
+            byte: 0,
+            bits_left: 0,
+            invert_bit_encoding: config.invert_bit_encoding,
+            bitstream_is_big_endian: config.bitstream_is_big_endian,
+            prob_bits: config.prob_bits,
+        }
+    }
+
+    /// Returns a mutable reference to the byte source.
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    /// Returns the byte source, dropping the decoder.
+    pub fn into_source(self) -> S {
+        self.source
+    }
+
+    /// The number of bytes read from the source so far.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// The current rANS state.
+    pub fn state(&self) -> u32 {
+        self.state
+    }
+
+    /// Decodes a bit with the probability of `context` and updates the context.
+    pub fn decode_with_context(&mut self, context: &mut Context) -> Result<bool, UnexpectedEOF> {
+        let bit = self.decode_bit(context.prob())?;
+        context.update(bit);
+        Ok(bit)
+    }
+
+    fn refill(&mut self) -> Result<(), UnexpectedEOF> {
+        if self.use_bitstream {
+            while self.state < 32768 {
+                if self.bits_left == 0 {
+                    self.byte = self.next_byte()?;
+                    self.bits_left = 8;
+                }
+                if self.bitstream_is_big_endian {
+                    self.state = (self.state << 1) | (self.byte >> 7) as u32;
+                    self.byte <<= 1;
+                } else {
+                    self.state = (self.state << 1) | (self.byte & 1) as u32;
+                    self.byte >>= 1;
+                }
+                self.bits_left -= 1;
+            }
+        } else {
+            while self.state < 4096 {
+                self.state = (self.state << 8) | self.next_byte()? as u32;
+            }
+        }
+        Ok(())
+    }
+
+    fn next_byte(&mut self) -> Result<u8, UnexpectedEOF> {
+        let byte = self.source.next_byte().ok_or(UnexpectedEOF)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    /// Decodes a bit with the fixed probability `prob`, in units of `Config::one_prob`.
+    pub fn decode_bit(&mut self, prob: u16) -> Result<bool, UnexpectedEOF> {
+        self.refill()?;
+
+        let prob = prob as u32;
+        let one_prob = 1 << self.prob_bits;
+        let prob_mask = one_prob - 1;
+
+        let bit = (self.state & prob_mask) < prob;
+
+        let (start, prob) = if bit {
+            (0, prob)
+        } else {
+            (prob, one_prob - prob)
+        };
+        self.state = prob * (self.state >> self.prob_bits) + (self.state & prob_mask) - start;
+
+        Ok(bit ^ self.invert_bit_encoding)
+    }
+}
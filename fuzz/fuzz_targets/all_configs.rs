@@ -5,10 +5,11 @@ fuzz_target!(|data: &[u8]| {
     let mut config = upkr::Config::default();
     let mut level = 1;
     let mut data = data;
-    if data.len() > 2 {
+    if data.len() > 3 {
         let flags1 = data[0];
         let flags2 = data[1];
-        data = &data[2..];
+        let flags3 = data[2];
+        data = &data[3..];
         config.use_bitstream = (flags1 & 1) != 0;
         config.parity_contexts = if (flags1 & 2) == 0 { 1 } else { 2 };
         config.invert_bit_encoding = (flags1 & 4) != 0;
@@ -22,6 +23,13 @@ fuzz_target!(|data: &[u8]| {
         config.max_offset = if (flags2 & 2) == 0 { usize::MAX } else { 32 };
         config.max_length = if (flags2 & 4) == 0 { usize::MAX } else { 5 };
         level = (flags2 >> 3) & 3;
+        config.literal_context_bits = flags2 >> 6;
+        config.prob_bits = 6 + (flags3 & 7).min(6);
+        let max_update_rate = config.prob_bits - 1;
+        let update_rate = |bits: u8| (2 + bits).min(max_update_rate);
+        config.literal_update_rate = update_rate((flags3 >> 3) & 3);
+        config.flag_update_rate = update_rate((flags3 >> 5) & 3);
+        config.length_update_rate = update_rate(1 + (flags3 >> 7));
     }
     let packed = upkr::pack(data, level, &config, None);
     let unpacked = upkr::unpack(&packed, &config, 1024 * 1024).unwrap();
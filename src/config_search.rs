@@ -0,0 +1,149 @@
+use crate::Config;
+
+/// The compression format variations to try in `search_configs`.
+///
+/// Each field selects whether the corresponding `Config` field is varied. Fields which
+/// are not varied keep the value of the base config passed to `search_configs`, so
+/// the search can be restricted to the variants a specific unpacker supports.
+///
+/// The bit polarity options (`invert_bit_encoding`, `is_match_bit`, `new_offset_bit`,
+/// `continue_value_bit`) and `bitstream_is_big_endian` are never varied: they only
+/// change the order of bits in the rANS stream, which changes the compressed size by
+/// at most a byte or two.
+#[derive(Debug, Clone)]
+pub struct ConfigSearch {
+    /// Try both byte-wise and bitstream rANS (`Config::use_bitstream`).
+    pub use_bitstream: bool,
+    /// Try 1, 2 and 4 parity contexts (`Config::parity_contexts`).
+    pub parity_contexts: bool,
+    /// Try both prob update variants (`Config::simplified_prob_update`).
+    pub simplified_prob_update: bool,
+    /// Try with and without repeated offsets (`Config::no_repeated_offsets`).
+    pub no_repeated_offsets: bool,
+    /// Try both EOF marker encodings (`Config::eof_in_length`).
+    pub eof_in_length: bool,
+    /// Try a few combinations of `Config::literal_update_rate`, `Config::flag_update_rate`
+    /// and `Config::length_update_rate`. As this multiplies the number of candidates by
+    /// eight, it is off by default.
+    pub update_rates: bool,
+}
+
+impl Default for ConfigSearch {
+    fn default() -> ConfigSearch {
+        ConfigSearch {
+            use_bitstream: true,
+            parity_contexts: true,
+            simplified_prob_update: true,
+            no_repeated_offsets: true,
+            eof_in_length: true,
+            update_rates: false,
+        }
+    }
+}
+
+// the literal, flag and length update rates tried by `ConfigSearch::update_rates`:
+// slower adapting literals and lengths, and faster adapting flags
+const UPDATE_RATES: [[u8; 3]; 8] = [
+    [4, 4, 4],
+    [5, 4, 4],
+    [4, 3, 4],
+    [5, 3, 4],
+    [4, 4, 5],
+    [5, 4, 5],
+    [4, 3, 5],
+    [5, 3, 5],
+];
+
+impl ConfigSearch {
+    /// Returns all candidate configs, varying the selected fields of `base`.
+    pub fn candidates(&self, base: &Config) -> Vec<Config> {
+        fn values<T: Copy>(enabled: bool, all: &[T], base: T) -> Vec<T> {
+            if enabled { all.to_vec() } else { vec![base] }
+        }
+
+        // with few prob_bits, the slower rates aren't possible
+        let update_rates: Vec<[u8; 3]> =
+            values(self.update_rates, &UPDATE_RATES, base.update_rates())
+                .into_iter()
+                .filter(|rates| rates.iter().all(|&rate| rate < base.prob_bits))
+                .collect();
+
+        let mut configs = vec![];
+        for use_bitstream in values(self.use_bitstream, &[false, true], base.use_bitstream) {
+            for parity_contexts in values(self.parity_contexts, &[1, 2, 4], base.parity_contexts) {
+                for simplified_prob_update in values(
+                    self.simplified_prob_update,
+                    &[false, true],
+                    base.simplified_prob_update,
+                ) {
+                    for no_repeated_offsets in values(
+                        self.no_repeated_offsets,
+                        &[false, true],
+                        base.no_repeated_offsets,
+                    ) {
+                        for eof_in_length in
+                            values(self.eof_in_length, &[false, true], base.eof_in_length)
+                        {
+                            for &[literal_update_rate, flag_update_rate, length_update_rate] in
+                                &update_rates
+                            {
+                                configs.push(Config {
+                                    use_bitstream,
+                                    parity_contexts,
+                                    simplified_prob_update,
+                                    no_repeated_offsets,
+                                    eof_in_length,
+                                    literal_update_rate,
+                                    flag_update_rate,
+                                    length_update_rate,
+                                    ..base.clone()
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        configs
+    }
+}
+
+/// Compresses the given data with each candidate config of `search`.
+///
+/// Returns the candidate configs with the compressed data, sorted by compressed size,
+/// smallest first. Candidates of equal size keep the order of `ConfigSearch::candidates`.
+///
+/// # Arguments
+/// - `data`: The data to compress
+/// - `level`: The compression level (0-12)
+/// - `base`: The config providing the values of all fields not varied by `search`
+/// - `search`: The config fields to vary
+///
+/// # Example
+/// ```rust
+/// let search = upkr::ConfigSearch {
+///     use_bitstream: false,
+///     ..Default::default()
+/// };
+/// let results = upkr::search_configs(b"Hello, World! Yellow world!", 0, &upkr::Config::default(), &search);
+/// let (best_config, packed_data) = &results[0];
+/// assert!(!best_config.use_bitstream);
+/// assert!(results.iter().all(|(_, p)| p.len() >= packed_data.len()));
+/// ```
+pub fn search_configs(
+    data: &[u8],
+    level: u8,
+    base: &Config,
+    search: &ConfigSearch,
+) -> Vec<(Config, Vec<u8>)> {
+    let mut results: Vec<_> = search
+        .candidates(base)
+        .into_iter()
+        .map(|config| {
+            let packed_data = crate::pack(data, level, &config, None);
+            (config, packed_data)
+        })
+        .collect();
+    results.sort_by_key(|(_, packed_data)| packed_data.len());
+    results
+}
@@ -0,0 +1,270 @@
+use crate::lz::UnpackError;
+use crate::{Config, ProgressCallback};
+
+const MAGIC: &[u8; 4] = b"upkr";
+const VERSION: u8 = 1;
+
+/// The header of a packed container.
+///
+/// A container consists of this header followed by the raw packed data. The header
+/// records everything needed to uncompress the data and verify the result:
+///
+/// | size     | content                                           |
+/// |----------|---------------------------------------------------|
+/// | 4 bytes  | magic `upkr`                                      |
+/// | 1 byte   | container version                                 |
+/// | 2 bytes  | boolean `Config` fields as flags, little endian   |
+/// | 1 byte   | `Config::parity_contexts` in the low nibble,      |
+/// |          | `Config::literal_context_bits` in the high nibble |
+/// | 1 byte   | `Config::prob_bits` in the low nibble,            |
+/// |          | `Config::literal_update_rate` in the high nibble  |
+/// | 1 byte   | `Config::flag_update_rate` in the low nibble,     |
+/// |          | `Config::length_update_rate` in the high nibble   |
+/// | varint   | `Config::max_offset`, `0` meaning unlimited       |
+/// | varint   | `Config::max_length`, `0` meaning unlimited       |
+/// | varint   | size of the uncompressed data                     |
+/// | varint   | margin for overlapped unpacking, zigzag encoded   |
+/// | 4 bytes  | CRC-32 of the uncompressed data, little endian    |
+///
+/// Varints are LEB128 encoded.
+#[derive(Debug, Clone)]
+pub struct ContainerHeader {
+    /// The compression format config used to compress the data
+    pub config: Config,
+    /// The size of the uncompressed data
+    pub unpacked_size: usize,
+    /// The margin needed for overlapped unpacking, see `calculate_margin`
+    pub margin: isize,
+    /// The CRC-32 checksum of the uncompressed data
+    pub crc: u32,
+}
+
+impl ContainerHeader {
+    /// Creates the header for `packed_data`, the result of packing `data` with `config`.
+    pub fn new(data: &[u8], packed_data: &[u8], config: &Config) -> ContainerHeader {
+        ContainerHeader {
+            config: config.clone(),
+            unpacked_size: data.len(),
+            margin: crate::calculate_margin(packed_data, config)
+                .expect("freshly packed data should be valid"),
+            crc: crc32(data),
+        }
+    }
+
+    /// Checks that `unpacked_data` matches the recorded size and checksum.
+    pub fn verify(&self, unpacked_data: &[u8]) -> Result<(), UnpackError> {
+        if unpacked_data.len() != self.unpacked_size {
+            return Err(UnpackError::SizeMismatch {
+                expected: self.unpacked_size,
+                actual: unpacked_data.len(),
+            });
+        }
+        let crc = crc32(unpacked_data);
+        if crc != self.crc {
+            return Err(UnpackError::ChecksumMismatch {
+                expected: self.crc,
+                actual: crc,
+            });
+        }
+        Ok(())
+    }
+
+    /// Appends the encoded header to `out`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the config fails `Config::validate`, as its fields wouldn't fit the header.
+    pub fn write(&self, out: &mut Vec<u8>) {
+        let config = &self.config;
+        crate::lz::assert_valid_config(config);
+        let flags = [
+            config.use_bitstream,
+            config.invert_bit_encoding,
+            config.is_match_bit,
+            config.new_offset_bit,
+            config.continue_value_bit,
+            config.bitstream_is_big_endian,
+            config.simplified_prob_update,
+            config.no_repeated_offsets,
+            config.eof_in_length,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0u16, |flags, (i, &flag)| flags | ((flag as u16) << i));
+
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&flags.to_le_bytes());
+        out.push(config.parity_contexts as u8 | config.literal_context_bits << 4);
+        out.push(config.prob_bits | config.literal_update_rate << 4);
+        out.push(config.flag_update_rate | config.length_update_rate << 4);
+        write_varint(out, unlimited_to_zero(config.max_offset));
+        write_varint(out, unlimited_to_zero(config.max_length));
+        write_varint(out, self.unpacked_size as u64);
+        let margin = (self.margin << 1) ^ (self.margin >> (isize::BITS - 1));
+        write_varint(out, margin as u64);
+        out.extend_from_slice(&self.crc.to_le_bytes());
+    }
+
+    fn read(data: &mut &[u8]) -> Option<ContainerHeader> {
+        if read_bytes(data, 4)? != MAGIC || read_bytes(data, 1)?[0] != VERSION {
+            return None;
+        }
+        let flags = u16::from_le_bytes(read_bytes(data, 2)?.try_into().unwrap());
+        let flag = |i: u32| flags & (1 << i) != 0;
+        let contexts = read_bytes(data, 1)?[0];
+        let parity_contexts = (contexts & 15) as usize;
+        let literal_context_bits = contexts >> 4;
+        let probs = read_bytes(data, 2)?;
+        let config = Config {
+            use_bitstream: flag(0),
+            parity_contexts,
+            literal_context_bits,
+            invert_bit_encoding: flag(1),
+            is_match_bit: flag(2),
+            new_offset_bit: flag(3),
+            continue_value_bit: flag(4),
+            bitstream_is_big_endian: flag(5),
+            simplified_prob_update: flag(6),
+            no_repeated_offsets: flag(7),
+            eof_in_length: flag(8),
+            prob_bits: probs[0] & 15,
+            literal_update_rate: probs[0] >> 4,
+            flag_update_rate: probs[1] & 15,
+            length_update_rate: probs[1] >> 4,
+            max_offset: zero_to_unlimited(read_varint(data)?)?,
+            max_length: zero_to_unlimited(read_varint(data)?)?,
+        };
+        config.validate().ok()?;
+        let unpacked_size = read_varint(data)?.try_into().ok()?;
+        let margin = read_varint(data)?;
+        let margin = (margin >> 1) as i64 ^ -((margin & 1) as i64);
+        let margin = margin.try_into().ok()?;
+        let crc = u32::from_le_bytes(read_bytes(data, 4)?.try_into().unwrap());
+        Some(ContainerHeader {
+            config,
+            unpacked_size,
+            margin,
+            crc,
+        })
+    }
+}
+
+/// Compresses the given data into a self-describing container.
+///
+/// Takes the same arguments as `pack`. The container records the `config`, so
+/// `unpack_container` doesn't need to be told which format variant was used.
+///
+/// # Example
+/// ```rust
+/// let mut config = upkr::Config::default();
+/// config.use_bitstream = true;
+/// let container = upkr::pack_container(b"Hello, World! Yellow world!", 0, &config, None);
+/// let unpacked_data = upkr::unpack_container(&container, 1024).unwrap();
+/// assert_eq!(unpacked_data, b"Hello, World! Yellow world!");
+/// ```
+pub fn pack_container(
+    data: &[u8],
+    level: u8,
+    config: &Config,
+    progress_callback: Option<ProgressCallback>,
+) -> Vec<u8> {
+    let packed_data = crate::pack(data, level, config, progress_callback);
+    let header = ContainerHeader::new(data, &packed_data, config);
+    let mut container = Vec::with_capacity(packed_data.len() + 32);
+    header.write(&mut container);
+    container.extend_from_slice(&packed_data);
+    container
+}
+
+/// Parses the header of a container.
+///
+/// Returns the header and the raw packed data following it, or
+/// `UnpackError::InvalidContainer` if `container` doesn't start with a valid header.
+pub fn read_container_header(container: &[u8]) -> Result<(ContainerHeader, &[u8]), UnpackError> {
+    let mut data = container;
+    let header = ContainerHeader::read(&mut data).ok_or(UnpackError::InvalidContainer)?;
+    Ok((header, data))
+}
+
+/// Uncompress a container created by `pack_container`.
+///
+/// The compression format config is read from the container header and the
+/// uncompressed data is verified against the recorded size and checksum.
+///
+/// # Parameters
+///
+/// - `container`: the container data
+/// - `max_size`: the maximum size of uncompressed data to return. When this is exceeded,
+///   `UnpackError::OverSize` is returned
+pub fn unpack_container(container: &[u8], max_size: usize) -> Result<Vec<u8>, UnpackError> {
+    let (header, packed_data) = read_container_header(container)?;
+    if header.unpacked_size > max_size {
+        return Err(UnpackError::OverSize {
+            size: header.unpacked_size,
+            limit: max_size,
+        });
+    }
+    let result = crate::unpack(packed_data, &header.config, header.unpacked_size);
+    let unpacked_data = result.map_err(|err| match err {
+        UnpackError::OverSize { size, .. } => UnpackError::SizeMismatch {
+            expected: header.unpacked_size,
+            actual: size,
+        },
+        err => err,
+    })?;
+    header.verify(&unpacked_data)?;
+    Ok(unpacked_data)
+}
+
+fn unlimited_to_zero(value: usize) -> u64 {
+    if value == usize::MAX { 0 } else { value as u64 }
+}
+
+fn zero_to_unlimited(value: u64) -> Option<usize> {
+    if value == 0 {
+        Some(usize::MAX)
+    } else {
+        value.try_into().ok()
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_bytes(data, 1)?[0];
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn read_bytes<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if data.len() < len {
+        return None;
+    }
+    let (bytes, rest) = data.split_at(len);
+    *data = rest;
+    Some(bytes)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
@@ -1,65 +1,139 @@
-use crate::match_finder::MatchFinder;
-use crate::rans::RansCoder;
-use crate::ProgressCallback;
-use crate::{lz, Config};
+use crate::{
+    Config, ParseProgress,
+    hash_chain_finder::HashChainFinder,
+    lz,
+    match_finder::{FindMatches, MatchFinder},
+};
 
-pub fn pack(
+// Parses `data[start..]`, allowing matches to reference `data[..start]`.
+pub fn parse(
     data: &[u8],
+    start: usize,
     config: &Config,
-    mut progress_callback: Option<ProgressCallback>,
-) -> Vec<u8> {
-    let mut match_finder = MatchFinder::new(data);
-    let mut rans_coder = RansCoder::new(config);
-    let mut state = lz::CoderState::new(config);
+    progress_callback: Option<ParseProgress>,
+) -> Vec<lz::Op> {
+    parse_segments(data, start, &[data.len()], config, progress_callback)
+        .pop()
+        .unwrap()
+}
+
+// Parses `data[start..]` in segments ending at `segment_ends`, matches can reference all
+// data before them. The match finder is built once for all segments.
+pub fn parse_segments(
+    data: &[u8],
+    start: usize,
+    segment_ends: &[usize],
+    config: &Config,
+    progress_callback: Option<ParseProgress>,
+) -> Vec<Vec<lz::Op>> {
+    if HashChainFinder::is_preferred(data, config.max_offset) {
+        let match_finder = HashChainFinder::new(data, config.max_offset);
+        parse_segments_with_finder(
+            data,
+            start,
+            segment_ends,
+            match_finder,
+            config,
+            progress_callback,
+        )
+    } else {
+        let match_finder = MatchFinder::new(data);
+        parse_segments_with_finder(
+            data,
+            start,
+            segment_ends,
+            match_finder,
+            config,
+            progress_callback,
+        )
+    }
+}
+
+fn parse_segments_with_finder<F: FindMatches>(
+    data: &[u8],
+    start: usize,
+    segment_ends: &[usize],
+    mut match_finder: F,
+    config: &Config,
+    mut progress_callback: Option<ParseProgress>,
+) -> Vec<Vec<lz::Op>> {
+    let mut segment_start = start;
+    segment_ends
+        .iter()
+        .map(|&segment_end| {
+            let mut segment_progress_callback = |pos: usize| {
+                if let Some(ref mut cb) = progress_callback {
+                    cb(segment_start - start + pos);
+                }
+            };
+            let ops = parse_with_finder(
+                &data[..segment_end],
+                segment_start,
+                &mut match_finder,
+                config,
+                Some(&mut segment_progress_callback),
+            );
+            segment_start = segment_end;
+            ops
+        })
+        .collect()
+}
 
-    let mut pos = 0;
+// Parses `data[start..]` with a match finder over `data` or a longer slice of it.
+fn parse_with_finder<F: FindMatches>(
+    data: &[u8],
+    start: usize,
+    match_finder: &mut F,
+    config: &Config,
+    mut progress_callback: Option<ParseProgress>,
+) -> Vec<lz::Op> {
+    let mut ops = vec![];
+    let mut last_offset = 0;
+
+    let mut pos = start;
     while pos < data.len() {
         if let Some(ref mut cb) = progress_callback {
-            cb(pos);
+            cb(pos - start);
         }
         let mut encoded_match = false;
         if let Some(m) = match_finder.matches(pos).next() {
-            let max_offset = config.max_offset.min(1 << (m.length * 3 - 1).min(31));
+            let match_length = m.length.min(data.len() - pos);
+            let max_offset = config.max_offset.min(1 << (match_length * 3 - 1).min(31));
             let offset = pos - m.pos;
-            if offset < max_offset && m.length >= config.min_length() {
-                let length = m.length.min(config.max_length);
-                lz::Op::Match {
+            if offset < max_offset && match_length >= config.min_length() {
+                let length = match_length.min(config.max_length);
+                ops.push(lz::Op::Match {
                     offset: offset as u32,
                     len: length as u32,
-                }
-                .encode(&mut rans_coder, &mut state, config);
+                });
+                last_offset = offset;
                 pos += length;
                 encoded_match = true;
             }
         }
 
-        if !encoded_match {
-            let offset = state.last_offset() as usize;
-            if offset != 0 {
-                let length = data[pos..]
-                    .iter()
-                    .zip(data[(pos - offset)..].iter())
-                    .take_while(|(a, b)| a == b)
-                    .count()
-                    .min(config.max_length);
-                if length >= config.min_length() {
-                    lz::Op::Match {
-                        offset: offset as u32,
-                        len: length as u32,
-                    }
-                    .encode(&mut rans_coder, &mut state, config);
-                    pos += length;
-                    encoded_match = true;
-                }
+        if !encoded_match && last_offset != 0 {
+            let length = data[pos..]
+                .iter()
+                .zip(data[(pos - last_offset)..].iter())
+                .take_while(|(a, b)| a == b)
+                .count()
+                .min(config.max_length);
+            if length >= config.min_length() {
+                ops.push(lz::Op::Match {
+                    offset: last_offset as u32,
+                    len: length as u32,
+                });
+                pos += length;
+                encoded_match = true;
             }
         }
 
         if !encoded_match {
-            lz::Op::Literal(data[pos]).encode(&mut rans_coder, &mut state, config);
+            ops.push(lz::Op::Literal(data[pos]));
             pos += 1;
         }
     }
 
-    lz::encode_eof(&mut rans_coder, &mut state, config);
-    rans_coder.finish()
+    ops
 }
@@ -0,0 +1,195 @@
+use crate::match_finder::{FindMatches, Match};
+
+const NONE: u32 = u32::MAX;
+// the longest window kept, 16M positions take 64MB of chain links
+const MAX_WINDOW_SIZE: usize = 1 << 24;
+// once a match this long is found, the closer one is good enough
+const NICE_LENGTH: usize = 256;
+// Finds matches by following chains of earlier positions starting with the same three bytes.
+// Positions are inserted as the search moves forward and the chain links are kept in a
+// ring buffer covering the reachable window, so memory use is bounded by `max_offset`
+// instead of the size of the data. Searching is limited to `max_chain` candidates per
+// position, so it can miss the longest match when a sequence of three bytes occurs very
+// often.
+//
+// Without a `max_offset` limiting the window, the suffix array is faster on repetitive data
+// and always finds the longest match. Once the window is smaller than the data, the longest
+// matches of the suffix array are often out of reach, and hash chains find more of the
+// reachable ones in less time.
+#[derive(Clone)]
+pub struct HashChainFinder<'a> {
+    data: &'a [u8],
+    head: Vec<u32>,
+    hash_shift: u32,
+    prev: Vec<u32>,
+    max_offset: usize,
+    next_insert: usize,
+
+    max_chain: usize,
+    max_matches_per_length: usize,
+    max_length_diff: usize,
+
+    found: Vec<Match>,
+    // the position and longest match of the previous search
+    last_match: Option<(usize, Match)>,
+}
+
+impl<'a> HashChainFinder<'a> {
+    pub fn new(data: &'a [u8], max_offset: usize) -> HashChainFinder<'a> {
+        let window_size = max_offset
+            .saturating_add(1)
+            .min(data.len())
+            .min(MAX_WINDOW_SIZE)
+            .next_power_of_two();
+        // about one chain per position in the window keeps hash collisions rare
+        let hash_bits = window_size.trailing_zeros().clamp(12, 20);
+        HashChainFinder {
+            data,
+            head: vec![NONE; 1 << hash_bits],
+            hash_shift: 32 - hash_bits,
+            prev: vec![NONE; window_size],
+            max_offset,
+            next_insert: 0,
+            max_chain: 256,
+            max_matches_per_length: 5,
+            max_length_diff: 2,
+            found: Vec::new(),
+            last_match: None,
+        }
+    }
+
+    pub fn with_max_chain(mut self, v: usize) -> HashChainFinder<'a> {
+        self.max_chain = v;
+        self
+    }
+
+    pub fn with_max_matches_per_length(mut self, v: usize) -> HashChainFinder<'a> {
+        self.max_matches_per_length = v;
+        self
+    }
+
+    pub fn with_max_length_diff(mut self, v: usize) -> HashChainFinder<'a> {
+        self.max_length_diff = v;
+        self
+    }
+
+    // whether hash chains are the better match finder for `data`, see above
+    pub fn is_preferred(data: &[u8], max_offset: usize) -> bool {
+        max_offset < data.len()
+    }
+
+    fn key(&self, pos: usize) -> usize {
+        let bytes = u32::from_le_bytes([self.data[pos], self.data[pos + 1], self.data[pos + 2], 0]);
+        (bytes.wrapping_mul(0x9e3779b1) >> self.hash_shift) as usize
+    }
+
+    // inserts all positions before `pos` which can still be reached from it
+    fn insert_until(&mut self, pos: usize) {
+        let window_size = self.prev.len();
+        let window_mask = window_size - 1;
+        if pos < self.next_insert {
+            // searching backwards, rebuild the chains
+            self.head.fill(NONE);
+            self.next_insert = 0;
+        }
+        self.next_insert = self.next_insert.max(pos.saturating_sub(window_size));
+        for p in self.next_insert..pos.min(self.data.len().saturating_sub(2)) {
+            let key = self.key(p);
+            self.prev[p & window_mask] = self.head[key];
+            self.head[key] = p as u32;
+        }
+        self.next_insert = pos;
+    }
+}
+
+impl FindMatches for HashChainFinder<'_> {
+    fn matches(&mut self, pos: usize) -> impl Iterator<Item = Match> {
+        self.insert_until(pos);
+        self.found.clear();
+        if pos + 2 >= self.data.len() {
+            return self.found.drain(..);
+        }
+
+        // a long match continues at the next position, without comparing it again
+        if let Some((last_pos, ref m)) = self.last_match
+            && last_pos + 1 == pos
+            && m.length > NICE_LENGTH
+        {
+            let m = Match {
+                pos: m.pos + 1,
+                length: m.length - 1,
+            };
+            self.found.push(m.clone());
+            self.last_match = Some((pos, m));
+            return self.found.drain(..);
+        }
+
+        let window_size = self.prev.len();
+        let window_mask = window_size - 1;
+        let max_length = self.data.len() - pos;
+        let mut best_length = 0usize;
+        let mut candidate = self.head[self.key(pos)];
+        let mut chain_left = self.max_chain;
+        while candidate != NONE && chain_left > 0 {
+            let candidate_pos = candidate as usize;
+            let offset = pos - candidate_pos;
+            if offset > self.max_offset || offset >= window_size {
+                break;
+            }
+            // skip candidates which can't be long enough without comparing all bytes
+            let min_length = best_length.saturating_sub(self.max_length_diff).max(2);
+            if self.data[candidate_pos + min_length - 1] == self.data[pos + min_length - 1] {
+                let length = match_length(&self.data[pos..], &self.data[candidate_pos..]);
+                if length >= min_length {
+                    self.found.push(Match {
+                        pos: candidate_pos,
+                        length,
+                    });
+                    best_length = best_length.max(length);
+                    if length >= NICE_LENGTH || length == max_length {
+                        break;
+                    }
+                }
+            }
+            candidate = self.prev[candidate_pos & window_mask];
+            chain_left -= 1;
+        }
+
+        // candidates were found closest first, the stable sort keeps that order per length
+        self.found.sort_by_key(|m| std::cmp::Reverse(m.length));
+        let mut current_length = usize::MAX;
+        let mut per_length = 0;
+        let max_length_diff = self.max_length_diff;
+        let max_matches_per_length = self.max_matches_per_length;
+        self.found.retain(|m| {
+            if m.length != current_length {
+                current_length = m.length;
+                per_length = 0;
+            }
+            per_length += 1;
+            m.length + max_length_diff >= best_length && per_length <= max_matches_per_length
+        });
+        self.last_match = self.found.first().map(|m| (pos, m.clone()));
+        self.found.drain(..)
+    }
+}
+
+// The length of the common prefix of `a` and `b`, comparing eight bytes at a time.
+fn match_length(a: &[u8], b: &[u8]) -> usize {
+    let max_length = a.len().min(b.len());
+    let mut length = 0;
+    while length + 8 <= max_length {
+        let a = u64::from_le_bytes(a[length..length + 8].try_into().unwrap());
+        let b = u64::from_le_bytes(b[length..length + 8].try_into().unwrap());
+        if a != b {
+            return length + (a ^ b).trailing_zeros() as usize / 8;
+        }
+        length += 8;
+    }
+    length
+        + a[length..max_length]
+            .iter()
+            .zip(&b[length..max_length])
+            .take_while(|(a, b)| a == b)
+            .count()
+}
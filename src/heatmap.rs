@@ -8,12 +8,26 @@
 /// that reference the literal.
 ///
 /// If the `terminal` feature is enabled, there is a function to write out the
-/// heatmap as a colored hexdump.
+/// heatmap as a colored hexdump. `write_html` writes the same hexdump as a
+/// self-contained HTML page.
 pub struct Heatmap {
     data: Vec<u8>,
     cost: Vec<f32>,
     raw_cost: Vec<f32>,
     literal_index: Vec<usize>,
+    source: Vec<Option<usize>>,
+}
+
+// the 256 color terminal palette used for the hexdump, from expensive to cheap
+const COLORS_256: [u8; 16] = [
+    196, 166, 136, 106, 76, 46, 41, 36, 31, 26, 21, 20, 19, 18, 17, 16,
+];
+
+fn color_index_256(cost: f32) -> usize {
+    ((3. - cost.log2()) * 2.5)
+        .round()
+        .max(0.)
+        .min((COLORS_256.len() - 1) as f32) as usize
 }
 
 impl Heatmap {
@@ -23,6 +37,7 @@ impl Heatmap {
             cost: Vec::new(),
             raw_cost: Vec::new(),
             literal_index: Vec::new(),
+            source: Vec::new(),
         }
     }
 
@@ -30,6 +45,7 @@ impl Heatmap {
         self.data.push(byte);
         self.cost.push(cost);
         self.literal_index.push(self.literal_index.len());
+        self.source.push(None);
     }
 
     pub(crate) fn add_match(&mut self, offset: usize, length: usize, mut cost: f32) {
@@ -39,6 +55,7 @@ impl Heatmap {
             self.literal_index
                 .push(self.literal_index[self.literal_index.len() - offset]);
             self.cost.push(cost);
+            self.source.push(Some(self.source.len() - offset));
         }
     }
 
@@ -57,7 +74,7 @@ impl Heatmap {
             shifted[index] -= delta;
         }
 
-        for (cost, delta) in self.cost.iter_mut().zip(shifted.into_iter()) {
+        for (cost, delta) in self.cost.iter_mut().zip(shifted) {
             *cost += delta;
         }
     }
@@ -66,9 +83,14 @@ impl Heatmap {
     pub fn reverse(&mut self) {
         self.data.reverse();
         self.cost.reverse();
+        self.raw_cost.reverse();
         self.literal_index.reverse();
         for index in self.literal_index.iter_mut() {
-            *index = self.data.len() - *index;
+            *index = self.data.len() - 1 - *index;
+        }
+        self.source.reverse();
+        for index in self.source.iter_mut().flatten() {
+            *index = self.data.len() - 1 - *index;
         }
     }
 
@@ -104,6 +126,174 @@ impl Heatmap {
         self.data[index]
     }
 
+    /// Returns the index of the byte that the byte at `index` was copied from,
+    /// or `None` if it was encoded as a literal
+    pub fn source(&self, index: usize) -> Option<usize> {
+        self.source[index]
+    }
+
+    /// Writes the heatmap as a self-contained HTML page.
+    ///
+    /// The page shows a colored hexdump with a tooltip for each byte, listing its cost,
+    /// raw cost and where a matched byte was copied from, followed by a summary of the
+    /// cost of each region of the data.
+    pub fn write_html(&self, mut out: impl std::io::Write) -> std::io::Result<()> {
+        const BYTES_PER_ROW: usize = 16;
+
+        // keep the summary table at no more than 64 rows
+        let mut region_size = 256;
+        while self.len() > region_size * 64 {
+            region_size *= 2;
+        }
+
+        writeln!(out, "<!DOCTYPE html>")?;
+        writeln!(
+            out,
+            "<html><head><meta charset=\"utf-8\"><title>upkr heatmap</title>"
+        )?;
+        writeln!(out, "<style>")?;
+        writeln!(
+            out,
+            "body {{ font-family: sans-serif; background: #202020; color: #e0e0e0; }}"
+        )?;
+        writeln!(
+            out,
+            ".hex {{ font-family: monospace; white-space: pre; line-height: 1.4; }}"
+        )?;
+        writeln!(out, ".hex span {{ cursor: default; }}")?;
+        writeln!(out, ".l {{ text-decoration: underline; }}")?;
+        writeln!(out, "table {{ border-collapse: collapse; }}")?;
+        writeln!(out, "td, th {{ padding: 2px 8px; text-align: right; }}")?;
+        writeln!(out, "td:first-child {{ font-family: monospace; }}")?;
+        for (i, &color) in COLORS_256.iter().enumerate() {
+            let (r, g, b) = ansi_rgb(color);
+            let fg = if r as u32 * 3 + g as u32 * 6 + b as u32 > 1000 {
+                "#000"
+            } else {
+                "#fff"
+            };
+            writeln!(
+                out,
+                "body:not(.raw) .c{i}, body.raw .r{i}, .k{i} {{ background: #{r:02x}{g:02x}{b:02x}; color: {fg}; }}"
+            )?;
+        }
+        writeln!(out, "</style></head><body>")?;
+
+        let total_cost: f32 = self.raw_cost.iter().sum();
+        writeln!(
+            out,
+            "<h1>upkr heatmap</h1><p>{} bytes packed to {:.1} bytes ({:.3} bits per byte), {} literals.</p>",
+            self.len(),
+            total_cost / 8.,
+            total_cost / self.len().max(1) as f32,
+            (0..self.len()).filter(|&i| self.is_literal(i)).count()
+        )?;
+        write!(out, "<p>Cost per byte in bits:")?;
+        for (bits, label) in [
+            (8., "8+"),
+            (4., "4"),
+            (2., "2"),
+            (1., "1"),
+            (0.5, "1/2"),
+            (0.25, "1/4"),
+            (0.125, "1/8-"),
+        ] {
+            write!(
+                out,
+                " <span class=\"k{}\">&nbsp;{}&nbsp;</span>",
+                color_index_256(bits),
+                label
+            )?;
+        }
+        writeln!(out, ". Literals are underlined.")?;
+        writeln!(
+            out,
+            "<label><input type=\"checkbox\" onchange=\"document.body.classList.toggle('raw', this.checked)\"> \
+             color by raw cost (without spreading the cost of literals across the matches referencing them)</label></p>"
+        )?;
+
+        writeln!(out, "<div class=\"hex\">")?;
+        for row_start in (0..self.len()).step_by(BYTES_PER_ROW) {
+            let row_range = row_start..self.len().min(row_start + BYTES_PER_ROW);
+            if row_start % region_size == 0 {
+                write!(out, "<span id=\"r{:x}\"></span>", row_start)?;
+            }
+            write!(out, "{:06x}  ", row_start)?;
+            for i in row_range.clone() {
+                self.write_html_byte(&mut out, i, &format!("{:02x}", self.data[i]))?;
+                write!(out, " ")?;
+            }
+            write!(out, "{:1$} ", "", (BYTES_PER_ROW - row_range.len()) * 3)?;
+            for i in row_range {
+                let text = match self.data[i] {
+                    b'<' => "&lt;".to_string(),
+                    b'>' => "&gt;".to_string(),
+                    b'&' => "&amp;".to_string(),
+                    byte @ 32..127 => (byte as char).to_string(),
+                    _ => ".".to_string(),
+                };
+                self.write_html_byte(&mut out, i, &text)?;
+            }
+            writeln!(out)?;
+        }
+        writeln!(out, "</div>")?;
+
+        writeln!(out, "<h2>Regions</h2><table>")?;
+        writeln!(
+            out,
+            "<tr><th>region</th><th>bytes</th><th>literals</th><th>packed bytes</th><th>bits per byte</th><th>share of packed size</th></tr>"
+        )?;
+        for start in (0..self.len()).step_by(region_size) {
+            let range = start..self.len().min(start + region_size);
+            let cost: f32 = self.cost[range.clone()].iter().sum();
+            let bits_per_byte = cost / range.len() as f32;
+            writeln!(
+                out,
+                "<tr><td><a href=\"#r{:x}\">{:06x}-{:06x}</a></td><td>{}</td><td>{}</td><td>{:.1}</td><td class=\"k{}\">{:.3}</td><td>{:.1}%</td></tr>",
+                start,
+                start,
+                range.end - 1,
+                range.len(),
+                range.clone().filter(|&i| self.is_literal(i)).count(),
+                cost / 8.,
+                color_index_256(bits_per_byte),
+                bits_per_byte,
+                cost * 100. / total_cost
+            )?;
+        }
+        writeln!(out, "</table>")?;
+        writeln!(out, "</body></html>")?;
+        Ok(())
+    }
+
+    fn write_html_byte(
+        &self,
+        mut out: impl std::io::Write,
+        index: usize,
+        text: &str,
+    ) -> std::io::Result<()> {
+        write!(
+            out,
+            "<span class=\"c{} r{}{}\" title=\"{:06x}: {:.2} bits, raw {:.2} bits, ",
+            color_index_256(self.cost(index)),
+            color_index_256(self.raw_cost(index)),
+            if self.is_literal(index) { " l" } else { "" },
+            index,
+            self.cost(index),
+            self.raw_cost(index)
+        )?;
+        match self.source(index) {
+            Some(source) => write!(
+                out,
+                "match from {:06x} (offset {})",
+                source,
+                source.abs_diff(index)
+            )?,
+            None => write!(out, "literal")?,
+        }
+        write!(out, "\">{}</span>", text)
+    }
+
     #[cfg(feature = "crossterm")]
     /// Print the heatmap as a colored hexdump
     pub fn print_as_hex(&self) -> std::io::Result<()> {
@@ -152,14 +342,9 @@ impl Heatmap {
                     .min((colors.len() - 1) as f32) as usize;
                 out.queue(SetBackgroundColor(colors[color_index]))?;
             } else {
-                let colors = [
-                    196, 166, 136, 106, 76, 46, 41, 36, 31, 26, 21, 20, 19, 18, 17, 16,
-                ];
-                let color_index = ((3. - cost.log2()) * 2.5)
-                    .round()
-                    .max(0.)
-                    .min((colors.len() - 1) as f32) as usize;
-                out.queue(SetBackgroundColor(Color::AnsiValue(colors[color_index])))?;
+                out.queue(SetBackgroundColor(Color::AnsiValue(
+                    COLORS_256[color_index_256(cost)],
+                )))?;
             }
             out.queue(SetAttribute(if heatmap.is_literal(index) {
                 Attribute::Underlined
@@ -186,15 +371,14 @@ impl Heatmap {
             }
 
             let num_spaces = 1 + (bytes_per_row - (row_range.end - row_range.start)) * 3;
-            let gap: String = std::iter::repeat(' ').take(num_spaces).collect();
             stdout
                 .queue(SetAttribute(Attribute::Reset))?
-                .queue(Print(&gap))?;
+                .queue(Print(&" ".repeat(num_spaces)))?;
 
             for i in row_range.clone() {
                 set_color(&mut stdout, self, i, num_colors, report_raw_cost)?;
                 let byte = self.data[i];
-                if byte >= 32 && byte < 127 {
+                if (32..127).contains(&byte) {
                     stdout.queue(Print(format!("{}", byte as char)))?;
                 } else {
                     stdout.queue(Print("."))?;
@@ -211,3 +395,10 @@ impl Heatmap {
         Ok(())
     }
 }
+
+// converts an index of the 256 color terminal palette to rgb
+fn ansi_rgb(color: u8) -> (u8, u8, u8) {
+    let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+    let color = color - 16;
+    (level(color / 36), level(color / 6 % 6), level(color % 6))
+}
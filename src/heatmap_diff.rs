@@ -0,0 +1,217 @@
+use crate::heatmap::Heatmap;
+use std::ops::Range;
+
+/// How a region of a `diff_heatmaps` result changed between the two heatmaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffKind {
+    /// The uncompressed bytes are the same and encoded the same way, only the cost
+    /// may have changed
+    Unchanged,
+    /// The uncompressed bytes were inserted, removed or replaced
+    Changed,
+    /// The uncompressed bytes are the same, but were part of a match in the old heatmap
+    /// and are literals in the new one
+    MatchToLiteral,
+    /// The uncompressed bytes are the same, but were literals in the old heatmap and
+    /// are part of a match in the new one
+    LiteralToMatch,
+}
+
+/// A region of aligned data in the two heatmaps compared by `diff_heatmaps`.
+#[derive(Debug, Clone)]
+pub struct DiffRegion {
+    /// How the region changed
+    pub kind: DiffKind,
+    /// The byte range in the old heatmap, empty for inserted data
+    pub old: Range<usize>,
+    /// The byte range in the new heatmap, empty for removed data
+    pub new: Range<usize>,
+    /// The summed `Heatmap::cost` of the region in the old heatmap, in (fractional) bits
+    pub old_cost: f32,
+    /// The summed `Heatmap::cost` of the region in the new heatmap, in (fractional) bits
+    pub new_cost: f32,
+}
+
+impl DiffRegion {
+    /// The change in cost from the old to the new heatmap, in (fractional) bits
+    pub fn delta(&self) -> f32 {
+        self.new_cost - self.old_cost
+    }
+}
+
+// runs of unchanged bytes are split into regions of at most this size, so that changes
+// in cost stay local
+const MAX_UNCHANGED_REGION: usize = 256;
+
+// Beyond this number of inserted and removed bytes, the rest of the data is reported as
+// one changed region. The memory used for the alignment grows with the square of it.
+const MAX_EDITS: usize = 2048;
+
+/// Compares the heatmaps of two versions of some data.
+///
+/// The uncompressed data of `old` and `new` is aligned with a minimal diff, and then split
+/// into regions of inserted, removed or replaced bytes, and of equal bytes which are
+/// unchanged or turned from matches into literals or vice versa. The regions cover both
+/// heatmaps completely and are returned in order.
+///
+/// # Example
+/// ```rust
+/// let config = upkr::Config::default();
+/// let heatmap = |data: &[u8]| {
+///     let packed_data = upkr::pack(data, 0, &config, None);
+///     upkr::create_heatmap(&packed_data, &config, data.len()).unwrap()
+/// };
+/// let old = heatmap(b"Hello, World! Yellow world!");
+/// let new = heatmap(b"Hello, World! Mellow world!");
+/// let diff = upkr::diff_heatmaps(&old, &new);
+/// let changed: Vec<_> = diff
+///     .iter()
+///     .filter(|region| region.kind == upkr::DiffKind::Changed)
+///     .collect();
+/// assert_eq!(changed.len(), 1);
+/// assert_eq!(changed[0].new, 14..15);
+/// ```
+pub fn diff_heatmaps(old: &Heatmap, new: &Heatmap) -> Vec<DiffRegion> {
+    let old_data: Vec<u8> = (0..old.len()).map(|i| old.byte(i)).collect();
+    let new_data: Vec<u8> = (0..new.len()).map(|i| new.byte(i)).collect();
+
+    let mut regions = vec![];
+    let mut push = |kind: DiffKind, old_range: Range<usize>, new_range: Range<usize>| {
+        if old_range.is_empty() && new_range.is_empty() {
+            return;
+        }
+        regions.push(DiffRegion {
+            kind,
+            old_cost: old_range.clone().fold(0., |sum, i| sum + old.cost(i)),
+            new_cost: new_range.clone().fold(0., |sum, i| sum + new.cost(i)),
+            old: old_range,
+            new: new_range,
+        });
+    };
+
+    let mut old_pos = 0;
+    let mut new_pos = 0;
+    for (old_start, new_start, length) in equal_runs(&old_data, &new_data) {
+        push(DiffKind::Changed, old_pos..old_start, new_pos..new_start);
+
+        let kind_at =
+            |i: usize| match (old.is_literal(old_start + i), new.is_literal(new_start + i)) {
+                (false, true) => DiffKind::MatchToLiteral,
+                (true, false) => DiffKind::LiteralToMatch,
+                _ => DiffKind::Unchanged,
+            };
+        let mut start = 0;
+        while start < length {
+            let kind = kind_at(start);
+            let mut end = start + 1;
+            while end < length
+                && kind_at(end) == kind
+                && (kind != DiffKind::Unchanged || end - start < MAX_UNCHANGED_REGION)
+            {
+                end += 1;
+            }
+            push(
+                kind,
+                old_start + start..old_start + end,
+                new_start + start..new_start + end,
+            );
+            start = end;
+        }
+
+        old_pos = old_start + length;
+        new_pos = new_start + length;
+    }
+    push(DiffKind::Changed, old_pos..old.len(), new_pos..new.len());
+
+    regions
+}
+
+// Returns the runs of equal bytes `(a_start, b_start, length)` of a minimal diff of `a`
+// and `b`, using the greedy algorithm of Myers' "An O(ND) Difference Algorithm".
+fn equal_runs(a: &[u8], b: &[u8]) -> Vec<(usize, usize, usize)> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    let mut runs = vec![(0, 0, prefix)];
+    let a_middle = &a[prefix..a.len() - suffix];
+    let b_middle = &b[prefix..b.len() - suffix];
+    if let Some(middle) = myers(a_middle, b_middle) {
+        runs.extend(
+            middle
+                .into_iter()
+                .map(|(x, y, length)| (prefix + x, prefix + y, length)),
+        );
+    }
+    runs.push((a.len() - suffix, b.len() - suffix, suffix));
+    runs.retain(|&(_, _, length)| length > 0);
+    runs
+}
+
+fn myers(a: &[u8], b: &[u8]) -> Option<Vec<(usize, usize, usize)>> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max_d = ((n + m) as usize).min(MAX_EDITS) as isize;
+    let index = |k: isize| (k + max_d + 1) as usize;
+
+    // the furthest x reached on each diagonal k = x - y, and a copy of the
+    // diagonals -d..=d after each step d for the backtracking
+    let mut v = vec![0isize; 2 * max_d as usize + 3];
+    let mut trace: Vec<Vec<u32>> = vec![];
+    let mut found = false;
+    for d in 0..=max_d {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+                v[index(k + 1)]
+            } else {
+                v[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index(k)] = x;
+            if x >= n && y >= m {
+                found = true;
+                break;
+            }
+        }
+        trace.push((-d..=d).map(|k| v[index(k)] as u32).collect());
+        if found {
+            break;
+        }
+    }
+    if !found {
+        return None;
+    }
+
+    let mut runs = vec![];
+    let (mut x, mut y) = (n, m);
+    for d in (1..trace.len() as isize).rev() {
+        let prev = &trace[d as usize - 1];
+        let prev_v = |k: isize| prev[(k + d - 1) as usize] as isize;
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && prev_v(k - 1) < prev_v(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = prev_v(prev_k);
+        let prev_y = prev_x - prev_k;
+        let (snake_x, snake_y) = if prev_k == k + 1 {
+            (prev_x, prev_y + 1)
+        } else {
+            (prev_x + 1, prev_y)
+        };
+        runs.push((snake_x as usize, snake_y as usize, (x - snake_x) as usize));
+        (x, y) = (prev_x, prev_y);
+    }
+    runs.push((0, 0, x as usize));
+    runs.reverse();
+    Some(runs)
+}
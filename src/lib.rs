@@ -7,111 +7,59 @@
 //! decompression, low memory overhead and very small decompression code
 //! when handoptimized in assembler. (An optimized DOS execuable decompressor is <140 bytes.)
 
-mod context_state;
+mod config_search;
+mod container;
 mod greedy_packer;
+mod hash_chain_finder;
 mod heatmap;
+mod heatmap_diff;
 mod lz;
 mod match_finder;
+mod pack_options;
 mod parsing_packer;
 mod rans;
+mod reader;
+mod symbols;
+mod unpacker_source;
 
+pub use config_search::{ConfigSearch, search_configs};
+pub use container::{ContainerHeader, pack_container, read_container_header, unpack_container};
 pub use heatmap::Heatmap;
-pub use lz::{calculate_margin, create_heatmap, unpack, UnpackError};
+pub use heatmap_diff::{DiffKind, DiffRegion, diff_heatmaps};
+pub use lz::{
+    Op, Trace, TraceEntry, UnpackError, calculate_margin, create_heatmap, encode_ops, op_costs,
+    trace, unpack, unpack_in_place, unpack_into, unpack_multi, unpack_with_dictionary,
+};
+pub use pack_options::PackOptions;
+pub use parsing_packer::ParserConfig;
+pub use reader::UnpackReader;
+pub use symbols::{Symbol, SymbolCost, SymbolCosts, SymbolFileError, parse_symbols};
+pub use unpacker_source::{UnpackerTarget, UnsupportedConfig, unpacker_source};
+pub use upkr_decoder::{Config, ConfigError};
+
+use pack_options::Budget;
+use std::ops::ControlFlow;
 
 /// The type of a callback function to be given to the `pack` function.
 ///
 /// It will be periodically called with the number of bytes of the input already processed.
-pub type ProgressCallback<'a> = &'a mut dyn FnMut(usize);
-
-/// A configuration of which compression format variation to use.
-///
-/// Use `Config::default()` for the standard upkr format.
-///
-/// Compression format variants exist to help with micro-optimizations in uncompression
-/// code on specific platforms.
-
-#[derive(Debug)]
-pub struct Config {
-    /// Shift in bits from a bitstream into the rANS state, rather than whole bytes.
-    /// This decreases the size of the rNAS state to 16 bits which is very useful on
-    /// 8 bit platforms.
-    pub use_bitstream: bool,
-    /// The number of parity contexts (usually 1, 2 or 4). This can improve compression
-    /// on data that consists of regular groups of 2 or 4 bytes. One example is 32bit ARM
-    /// code, where each instruction is 4 bytes, so `parity_contexts = 4` improves compression
-    /// quite a bit. Defaults to `1`.
-    pub parity_contexts: usize,
-
-    /// Invert the encoding of bits in the rANS coder. `bit = state_lo >= prob` instead of
-    /// `bit = state_lo < prob`.
-    pub invert_bit_encoding: bool,
-    /// The boolean value which encodes a match. Defaults to `true`.
-    pub is_match_bit: bool,
-    /// The boolean value which encodes a new offset (rather than re-using the previous offset).
-    /// Defaults to `true`.
-    pub new_offset_bit: bool,
-    /// The boolean value which encodes that there are more bits comming for length/offset values.
-    /// Defaults to `true`.
-    pub continue_value_bit: bool,
-
-    /// Reverses the bits in the bitstream.
-    pub bitstream_is_big_endian: bool,
-    /// A slightly less accurate, but slightly simpler variation of the prob update in the
-    /// rANS coder, Used for the z80 uncompressor.
-    pub simplified_prob_update: bool,
-
-    /// Disables support for re-using the last offset in the compression format.
-    /// This might save a few bytes when working with very small data.
-    pub no_repeated_offsets: bool,
-    /// Standard upkr encodes the EOF marker in the offset. This encodes it in the match length
-    /// instead.
-    pub eof_in_length: bool,
-
-    /// The maximum match offset value to encode when compressing.
-    pub max_offset: usize,
-    /// The maximum match length value to encode when compressing.
-    pub max_length: usize,
-}
-
-impl Default for Config {
-    fn default() -> Config {
-        Config {
-            use_bitstream: false,
-            parity_contexts: 1,
-
-            invert_bit_encoding: false,
-            is_match_bit: true,
-            new_offset_bit: true,
-            continue_value_bit: true,
-
-            bitstream_is_big_endian: false,
-            simplified_prob_update: false,
-
-            no_repeated_offsets: false,
-            eof_in_length: false,
-
-            max_offset: usize::MAX,
-            max_length: usize::MAX,
-        }
-    }
-}
+/// Returning `ControlFlow::Break(())` cancels packing: the remaining data is then parsed as
+/// fast as possible, so the result is still a valid stream.
+pub type ProgressCallback<'a> = &'a mut dyn FnMut(usize) -> ControlFlow<()>;
 
-impl Config {
-    fn min_length(&self) -> usize {
-        if self.eof_in_length {
-            2
-        } else {
-            1
-        }
-    }
-}
+// The progress callback of the parsers. Cancelling is passed to them through the `Budget`.
+type ParseProgress<'a> = &'a mut dyn FnMut(usize);
 
 /// Compresses the given data.
 ///
 /// # Arguments
 /// - `data`: The data to compress
-/// - `level`: The compression level (0-9). Increasing the level by one roughly halves the
-///   compression speed.
+/// - `level`: The compression level (0-12). Increasing the level by one roughly halves the
+///   compression speed. Levels 10-12 add one to three refinement passes to level 9, which
+///   re-run the parse with the statistics learned in the previous pass and keep the
+///   smallest result. When `config.max_offset` is smaller than the data, levels 0 and 1
+///   search matches in hash chains limited to it, which is faster on large data and finds
+///   more reachable matches than the suffix array of the higher levels.
 /// - `config`: The compression format variant to use.
 /// - `progress_callback`: An optional callback which will periodically be called with
 ///   the number of bytes already processed.
@@ -127,21 +75,556 @@ pub fn pack(
     config: &Config,
     progress_callback: Option<ProgressCallback>,
 ) -> Vec<u8> {
-    if level == 0 {
-        greedy_packer::pack(data, config, progress_callback)
+    let options = PackOptions {
+        level,
+        ..Default::default()
+    };
+    pack_internal(data, &[], config, &options, progress_callback)
+}
+
+/// Compresses the given data using multiple threads.
+///
+/// For levels above 0, the input is split into `threads` equally sized blocks which are
+/// parsed concurrently and then encoded into a single standard upkr stream. Blocks are
+/// at least 16kb in size, so small inputs use fewer threads. As the parse of a block
+/// can't take the actual encoding state at its start into account, compression is slightly
+/// worse than with `pack`.
+///
+/// The output only depends on the data, `level`, `config` and `threads`, never on the
+/// scheduling of the threads. With `threads = 1` it is identical to `pack`.
+///
+/// # Arguments
+/// - `data`: The data to compress
+/// - `level`: The compression level (0-12)
+/// - `config`: The compression format variant to use.
+/// - `threads`: The number of threads to use
+/// - `progress_callback`: An optional callback which will periodically be called with
+///   the number of bytes already processed.
+pub fn pack_parallel(
+    data: &[u8],
+    level: u8,
+    config: &Config,
+    threads: usize,
+    progress_callback: Option<ProgressCallback>,
+) -> Vec<u8> {
+    let options = PackOptions {
+        level,
+        threads,
+        ..Default::default()
+    };
+    pack_internal(data, &[], config, &options, progress_callback)
+}
+
+/// Compresses large data in windows, bounding the memory used by the parser.
+///
+/// The data is parsed in blocks of `window_size` bytes. The match finder of each block only
+/// covers the block itself and the up to `min(window_size, config.max_offset)` bytes before
+/// it, so its memory use depends on `window_size` instead of the size of the data. The
+/// probabilities learned while parsing one block seed the cost model of the next, and all
+/// blocks are encoded into a single continuous standard upkr stream, which any unpacker can
+/// uncompress. Matches can't reach further back than the window though, so compression is
+/// slightly worse on data with long distance repetitions.
+///
+/// The ops and the compressed data are still kept in memory. When `window_size` is at
+/// least the size of the data, the output is identical to `pack_parallel`.
+///
+/// # Arguments
+/// - `data`: The data to compress
+/// - `level`: The compression level (0-12)
+/// - `config`: The compression format variant to use.
+/// - `window_size`: The size of the blocks to parse
+/// - `threads`: The number of threads to parse each block with
+/// - `progress_callback`: An optional callback which will periodically be called with
+///   the number of bytes already processed.
+///
+/// # Example
+/// ```rust
+/// let config = upkr::Config::default();
+/// let data = b"Hello, World! Yellow world! Hello, World! Yellow world!".repeat(4);
+/// let packed_data = upkr::pack_windowed(&data, 2, &config, 64, 1, None);
+/// assert_eq!(upkr::unpack(&packed_data, &config, 1024).unwrap(), data);
+/// ```
+pub fn pack_windowed(
+    data: &[u8],
+    level: u8,
+    config: &Config,
+    window_size: usize,
+    threads: usize,
+    progress_callback: Option<ProgressCallback>,
+) -> Vec<u8> {
+    let options = PackOptions {
+        level,
+        threads,
+        window_size,
+        ..Default::default()
+    };
+    pack_with_options(data, config, &options, progress_callback)
+}
+
+/// Compresses the given data with the level, threads and window size of `options`, within
+/// its time budget and memory limit.
+///
+/// # Example
+/// ```rust
+/// use std::time::Duration;
+///
+/// let config = upkr::Config::default();
+/// let data = b"Hello, World! Yellow world!";
+/// let options = upkr::PackOptions {
+///     level: 9,
+///     time_budget: Some(Duration::from_secs(1)),
+///     ..Default::default()
+/// };
+/// let packed_data = upkr::pack_with_options(data, &config, &options, None);
+/// assert_eq!(upkr::unpack(&packed_data, &config, 1024).unwrap(), data);
+/// ```
+pub fn pack_with_options(
+    data: &[u8],
+    config: &Config,
+    options: &PackOptions,
+    progress_callback: Option<ProgressCallback>,
+) -> Vec<u8> {
+    assert!(
+        options.window_size > 0,
+        "window_size needs to be at least 1"
+    );
+    pack_internal(data, &[], config, options, progress_callback)
+}
+
+/// Compresses the given data with a preset dictionary.
+///
+/// Matches can reference the `dictionary` as if it directly preceded `data`, but the
+/// dictionary itself is not part of the compressed data. The compressed data has to be
+/// uncompressed with `unpack_with_dictionary` and the same dictionary. The contexts are
+/// not primed with the dictionary, so uncompressing only needs to start at position
+/// `dictionary.len()` with the dictionary already in the output buffer.
+///
+/// # Arguments
+/// - `data`: The data to compress
+/// - `dictionary`: The dictionary both sides know
+/// - `level`: The compression level (0-12)
+/// - `config`: The compression format variant to use.
+/// - `progress_callback`: An optional callback which will periodically be called with
+///   the number of bytes already processed.
+///
+/// # Example
+/// ```rust
+/// let config = upkr::Config::default();
+/// let dictionary = b"Hello, World!";
+/// let data = b"Hello, World! Yellow world!";
+/// let packed_data = upkr::pack_with_dictionary(data, dictionary, 2, &config, None);
+/// assert!(packed_data.len() < upkr::pack(data, 2, &config, None).len());
+/// let unpacked_data = upkr::unpack_with_dictionary(&packed_data, dictionary, &config, 1024);
+/// assert_eq!(unpacked_data.unwrap(), data);
+/// ```
+pub fn pack_with_dictionary(
+    data: &[u8],
+    dictionary: &[u8],
+    level: u8,
+    config: &Config,
+    progress_callback: Option<ProgressCallback>,
+) -> Vec<u8> {
+    let options = PackOptions {
+        level,
+        ..Default::default()
+    };
+    pack_internal(data, dictionary, config, &options, progress_callback)
+}
+
+/// Compresses several segments into a single stream, which `unpack_multi` splits up again.
+///
+/// Each segment is followed by an EOF marker and a second EOF marker ends the stream, so
+/// the segments can't be empty. Matches can reference earlier segments and the context
+/// probabilities carry over, which compresses better than packing each on its own. The
+/// offset and match state restart with each segment like at the start of a stream, an
+/// unpacker continuing after an EOF marker needs to reset them.
+///
+/// # Example
+/// ```rust
+/// let config = upkr::Config::default();
+/// let segments: [&[u8]; 2] = [b"Hello, World!", b" Yellow world!"];
+/// let packed_data = upkr::pack_multi(&segments, &config, &Default::default(), None);
+/// assert_eq!(upkr::unpack_multi(&packed_data, &config, 1024).unwrap(), segments);
+/// ```
+pub fn pack_multi(
+    segments: &[&[u8]],
+    config: &Config,
+    options: &PackOptions,
+    mut progress_callback: Option<ProgressCallback>,
+) -> Vec<u8> {
+    assert!(
+        segments.iter().all(|segment| !segment.is_empty()),
+        "segments can't be empty"
+    );
+    assert!(
+        options.window_size > 0,
+        "window_size needs to be at least 1"
+    );
+    lz::assert_valid_config(config);
+    let data = segments.concat();
+    let segment_ends: Vec<usize> = segments
+        .iter()
+        .scan(0, |end, segment| {
+            *end += segment.len();
+            Some(*end)
+        })
+        .collect();
+    let budget = Budget::new(options, data.len());
+    let window_size = window_size(&data, config, options, &budget);
+    let history = window_size.min(config.max_offset);
+    let mut segment_ops = vec![];
+    // the state after the EOF marker of the previous segment
+    let mut state = lz::CoderState::new(config);
+    let mut index = 0;
+    while index < segment_ends.len() {
+        // the following segments which fit into one window are parsed with the same match
+        // finder, which is only built over the window and the history in front of it
+        let start = if index > 0 { segment_ends[index - 1] } else { 0 };
+        let mut group_end = index + 1;
+        while group_end < segment_ends.len() && segment_ends[group_end] - start <= window_size {
+            group_end += 1;
+        }
+        let window_start =
+            start.saturating_sub(history) / config.parity_contexts * config.parity_contexts;
+        let window = &data[window_start..segment_ends[group_end - 1]];
+        let window_segment_ends: Vec<usize> = segment_ends[index..group_end]
+            .iter()
+            .map(|end| end - window_start)
+            .collect();
+        let group_ops = parse_segments(
+            window,
+            start - window_start,
+            &window_segment_ends,
+            config,
+            options,
+            &budget,
+            (start > 0).then_some(&state),
+            Some(&mut |pos| report_progress(&budget, &mut progress_callback, start + pos)),
+        );
+        let mut cost_counter = rans::CostCounter::new(config);
+        for (ops, &end) in group_ops.iter().zip(&segment_ends[index..group_end]) {
+            for &op in ops {
+                lz::encode_op(op, &mut cost_counter, &mut state, &data, config);
+            }
+            lz::encode_eof(&mut cost_counter, &mut state, &data, config);
+            state = state.restart(end);
+        }
+        segment_ops.extend(group_ops);
+        index = group_end;
+    }
+    lz::encode_segments(&segment_ops, &data, config)
+}
+
+/// Parses the given data into the list of LZ ops `pack` would encode.
+///
+/// The ops can be inspected or post-processed and then encoded with `encode_ops`.
+///
+/// # Arguments
+/// - `data`: The data to parse
+/// - `level`: The compression level (0-12)
+/// - `config`: The compression format variant to use.
+///
+/// # Example
+/// ```rust
+/// let config = upkr::Config::default();
+/// let data = b"Hello, World! Yellow world!";
+/// let ops = upkr::parse(data, 2, &config);
+/// assert!(ops.iter().any(|op| matches!(op, upkr::Op::Match { .. })));
+/// assert_eq!(upkr::encode_ops(&ops, &config), upkr::pack(data, 2, &config, None));
+/// ```
+pub fn parse(data: &[u8], level: u8, config: &Config) -> Vec<Op> {
+    let options = PackOptions {
+        level,
+        ..Default::default()
+    };
+    parse_internal(data, 0, config, &options, &Budget::unlimited(), None, None)
+}
+
+/// Parses the given data into the list of LZ ops `pack_with_options` would encode.
+///
+/// All of `options` apply like when packing, so encoding the ops with `encode_ops` gives
+/// the same packed data, unless the time budget runs out at a different point.
+///
+/// # Example
+/// ```rust
+/// let config = upkr::Config::default();
+/// let data = b"Hello, World! Yellow world!";
+/// let options = upkr::PackOptions {
+///     level: 2,
+///     window_size: 16,
+///     ..Default::default()
+/// };
+/// let ops = upkr::parse_with_options(data, &config, &options);
+/// let packed_data = upkr::pack_with_options(data, &config, &options, None);
+/// assert_eq!(upkr::encode_ops(&ops, &config), packed_data);
+/// ```
+pub fn parse_with_options(data: &[u8], config: &Config, options: &PackOptions) -> Vec<Op> {
+    assert!(
+        options.window_size > 0,
+        "window_size needs to be at least 1"
+    );
+    let budget = Budget::new(options, data.len());
+    parse_internal(
+        data,
+        0,
+        config,
+        options,
+        &budget,
+        None,
+        Some(&mut |pos| budget.set_progress(pos)),
+    )
+}
+
+fn pack_internal(
+    data: &[u8],
+    dictionary: &[u8],
+    config: &Config,
+    options: &PackOptions,
+    mut progress_callback: Option<ProgressCallback>,
+) -> Vec<u8> {
+    let combined;
+    let full_data = if dictionary.is_empty() {
+        data
     } else {
-        parsing_packer::pack(data, level, config, progress_callback)
+        combined = [dictionary, data].concat();
+        &combined
+    };
+    let budget = Budget::new(options, data.len());
+    let ops = parse_internal(
+        full_data,
+        dictionary.len(),
+        config,
+        options,
+        &budget,
+        None,
+        Some(&mut |pos| report_progress(&budget, &mut progress_callback, pos)),
+    );
+    lz::encode_ops_from(&ops, dictionary, config)
+}
+
+// Passes the progress on to the budget and the callback, cancelling packing on its request.
+fn report_progress(budget: &Budget, progress_callback: &mut Option<ProgressCallback>, pos: usize) {
+    budget.set_progress(pos);
+    if let Some(cb) = progress_callback
+        && cb(pos).is_break()
+    {
+        budget.cancel();
+    }
+}
+
+// Parses `data[start..]`, allowing matches to reference `data[..start]`. The cost model
+// starts with the probabilities of `initial_state` if given.
+fn parse_internal(
+    data: &[u8],
+    start: usize,
+    config: &Config,
+    options: &PackOptions,
+    budget: &Budget,
+    initial_state: Option<&lz::CoderState>,
+    mut progress_callback: Option<ParseProgress>,
+) -> Vec<Op> {
+    lz::assert_valid_config(config);
+    let window_size = window_size(data, config, options, budget);
+    if window_size >= data.len() - start {
+        return parse_window(
+            data,
+            start,
+            config,
+            options,
+            budget,
+            initial_state,
+            progress_callback,
+        );
+    }
+
+    // Each block is parsed with only the history matches can reach in front of it, the
+    // start of the window is kept at the same parity as the block.
+    let history = window_size.min(config.max_offset);
+    let mut ops = vec![];
+    let mut state: Option<lz::CoderState> = initial_state.cloned();
+    let mut block_start = start;
+    while block_start < data.len() {
+        let block_end = block_start.saturating_add(window_size).min(data.len());
+        let window_start =
+            block_start.saturating_sub(history) / config.parity_contexts * config.parity_contexts;
+        let window = &data[window_start..block_end];
+        let start_in_window = block_start - window_start;
+        let mut block_progress_callback = |pos: usize| {
+            if let Some(ref mut cb) = progress_callback {
+                cb(block_start - start + pos);
+            }
+        };
+        let block_ops = parse_window(
+            window,
+            start_in_window,
+            config,
+            options,
+            budget,
+            state.as_ref(),
+            Some(&mut block_progress_callback),
+        );
+
+        // carry the probabilities at the end of this block over to the parse of the next
+        if options.parser_config().is_some() {
+            let mut block_state = state.map_or_else(
+                || lz::CoderState::new(config).restart(start_in_window),
+                |state| state.restart(start_in_window),
+            );
+            let mut cost_counter = rans::CostCounter::new(config);
+            for &op in &block_ops {
+                lz::encode_op(op, &mut cost_counter, &mut block_state, window, config);
+            }
+            state = Some(block_state);
+        }
+        ops.extend(block_ops);
+        block_start = block_end;
+    }
+    ops
+}
+
+// The size of the blocks `data` is parsed in, the window size of `options` reduced to fit
+// the suffix array into the memory limit.
+fn window_size(data: &[u8], config: &Config, options: &PackOptions, budget: &Budget) -> usize {
+    let uses_suffix_array = match options.parser_config() {
+        Some(parser_config) => !parser_config.uses_hash_chain(data, config),
+        None => !hash_chain_finder::HashChainFinder::is_preferred(data, config.max_offset),
+    };
+    match budget.memory_limit() {
+        Some(limit) if uses_suffix_array => options
+            .window_size
+            .min(parsing_packer::max_window_size(limit)),
+        _ => options.window_size,
+    }
+}
+
+// Parses `data[start..]` in segments ending at `segment_ends`, each followed by an EOF
+// marker like in `pack_multi`. Several segments are parsed in a single window with one
+// match finder, a single one can be larger than the window.
+#[allow(clippy::too_many_arguments)]
+fn parse_segments(
+    data: &[u8],
+    start: usize,
+    segment_ends: &[usize],
+    config: &Config,
+    options: &PackOptions,
+    budget: &Budget,
+    initial_state: Option<&lz::CoderState>,
+    progress_callback: Option<ParseProgress>,
+) -> Vec<Vec<Op>> {
+    if let [segment_end] = segment_ends {
+        assert_eq!(*segment_end, data.len());
+        return vec![parse_internal(
+            data,
+            start,
+            config,
+            options,
+            budget,
+            initial_state,
+            progress_callback,
+        )];
+    }
+    match options.parser_config() {
+        Some(parser_config) if !budget.is_out_of_time() => parsing_packer::parse_segments(
+            data,
+            start,
+            segment_ends,
+            &parser_config,
+            config,
+            options.threads,
+            budget,
+            initial_state,
+            progress_callback,
+        ),
+        _ => greedy_packer::parse_segments(data, start, segment_ends, config, progress_callback),
+    }
+}
+
+// Parses `data[start..]` with the parser settings of `options`, or greedily once out of time.
+fn parse_window(
+    data: &[u8],
+    start: usize,
+    config: &Config,
+    options: &PackOptions,
+    budget: &Budget,
+    initial_state: Option<&lz::CoderState>,
+    progress_callback: Option<ParseProgress>,
+) -> Vec<Op> {
+    match options.parser_config() {
+        Some(parser_config) if !budget.is_out_of_time() => parsing_packer::parse(
+            data,
+            start,
+            &parser_config,
+            config,
+            options.threads,
+            budget,
+            initial_state,
+            progress_callback,
+        ),
+        _ => greedy_packer::parse(data, start, config, progress_callback),
     }
 }
 
 /// Estimate the exact (fractional) size of upkr compressed data.
 ///
-/// Note that this currently does NOT work for the bitstream variant.
-pub fn compressed_size(mut data: &[u8]) -> f32 {
-    let mut state = 0;
-    while state < 4096 {
-        state = (state << 8) | data[0] as u32;
-        data = &data[1..];
+/// The initial rANS state is read like the unpacker does, it carries fewer bits of
+/// information than it takes up.
+pub fn compressed_size(data: &[u8], config: &Config) -> f32 {
+    let mut state = 0u32;
+    let mut bits_read = 0;
+    if config.use_bitstream {
+        let big_endian = config.bitstream_is_big_endian;
+        let bits = data.iter().flat_map(|&byte| {
+            (0..8).map(move |i| {
+                if big_endian {
+                    (byte >> (7 - i)) & 1
+                } else {
+                    (byte >> i) & 1
+                }
+            })
+        });
+        for bit in bits {
+            if state >= 32768 {
+                break;
+            }
+            state = (state << 1) | bit as u32;
+            bits_read += 1;
+        }
+    } else {
+        for &byte in data {
+            if state >= 4096 {
+                break;
+            }
+            state = (state << 8) | byte as u32;
+            bits_read += 8;
+        }
     }
-    data.len() as f32 + (state as f32).log2() / 8.
+    ((data.len() * 8 - bits_read) as f32 + (state.max(1) as f32).log2()) / 8.
+}
+
+/// Estimates the size `pack` compresses the data to, without producing the packed data.
+///
+/// The data is parsed like `pack` does, but only the entropy of the parse is summed up,
+/// also when choosing between the refinement passes of levels 10 to 12. This ignores the
+/// quantisation loss of the rANS coder, so the estimate is a lower bound. It is usually
+/// within a few bits of the actual size, but gets further off with `prob_bits` above the
+/// default, for example about 10 bytes low on 3000 bytes of text with `prob_bits` 12.
+///
+/// # Example
+/// ```rust
+/// let config = upkr::Config::default();
+/// let data = b"Hello, World! Yellow world!";
+/// let estimate = upkr::estimate_packed_size(data, 2, &config);
+/// let packed_size = upkr::pack(data, 2, &config, None).len() as f64;
+/// assert!((estimate - packed_size).abs() < 2.);
+/// ```
+pub fn estimate_packed_size(data: &[u8], level: u8, config: &Config) -> f64 {
+    let options = PackOptions {
+        level,
+        ..Default::default()
+    };
+    let budget = Budget::estimate_only();
+    let ops = parse_internal(data, 0, config, &options, &budget, None, None);
+    // the initial state of the rANS encoder is written out in full at the end
+    let initial_state_bits = if config.use_bitstream { 15. } else { 12. };
+    (lz::encoded_bits(&ops, data, config) + initial_state_bits) / 8.
 }
@@ -1,82 +1,251 @@
 use crate::Config;
-use crate::context_state::ContextState;
 use crate::heatmap::Heatmap;
-use crate::rans::{EntropyCoder, RansDecoder};
+use crate::rans::{CostCounter, EntropyCoder, RansCoder, RansDecoder, decoding_cost};
+use std::cell::Cell;
 use thiserror::Error;
+use upkr_decoder::ContextClass::{self, Flag, Length, Literal};
+pub use upkr_decoder::Op;
+use upkr_decoder::{Context, ContextState, num_contexts, unpack_to_cells};
 
-#[derive(Copy, Clone, Debug)]
-pub enum Op {
-    Literal(u8),
-    Match { offset: u32, len: u32 },
+/// The op decoder used by the unpack functions, keeping its contexts on the heap.
+pub type OpDecoder = upkr_decoder::OpDecoder<Vec<u16>>;
+
+pub fn new_op_decoder(config: &Config) -> OpDecoder {
+    OpDecoder::new(
+        vec![0; num_contexts(config.parity_contexts, config.literal_context_bits)],
+        config,
+    )
 }
 
-impl Op {
-    pub fn encode(&self, coder: &mut dyn EntropyCoder, state: &mut CoderState, config: &Config) {
-        let literal_base = state.pos % state.parity_contexts * 256;
-        match *self {
-            Op::Literal(lit) => {
-                encode_bit(coder, state, literal_base, !config.is_match_bit);
-                let mut context_index = 1;
-                for i in (0..8).rev() {
-                    let bit = (lit >> i) & 1 != 0;
-                    encode_bit(coder, state, literal_base + context_index, bit);
-                    context_index = (context_index << 1) | bit as usize;
-                }
-                state.prev_was_match = false;
-                state.pos += 1;
+// `data` is the uncompressed data, at least up to `state.pos()`. It is only read for the
+// previous byte selecting the literal contexts.
+pub fn encode_op(
+    op: Op,
+    coder: &mut dyn EntropyCoder,
+    state: &mut CoderState,
+    data: &[u8],
+    config: &Config,
+) {
+    let literal_base = literal_base(state, data, config);
+    let num_literal_contexts = config.num_literal_contexts();
+    match op {
+        Op::Literal(lit) => {
+            encode_bit(coder, state, literal_base, Flag, !config.is_match_bit);
+            let mut context_index = 1;
+            for i in (0..8).rev() {
+                let bit = (lit >> i) & 1 != 0;
+                encode_bit(coder, state, literal_base + context_index, Literal, bit);
+                context_index = (context_index << 1) | bit as usize;
             }
-            Op::Match { offset, len } => {
-                encode_bit(coder, state, literal_base, config.is_match_bit);
-                let mut new_offset = true;
-                if !state.prev_was_match && !config.no_repeated_offsets {
-                    new_offset = offset != state.last_offset;
-                    encode_bit(
-                        coder,
-                        state,
-                        256 * state.parity_contexts,
-                        new_offset == config.new_offset_bit,
-                    );
-                }
-                assert!(offset as usize <= config.max_offset);
-                if new_offset {
-                    encode_length(
-                        coder,
-                        state,
-                        256 * state.parity_contexts + 1,
-                        offset + if config.eof_in_length { 0 } else { 1 },
-                        config,
-                    );
-                    state.last_offset = offset;
-                }
-                assert!(len as usize >= config.min_length() && len as usize <= config.max_length);
-                encode_length(coder, state, 256 * state.parity_contexts + 65, len, config);
-                state.prev_was_match = true;
-                state.pos += len as usize;
+            state.prev_was_match = false;
+            state.pos += 1;
+        }
+        Op::Match { offset, len } => {
+            encode_bit(coder, state, literal_base, Flag, config.is_match_bit);
+            let mut new_offset = true;
+            if !state.prev_was_match && !config.no_repeated_offsets {
+                new_offset = offset != state.last_offset;
+                encode_bit(
+                    coder,
+                    state,
+                    num_literal_contexts,
+                    Flag,
+                    new_offset == config.new_offset_bit,
+                );
             }
+            assert!(offset as usize <= config.max_offset);
+            if new_offset {
+                encode_length(
+                    coder,
+                    state,
+                    num_literal_contexts + 1,
+                    offset + if config.eof_in_length { 0 } else { 1 },
+                    config,
+                );
+                state.last_offset = offset;
+            }
+            assert!(len as usize >= config.min_length() && len as usize <= config.max_length);
+            encode_length(coder, state, num_literal_contexts + 65, len, config);
+            state.prev_was_match = true;
+            state.pos += len as usize;
+        }
+    }
+}
+
+// Returns the cost of encoding `op` in `state` without changing `state`. Each context is
+// coded at most once per op, so this is the same cost `encode_op` would count.
+pub fn op_cost(
+    op: Op,
+    cost_counter: &mut CostCounter,
+    state: &mut CoderState,
+    data: &[u8],
+    config: &Config,
+) -> f64 {
+    struct FixedContexts<'a>(&'a mut CostCounter);
+
+    impl EntropyCoder for FixedContexts<'_> {
+        fn encode_bit(&mut self, bit: bool, prob: u16) {
+            self.0.encode_bit(bit, prob);
+        }
+
+        fn encode_with_context(&mut self, bit: bool, context: &mut Context) {
+            self.encode_bit(bit, context.prob());
+        }
+    }
+
+    let (last_offset, prev_was_match, pos) = (state.last_offset, state.prev_was_match, state.pos);
+    cost_counter.reset();
+    encode_op(op, &mut FixedContexts(cost_counter), state, data, config);
+    state.last_offset = last_offset;
+    state.prev_was_match = prev_was_match;
+    state.pos = pos;
+    cost_counter.cost()
+}
+
+// The packing functions return the packed data directly, so they panic on an invalid config.
+pub(crate) fn assert_valid_config(config: &Config) {
+    if let Err(err) = config.validate() {
+        panic!("invalid config: {}", err);
+    }
+}
+
+/// Encodes a list of ops, as returned by `parse`, into compressed data.
+///
+/// This allows to post-process a parse before encoding it.
+///
+/// # Panics
+///
+/// Panics if `config` fails `Config::validate`, a match offset points before the start of
+/// the data or exceeds `config.max_offset`, or a match length is outside the range allowed
+/// by `config`.
+///
+/// # Example
+/// ```rust
+/// use upkr::Op;
+///
+/// let config = upkr::Config::default();
+/// let ops = [Op::Literal(b'a'), Op::Literal(b'b'), Op::Match { offset: 2, len: 4 }];
+/// let packed_data = upkr::encode_ops(&ops, &config);
+/// assert_eq!(upkr::unpack(&packed_data, &config, 1024).unwrap(), b"ababab");
+/// ```
+pub fn encode_ops(ops: &[Op], config: &Config) -> Vec<u8> {
+    encode_ops_from(ops, &[], config)
+}
+
+// Encodes `ops` following the preset `dictionary`.
+pub fn encode_ops_from(ops: &[Op], dictionary: &[u8], config: &Config) -> Vec<u8> {
+    assert_valid_config(config);
+    let mut coder = RansCoder::new(config);
+    let mut state = CoderState::new(config).restart(dictionary.len());
+    let mut data = dictionary.to_vec();
+    for &op in ops {
+        if let Op::Match { offset, .. } = op {
+            assert!(
+                offset as usize <= state.pos(),
+                "match offset out of range: {} > {}",
+                offset,
+                state.pos()
+            );
+        }
+        encode_op(op, &mut coder, &mut state, &data, config);
+        append_op(&mut data, op);
+    }
+    encode_eof(&mut coder, &mut state, &data, config);
+    coder.finish()
+}
+
+// Encodes the ops of consecutive segments of `data`, each followed by an EOF marker, and
+// a final EOF marker ending the stream. Each segment restarts the offset and match state.
+pub fn encode_segments(segment_ops: &[Vec<Op>], data: &[u8], config: &Config) -> Vec<u8> {
+    let mut coder = RansCoder::new(config);
+    let mut state = CoderState::new(config);
+    for ops in segment_ops {
+        for &op in ops {
+            encode_op(op, &mut coder, &mut state, data, config);
         }
+        encode_eof(&mut coder, &mut state, data, config);
+        state = state.restart(state.pos());
+    }
+    encode_eof(&mut coder, &mut state, data, config);
+    coder.finish()
+}
+
+/// Returns the cost in (fractional) bits of encoding each of `ops`.
+///
+/// The sum of the costs plus a few bits for the EOF marker is the compressed size.
+pub fn op_costs(ops: &[Op], config: &Config) -> Vec<f32> {
+    let mut cost_counter = CostCounter::new(config);
+    let mut state = CoderState::new(config);
+    let mut data = vec![];
+    ops.iter()
+        .map(|&op| {
+            cost_counter.reset();
+            encode_op(op, &mut cost_counter, &mut state, &data, config);
+            append_op(&mut data, op);
+            cost_counter.cost() as f32
+        })
+        .collect()
+}
+
+// Returns the cost in (fractional) bits of encoding `ops` of `data`, including the EOF
+// marker, without producing the encoded data.
+pub fn encoded_bits(ops: &[Op], data: &[u8], config: &Config) -> f64 {
+    let mut cost_counter = CostCounter::new(config);
+    let mut state = CoderState::new(config);
+    for &op in ops {
+        encode_op(op, &mut cost_counter, &mut state, data, config);
     }
+    encode_eof(&mut cost_counter, &mut state, data, config);
+    cost_counter.cost()
 }
 
-pub fn encode_eof(coder: &mut dyn EntropyCoder, state: &mut CoderState, config: &Config) {
+pub fn encode_eof(
+    coder: &mut dyn EntropyCoder,
+    state: &mut CoderState,
+    data: &[u8],
+    config: &Config,
+) {
+    let num_literal_contexts = config.num_literal_contexts();
     encode_bit(
         coder,
         state,
-        state.pos % state.parity_contexts * 256,
+        literal_base(state, data, config),
+        Flag,
         config.is_match_bit,
     );
     if !state.prev_was_match && !config.no_repeated_offsets {
         encode_bit(
             coder,
             state,
-            256 * state.parity_contexts,
+            num_literal_contexts,
+            Flag,
             config.new_offset_bit ^ config.eof_in_length,
         );
     }
     if !config.eof_in_length || state.prev_was_match || config.no_repeated_offsets {
-        encode_length(coder, state, 256 * state.parity_contexts + 1, 1, config);
+        encode_length(coder, state, num_literal_contexts + 1, 1, config);
     }
     if config.eof_in_length {
-        encode_length(coder, state, 256 * state.parity_contexts + 65, 1, config);
+        encode_length(coder, state, num_literal_contexts + 65, 1, config);
+    }
+}
+
+fn literal_base(state: &CoderState, data: &[u8], config: &Config) -> usize {
+    let prev_byte = match state.pos {
+        0 => 0,
+        pos => data[pos - 1],
+    };
+    config.literal_base(state.pos, prev_byte)
+}
+
+fn append_op(data: &mut Vec<u8>, op: Op) {
+    match op {
+        Op::Literal(byte) => data.push(byte),
+        Op::Match { offset, len } => {
+            for _ in 0..len {
+                data.push(data[data.len() - offset as usize]);
+            }
+        }
     }
 }
 
@@ -84,9 +253,10 @@ fn encode_bit(
     coder: &mut dyn EntropyCoder,
     state: &mut CoderState,
     context_index: usize,
+    class: ContextClass,
     bit: bool,
 ) {
-    coder.encode_with_context(bit, &mut state.contexts.context_mut(context_index));
+    coder.encode_with_context(bit, &mut state.contexts.context_mut(context_index, class));
 }
 
 fn encode_length(
@@ -100,37 +270,70 @@ fn encode_length(
 
     let mut context_index = context_start;
     while value >= 2 {
-        encode_bit(coder, state, context_index, config.continue_value_bit);
-        encode_bit(coder, state, context_index + 1, value & 1 != 0);
+        encode_bit(
+            coder,
+            state,
+            context_index,
+            Length,
+            config.continue_value_bit,
+        );
+        encode_bit(coder, state, context_index + 1, Length, value & 1 != 0);
         context_index += 2;
         value >>= 1;
     }
-    encode_bit(coder, state, context_index, !config.continue_value_bit);
+    encode_bit(
+        coder,
+        state,
+        context_index,
+        Length,
+        !config.continue_value_bit,
+    );
 }
 
 #[derive(Clone)]
 pub struct CoderState {
-    contexts: ContextState,
+    contexts: ContextState<Vec<u16>>,
     last_offset: u32,
     prev_was_match: bool,
     pos: usize,
-    parity_contexts: usize,
 }
 
 impl CoderState {
     pub fn new(config: &Config) -> CoderState {
         CoderState {
-            contexts: ContextState::new((1 + 255) * config.parity_contexts + 1 + 64 + 64, config),
+            contexts: ContextState::new(
+                vec![0; num_contexts(config.parity_contexts, config.literal_context_bits)],
+                config,
+            ),
             last_offset: 0,
             prev_was_match: false,
             pos: 0,
-            parity_contexts: config.parity_contexts,
         }
     }
 
+    // the approximate number of bytes a state takes, mostly its contexts
+    pub fn memory_size(config: &Config) -> usize {
+        num_contexts(config.parity_contexts, config.literal_context_bits) * size_of::<u16>()
+            + size_of::<CoderState>()
+    }
+
     pub fn last_offset(&self) -> u32 {
         self.last_offset
     }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// Returns a fresh state at `pos`, keeping the learned context probabilities.
+    pub fn restart(&self, pos: usize) -> CoderState {
+        CoderState {
+            contexts: self.contexts.clone(),
+            last_offset: 0,
+            prev_was_match: false,
+            pos,
+        }
+    }
 }
 
 /// The error type for the uncompressing related functions
@@ -144,6 +347,15 @@ pub enum UnpackError {
         /// the current position in the uncompressed stream
         position: usize,
     },
+    /// a match offset beyond the window kept by `UnpackReader` was encountered, the data
+    /// was packed with a larger `Config::max_offset` than the reader was given
+    #[error("match offset beyond the unpack window: {offset} > {max_offset}")]
+    OffsetBeyondWindow {
+        /// the match offset
+        offset: usize,
+        /// the `Config::max_offset` passed to the reader
+        max_offset: usize,
+    },
     /// The passed size limit was exceeded
     #[error("Unpacked data over size limit: {size} > {limit}")]
     OverSize {
@@ -162,6 +374,61 @@ pub enum UnpackError {
     /// An offset or length value was found that exceeded 32bit
     #[error("Overflow while reading value")]
     ValueOverflow,
+    /// The config failed `Config::validate`
+    #[error("Invalid config: {source}")]
+    InvalidConfig {
+        #[from]
+        /// the reason the config is invalid
+        source: upkr_decoder::ConfigError,
+    },
+    /// The space after the uncompressed data is smaller than the margin needed for
+    /// in-place uncompression
+    #[error("Insufficient margin for in-place unpacking: {available} < {margin}")]
+    InsufficientMargin {
+        /// the margin needed between the end of the compressed and uncompressed data
+        margin: isize,
+        /// the space left in the buffer after the uncompressed data
+        available: isize,
+    },
+    /// The data doesn't start with a valid container header
+    #[error("Invalid container header")]
+    InvalidContainer,
+    /// The size of the uncompressed data doesn't match the size recorded in the container
+    #[error("Unpacked size mismatch: {actual} != {expected}")]
+    SizeMismatch {
+        /// the size recorded in the container header
+        expected: usize,
+        /// the actual size of the uncompressed data
+        actual: usize,
+    },
+    /// The checksum of the uncompressed data doesn't match the one recorded in the container
+    #[error("Checksum mismatch: {actual:08x} != {expected:08x}")]
+    ChecksumMismatch {
+        /// the checksum recorded in the container header
+        expected: u32,
+        /// the actual checksum of the uncompressed data
+        actual: u32,
+    },
+}
+
+impl From<upkr_decoder::UnpackError> for UnpackError {
+    fn from(err: upkr_decoder::UnpackError) -> UnpackError {
+        match err {
+            upkr_decoder::UnpackError::OffsetOutOfRange { offset, position } => {
+                UnpackError::OffsetOutOfRange { offset, position }
+            }
+            upkr_decoder::UnpackError::OverSize { size, limit } => {
+                UnpackError::OverSize { size, limit }
+            }
+            upkr_decoder::UnpackError::UnexpectedEOF => UnpackError::UnexpectedEOF {
+                source: crate::rans::UnexpectedEOF,
+            },
+            upkr_decoder::UnpackError::ValueOverflow => UnpackError::ValueOverflow,
+            upkr_decoder::UnpackError::InvalidConfig(source) => {
+                UnpackError::InvalidConfig { source }
+            }
+        }
+    }
 }
 
 /// Uncompress a piece of compressed data
@@ -180,16 +447,168 @@ pub fn unpack(
     max_size: usize,
 ) -> Result<Vec<u8>, UnpackError> {
     let mut result = vec![];
-    let _ = unpack_internal(Some(&mut result), None, packed_data, config, max_size)?;
+    let _ = unpack_internal(Some(&mut result), None, packed_data, &[], config, max_size)?;
+    Ok(result)
+}
+
+/// Uncompress a piece of data compressed with a preset dictionary
+///
+/// Returns either the uncompressed data (not including the dictionary), or an `UnpackError`
+///
+/// # Parameters
+///
+/// - `packed_data`: the compressed data
+/// - `dictionary`: the exact dictionary passed to `pack_with_dictionary`
+/// - `config`: the exact compression format config used to compress the data
+/// - `max_size`: the maximum size of uncompressed data to return. When this is exceeded,
+///   `UnpackError::OverSize` is returned
+pub fn unpack_with_dictionary(
+    packed_data: &[u8],
+    dictionary: &[u8],
+    config: &Config,
+    max_size: usize,
+) -> Result<Vec<u8>, UnpackError> {
+    let mut result = dictionary.to_vec();
+    let _ = unpack_internal(
+        Some(&mut result),
+        None,
+        packed_data,
+        dictionary,
+        config,
+        max_size,
+    )?;
+    result.drain(..dictionary.len());
     Ok(result)
 }
 
+/// Uncompress a stream of several segments compressed with `pack_multi`
+///
+/// Returns either the uncompressed segments, or an `UnpackError`
+///
+/// # Parameters
+///
+/// - `packed_data`: the compressed data
+/// - `config`: the exact compression format config used to compress the data
+/// - `max_size`: the maximum total size of the uncompressed segments. When this is
+///   exceeded, `UnpackError::OverSize` is returned
+pub fn unpack_multi(
+    packed_data: &[u8],
+    config: &Config,
+    max_size: usize,
+) -> Result<Vec<Vec<u8>>, UnpackError> {
+    config.validate()?;
+    let mut decoder = RansDecoder::new(packed_data, config)?;
+    let mut op_decoder = new_op_decoder(config);
+    let mut data = vec![];
+    let mut segments = vec![];
+    loop {
+        let start = data.len();
+        while let Some(op) =
+            op_decoder.next_op(&mut decoder, data.last().copied().unwrap_or(0), config)?
+        {
+            if op_decoder.position() > max_size {
+                return Err(UnpackError::OverSize {
+                    size: op_decoder.position(),
+                    limit: max_size,
+                });
+            }
+            append_op(&mut data, op);
+        }
+        // an empty segment ends the stream
+        if data.len() == start {
+            return Ok(segments);
+        }
+        segments.push(data[start..].to_vec());
+        op_decoder.next_segment();
+    }
+}
+
+/// Uncompress a piece of compressed data into a caller-provided buffer
+///
+/// Returns either the size of the uncompressed data, or an `UnpackError`
+///
+/// # Parameters
+///
+/// - `packed_data`: the compressed data
+/// - `buffer`: the buffer to uncompress into. When the uncompressed data doesn't fit,
+///   the buffer is filled and `UnpackError::OverSize` is returned with the full size
+/// - `config`: the exact compression format config used to compress the data
+pub fn unpack_into(
+    packed_data: &[u8],
+    buffer: &mut [u8],
+    config: &Config,
+) -> Result<usize, UnpackError> {
+    config.validate()?;
+    let decoder = RansDecoder::new(packed_data, config)?;
+    let buffer = Cell::from_mut(buffer).as_slice_of_cells();
+    Ok(unpack_to_cells(
+        decoder,
+        new_op_decoder(config),
+        buffer,
+        config,
+    )?)
+}
+
+/// Uncompress a piece of compressed data in place
+///
+/// The compressed data is expected at the end of `buffer` and is overwritten by the
+/// uncompressed data, which starts at the beginning of `buffer`. This mirrors the
+/// overlapped unpacking done by the optimized unpackers.
+///
+/// Before anything is written, the margin as returned by `calculate_margin` is checked
+/// against the space left in the buffer after the uncompressed data. If it is
+/// insufficient, `UnpackError::InsufficientMargin` is returned and `buffer` is left
+/// untouched.
+///
+/// Returns either the size of the uncompressed data, or an `UnpackError`
+///
+/// # Parameters
+///
+/// - `buffer`: the buffer holding the compressed data in its last `packed_size` bytes
+/// - `packed_size`: the size of the compressed data, panics if larger than `buffer`
+/// - `config`: the exact compression format config used to compress the data
+///
+/// # Example
+/// ```rust
+/// let config = upkr::Config::default();
+/// let data = b"Hello, World! Yellow world!";
+/// let packed_data = upkr::pack(data, 0, &config, None);
+/// let margin = upkr::calculate_margin(&packed_data, &config).unwrap();
+/// let mut buffer = vec![0; data.len() + margin.max(0) as usize];
+/// let packed_start = buffer.len() - packed_data.len();
+/// buffer[packed_start..].copy_from_slice(&packed_data);
+/// let size = upkr::unpack_in_place(&mut buffer, packed_data.len(), &config).unwrap();
+/// assert_eq!(&buffer[..size], data);
+/// ```
+pub fn unpack_in_place(
+    buffer: &mut [u8],
+    packed_size: usize,
+    config: &Config,
+) -> Result<usize, UnpackError> {
+    let packed_start = buffer.len() - packed_size;
+    let (margin, size) =
+        unpack_internal(None, None, &buffer[packed_start..], &[], config, usize::MAX)?;
+    let available = buffer.len() as isize - size as isize;
+    if available < margin.max(0) {
+        return Err(UnpackError::InsufficientMargin { margin, available });
+    }
+
+    let buffer = Cell::from_mut(buffer).as_slice_of_cells();
+    let decoder = RansDecoder::new(&buffer[packed_start..], config)?;
+    Ok(unpack_to_cells(
+        decoder,
+        new_op_decoder(config),
+        buffer,
+        config,
+    )?)
+}
+
 /// Calculates the minimum margin when overlapping buffers.
 ///
 /// Returns the minimum margin needed between the end of the compressed data and the
 /// end of the uncompressed data when overlapping the two buffers to save on RAM.
 pub fn calculate_margin(packed_data: &[u8], config: &Config) -> Result<isize, UnpackError> {
-    unpack_internal(None, None, packed_data, config, usize::MAX)
+    Ok(unpack_internal(None, None, packed_data, &[], config, usize::MAX)?.0)
 }
 
 /// Calculates a `Heatmap` from compressed data.
@@ -206,115 +625,184 @@ pub fn create_heatmap(
     max_size: usize,
 ) -> Result<Heatmap, UnpackError> {
     let mut heatmap = Heatmap::new();
-    let _ = unpack_internal(None, Some(&mut heatmap), packed_data, config, max_size)?;
+    let _ = unpack_internal(None, Some(&mut heatmap), packed_data, &[], config, max_size)?;
     Ok(heatmap)
 }
 
+/// A decoded op with its position in the uncompressed and compressed data, see `trace`.
+#[derive(Debug, Clone)]
+pub struct TraceEntry {
+    /// The decoded op
+    pub op: Op,
+    /// The position of the op in the uncompressed data
+    pub position: usize,
+    /// The number of bytes of compressed data read before decoding the op
+    pub packed_position: usize,
+    /// The rANS state before decoding the op
+    pub state: u32,
+    /// Whether the op is a match reusing the offset of the previous match
+    pub repeated_offset: bool,
+    /// The cost of the op in (fractional) bits
+    pub cost: f32,
+}
+
+/// An iterator over the decoded ops of compressed data, returned by `trace`.
+pub struct Trace<'a> {
+    decoder: RansDecoder<&'a [u8]>,
+    op_decoder: OpDecoder,
+    config: Config,
+    data: Vec<u8>,
+    last_offset: u32,
+    prev_was_match: bool,
+    done: bool,
+}
+
+/// Decodes compressed data into an annotated trace of its ops.
+///
+/// The iterator ends after the EOF marker or the first error. This is meant as a
+/// reference to compare custom unpackers against.
+///
+/// # Parameters
+///
+/// - `packed_data`: the compressed data
+/// - `config`: the exact compression format config used to compress the data
+///
+/// # Example
+/// ```rust
+/// let config = upkr::Config::default();
+/// let packed_data = upkr::pack(b"abababab", 0, &config, None);
+/// let trace: Vec<_> = upkr::trace(&packed_data, &config)
+///     .unwrap()
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+/// assert_eq!(trace.len(), 3);
+/// assert_eq!(trace[2].position, 2);
+/// assert!(matches!(trace[2].op, upkr::Op::Match { offset: 2, len: 6 }));
+/// ```
+pub fn trace<'a>(packed_data: &'a [u8], config: &Config) -> Result<Trace<'a>, UnpackError> {
+    config.validate()?;
+    Ok(Trace {
+        decoder: RansDecoder::new(packed_data, config)?,
+        op_decoder: new_op_decoder(config),
+        config: config.clone(),
+        data: vec![],
+        last_offset: 0,
+        prev_was_match: false,
+        done: false,
+    })
+}
+
+impl Iterator for Trace<'_> {
+    type Item = Result<TraceEntry, UnpackError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let prev_decoder = self.decoder.clone();
+        let position = self.op_decoder.position();
+        let prev_byte = self.data.last().copied().unwrap_or(0);
+        let op = match self
+            .op_decoder
+            .next_op(&mut self.decoder, prev_byte, &self.config)
+        {
+            Ok(Some(op)) => op,
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(err) => {
+                self.done = true;
+                return Some(Err(err.into()));
+            }
+        };
+        let mut repeated_offset = false;
+        if let Op::Match { offset, .. } = op {
+            repeated_offset = offset == self.last_offset
+                && !self.prev_was_match
+                && !self.config.no_repeated_offsets;
+            self.last_offset = offset;
+        }
+        self.prev_was_match = matches!(op, Op::Match { .. });
+        if self.config.literal_context_bits != 0 {
+            append_op(&mut self.data, op);
+        }
+        Some(Ok(TraceEntry {
+            op,
+            position,
+            packed_position: prev_decoder.pos(),
+            state: prev_decoder.state(),
+            repeated_offset,
+            cost: decoding_cost(&self.decoder, &prev_decoder),
+        }))
+    }
+}
+
+// When a `dictionary` is given, `result` is expected to already contain it and the
+// returned margin and size don't include it.
 fn unpack_internal(
-    mut result: Option<&mut Vec<u8>>,
+    result: Option<&mut Vec<u8>>,
     mut heatmap: Option<&mut Heatmap>,
     packed_data: &[u8],
+    dictionary: &[u8],
     config: &Config,
     max_size: usize,
-) -> Result<isize, UnpackError> {
+) -> Result<(isize, usize), UnpackError> {
+    config.validate()?;
     let mut decoder = RansDecoder::new(packed_data, config)?;
-    let mut contexts = ContextState::new((1 + 255) * config.parity_contexts + 1 + 64 + 64, config);
-    let mut offset = usize::MAX;
-    let mut position = 0usize;
-    let mut prev_was_match = false;
+    let mut op_decoder = new_op_decoder(config).with_position(dictionary.len());
+    let max_result_size = max_size.saturating_add(dictionary.len());
     let mut margin = 0isize;
 
-    fn decode_length(
-        decoder: &mut RansDecoder,
-        contexts: &mut ContextState,
-        mut context_index: usize,
-        config: &Config,
-    ) -> Result<usize, UnpackError> {
-        let mut length = 0;
-        let mut bit_pos = 0;
-        while decoder.decode_with_context(&mut contexts.context_mut(context_index))?
-            == config.continue_value_bit
-        {
-            length |= (decoder.decode_with_context(&mut contexts.context_mut(context_index + 1))?
-                as usize)
-                << bit_pos;
-            bit_pos += 1;
-            if bit_pos >= 32 {
-                return Err(UnpackError::ValueOverflow);
-            }
-            context_index += 2;
-        }
-        Ok(length | (1 << bit_pos))
-    }
+    // the previous byte selects the literal contexts, so keep the uncompressed data
+    // even when the caller doesn't need it
+    let mut own_result = dictionary.to_vec();
+    let mut result = result.or((config.literal_context_bits != 0).then_some(&mut own_result));
 
     loop {
         let prev_decoder = decoder.clone();
+        let position = op_decoder.position() - dictionary.len();
         margin = margin.max(position as isize - decoder.pos() as isize);
-        let literal_base = position % config.parity_contexts * 256;
-        if decoder.decode_with_context(&mut contexts.context_mut(literal_base))?
-            == config.is_match_bit
+        let mut prev_byte = 0;
+        if config.literal_context_bits != 0
+            && let Some(ref result) = result
         {
-            if config.no_repeated_offsets
-                || prev_was_match
-                || decoder
-                    .decode_with_context(&mut contexts.context_mut(256 * config.parity_contexts))?
-                    == config.new_offset_bit
-            {
-                offset = decode_length(
-                    &mut decoder,
-                    &mut contexts,
-                    256 * config.parity_contexts + 1,
-                    config,
-                )? - if config.eof_in_length { 0 } else { 1 };
-                if offset == 0 {
-                    break;
-                }
+            if result.len() < op_decoder.position() {
+                // the data was truncated at `max_size`
+                return Err(UnpackError::OverSize {
+                    size: position,
+                    limit: max_size,
+                });
             }
-            let length = decode_length(
-                &mut decoder,
-                &mut contexts,
-                256 * config.parity_contexts + 65,
-                config,
-            )?;
-            if config.eof_in_length && length == 1 {
-                break;
-            }
-            if offset > position {
-                return Err(UnpackError::OffsetOutOfRange { offset, position });
-            }
-            if let Some(ref mut heatmap) = heatmap {
-                heatmap.add_match(offset, length, decoder.cost(&prev_decoder));
-            }
-            if let Some(ref mut result) = result {
-                for _ in 0..length {
-                    if result.len() < max_size {
-                        result.push(result[result.len() - offset]);
-                    } else {
-                        break;
+            prev_byte = result.last().copied().unwrap_or(0);
+        }
+        match op_decoder.next_op(&mut decoder, prev_byte, config)? {
+            Some(Op::Match { offset, len }) => {
+                let (offset, length) = (offset as usize, len as usize);
+                if let Some(ref mut heatmap) = heatmap {
+                    heatmap.add_match(offset, length, decoding_cost(&decoder, &prev_decoder));
+                }
+                if let Some(ref mut result) = result {
+                    for _ in 0..length {
+                        if result.len() < max_result_size {
+                            result.push(result[result.len() - offset]);
+                        } else {
+                            break;
+                        }
                     }
                 }
             }
-            position += length;
-            prev_was_match = true;
-        } else {
-            let mut context_index = 1;
-            let mut byte = 0;
-            for i in (0..8).rev() {
-                let bit = decoder
-                    .decode_with_context(&mut contexts.context_mut(literal_base + context_index))?;
-                context_index = (context_index << 1) | bit as usize;
-                byte |= (bit as u8) << i;
-            }
-            if let Some(ref mut heatmap) = heatmap {
-                heatmap.add_literal(byte, decoder.cost(&prev_decoder));
-            }
-            if let Some(ref mut result) = result {
-                if result.len() < max_size {
+            Some(Op::Literal(byte)) => {
+                if let Some(ref mut heatmap) = heatmap {
+                    heatmap.add_literal(byte, decoding_cost(&decoder, &prev_decoder));
+                }
+                if let Some(ref mut result) = result
+                    && result.len() < max_result_size
+                {
                     result.push(byte);
                 }
             }
-            position += 1;
-            prev_was_match = false;
+            None => break,
         }
     }
 
@@ -322,6 +810,7 @@ fn unpack_internal(
         heatmap.finish();
     }
 
+    let position = op_decoder.position() - dictionary.len();
     if position > max_size {
         return Err(UnpackError::OverSize {
             size: position,
@@ -329,5 +818,8 @@ fn unpack_internal(
         });
     }
 
-    Ok(margin + decoder.pos() as isize - position as isize)
+    Ok((
+        margin + decoder.pos() as isize - position as isize,
+        position,
+    ))
 }
@@ -1,9 +1,15 @@
 use anyhow::Result;
 use std::ffi::OsStr;
 use std::io::prelude::*;
+#[cfg(feature = "terminal")]
+use std::ops::ControlFlow;
 use std::process;
+use std::time::Duration;
 use std::{fs::File, path::PathBuf};
 
+// a parser option from the command line, applied to the preset of the level
+type ParserOverride = Box<dyn Fn(&mut upkr::ParserConfig)>;
+
 fn main() -> Result<()> {
     let mut config = upkr::Config::default();
     let mut reverse = false;
@@ -11,31 +17,91 @@ fn main() -> Result<()> {
     let mut calculate_margin = false;
     let mut create_heatmap = false;
     let mut report_raw_cost = false;
+    let mut heatmap_html = false;
+    let mut heatmap_diff = false;
+    let mut dump_ops = false;
+    let mut trace = false;
+    let mut symbol_file: Option<PathBuf> = None;
+    let mut load_address = 0;
+    let mut container = false;
+    let mut multi = false;
+    let mut auto = false;
+    let mut search = upkr::ConfigSearch::default();
+    let mut explicit_search = None;
+    let mut emit_unpacker = None;
     #[allow(unused_mut)]
     let mut do_hexdump = false;
     let mut level = 2;
+    let mut threads = 1;
+    let mut window_size = usize::MAX;
+    let mut time_budget = None;
+    let mut memory_limit = None;
+    // applied once all arguments are read, as the level can come after them
+    let mut parser_overrides: Vec<ParserOverride> = vec![];
     let mut infile: Option<PathBuf> = None;
     let mut outfile: Option<PathBuf> = None;
+    let mut more_files: Vec<PathBuf> = vec![];
     let mut max_unpacked_size = 512 * 1024 * 1024;
 
     let mut parser = lexopt::Parser::from_env();
     while let Some(arg) = parser.next()? {
         use lexopt::prelude::*;
         match arg {
-            Short('b') | Long("bitstream") => config.use_bitstream = true,
-            Short('p') | Long("parity") => config.parity_contexts = parser.value()?.parse()?,
+            Short('b') | Long("bitstream") => {
+                config.use_bitstream = true;
+                search.use_bitstream = false;
+            }
+            Short('p') | Long("parity") => {
+                config.parity_contexts = parser.value()?.parse()?;
+                search.parity_contexts = false;
+            }
+            Long("literal-context-bits") => {
+                config.literal_context_bits = parser.value()?.parse()?;
+            }
+            Long("prob-bits") => config.prob_bits = parser.value()?.parse()?,
+            Long("update-rate") => {
+                let rate = parser.value()?.parse()?;
+                config.literal_update_rate = rate;
+                config.flag_update_rate = rate;
+                config.length_update_rate = rate;
+                search.update_rates = false;
+            }
+            Long("literal-update-rate") => {
+                config.literal_update_rate = parser.value()?.parse()?;
+                search.update_rates = false;
+            }
+            Long("flag-update-rate") => {
+                config.flag_update_rate = parser.value()?.parse()?;
+                search.update_rates = false;
+            }
+            Long("length-update-rate") => {
+                config.length_update_rate = parser.value()?.parse()?;
+                search.update_rates = false;
+            }
             Short('r') | Long("reverse") => reverse = true,
+            Short('c') | Long("container") => container = true,
+            Long("multi") => multi = true,
             Long("invert-is-match-bit") => config.is_match_bit = false,
             Long("invert-new-offset-bit") => config.new_offset_bit = false,
             Long("invert-continue-value-bit") => config.continue_value_bit = false,
             Long("invert-bit-encoding") => config.invert_bit_encoding = true,
-            Long("simplified-prob-update") => config.simplified_prob_update = true,
+            Long("simplified-prob-update") => {
+                config.simplified_prob_update = true;
+                search.simplified_prob_update = false;
+            }
             Long("big-endian-bitstream") => {
                 config.use_bitstream = true;
                 config.bitstream_is_big_endian = true;
+                search.use_bitstream = false;
+            }
+            Long("no-repeated-offsets") => {
+                config.no_repeated_offsets = true;
+                search.no_repeated_offsets = false;
+            }
+            Long("eof-in-length") => {
+                config.eof_in_length = true;
+                search.eof_in_length = false;
             }
-            Long("no-repeated-offsets") => config.no_repeated_offsets = true,
-            Long("eof-in-length") => config.eof_in_length = true,
 
             Long("max-offset") => config.max_offset = parser.value()?.parse()?,
             Long("max-length") => config.max_length = parser.value()?.parse()?,
@@ -45,6 +111,7 @@ fn main() -> Result<()> {
                 config.bitstream_is_big_endian = true;
                 config.invert_bit_encoding = true;
                 config.simplified_prob_update = true;
+                search = fixed_config_search();
                 level = 9;
             }
             Long("x86") => {
@@ -52,22 +119,114 @@ fn main() -> Result<()> {
                 config.continue_value_bit = false;
                 config.is_match_bit = false;
                 config.new_offset_bit = false;
+                search = fixed_config_search();
             }
             Long("x86b") => {
                 config.use_bitstream = true;
                 config.continue_value_bit = false;
                 config.no_repeated_offsets = true;
+                search = fixed_config_search();
                 level = 9;
             }
+            Long("auto") => {
+                auto = true;
+                if let Some(list) = parser.optional_value() {
+                    explicit_search = Some(parse_config_search(&list.string()?)?);
+                }
+            }
+
+            Long("emit-unpacker") => {
+                let name = parser.value()?.string()?;
+                let target = upkr::UnpackerTarget::from_name(&name)
+                    .ok_or_else(|| anyhow::anyhow!("unknown unpacker target '{}'", name))?;
+                emit_unpacker = Some(target);
+            }
 
             Short('u') | Long("unpack") | Short('d') | Long("decompress") => unpack = true,
             Long("margin") => calculate_margin = true,
             Long("heatmap") => create_heatmap = true,
             Long("raw-cost") => report_raw_cost = true,
+            Long("symbols") => {
+                create_heatmap = true;
+                symbol_file = Some(parser.value()?.into());
+            }
+            Long("load-address") => {
+                let value = parser.value()?.string()?;
+                load_address = parse_number(&value)
+                    .ok_or_else(|| anyhow::anyhow!("invalid load address '{}'", value))?;
+            }
+            Long("heatmap-diff") => heatmap_diff = true,
+            Long("dump-ops") => dump_ops = true,
+            Long("trace") => trace = true,
+            Long("heatmap-html") => {
+                create_heatmap = true;
+                heatmap_html = true;
+            }
             #[cfg(feature = "crossterm")]
             Long("hexdump") => do_hexdump = true,
             Short('l') | Long("level") => level = parser.value()?.parse()?,
             Short(n) if n.is_ascii_digit() => level = n as u8 - b'0',
+            Short('j') | Long("threads") => threads = parser.value()?.parse()?,
+            Short('w') | Long("window") => {
+                window_size = parser.value()?.parse()?;
+                if window_size == 0 {
+                    anyhow::bail!("window size needs to be at least 1");
+                }
+            }
+            Long("time-budget") => {
+                let seconds: f64 = parser.value()?.parse()?;
+                time_budget = Some(Duration::try_from_secs_f64(seconds)?);
+            }
+            Long("memory-limit") => {
+                let megabytes: usize = parser.value()?.parse()?;
+                memory_limit = Some(megabytes * 1024 * 1024);
+            }
+            Long("arrivals") => {
+                let arrivals = parse_count(&parser.value()?.string()?)?;
+                parser_overrides.push(Box::new(move |c| c.max_arrivals = arrivals));
+            }
+            Long("cost-delta") => {
+                let delta = parser.value()?.parse()?;
+                parser_overrides.push(Box::new(move |c| c.max_cost_delta = delta));
+            }
+            Long("offset-cost-delta") => {
+                let delta = parser.value()?.parse()?;
+                parser_overrides.push(Box::new(move |c| c.max_offset_cost_delta = delta));
+            }
+            Long("near-matches") => {
+                let near_matches = parse_count(&parser.value()?.string()?)?;
+                parser_overrides.push(Box::new(move |c| c.num_near_matches = near_matches));
+            }
+            Long("greedy-size") => {
+                let greedy_size = parse_count(&parser.value()?.string()?)?;
+                parser_overrides.push(Box::new(move |c| c.greedy_size = greedy_size));
+            }
+            Long("queue-size") => {
+                let queue_size = parse_count(&parser.value()?.string()?)?;
+                parser_overrides.push(Box::new(move |c| c.max_queue_size = queue_size));
+            }
+            Long("patience") => {
+                let patience = parse_count(&parser.value()?.string()?)?;
+                parser_overrides.push(Box::new(move |c| c.patience = patience));
+            }
+            Long("chain") => {
+                let chain = parse_count(&parser.value()?.string()?)?;
+                parser_overrides.push(Box::new(move |c| c.max_chain = chain));
+            }
+            Long("matches-per-length") => {
+                let matches = parse_count(&parser.value()?.string()?)?;
+                parser_overrides.push(Box::new(move |c| c.max_matches_per_length = matches));
+            }
+            Long("length-diff") => {
+                let diff = parse_count(&parser.value()?.string()?)?;
+                parser_overrides.push(Box::new(move |c| c.max_length_diff = diff));
+            }
+            Long("passes") => {
+                let passes = parser.value()?.parse()?;
+                parser_overrides.push(Box::new(move |c| c.passes = passes));
+            }
+            Long("hash-chain") => parser_overrides.push(Box::new(|c| c.hash_chain = Some(true))),
+            Long("suffix-array") => parser_overrides.push(Box::new(|c| c.hash_chain = Some(false))),
             Short('h') | Long("help") => print_help(0),
             Long("version") => {
                 println!("{}", env!("CARGO_PKG_VERSION"));
@@ -76,47 +235,194 @@ fn main() -> Result<()> {
             Long("max-unpacked-size") => max_unpacked_size = parser.value()?.parse()?,
             Value(val) if infile.is_none() => infile = Some(val.into()),
             Value(val) if outfile.is_none() => outfile = Some(val.into()),
+            Value(val) if multi => more_files.push(val.into()),
             _ => return Err(arg.unexpected().into()),
         }
     }
 
-    let infile = IoTarget::from_filename(infile);
-    let outfile = |tpe: OutFileType| infile.output(tpe, &outfile);
+    if level > 12 {
+        eprintln!("--level has to be between 0 and 12");
+        process::exit(1);
+    }
 
     if config.parity_contexts != 1 && config.parity_contexts != 2 && config.parity_contexts != 4 {
         eprintln!("--parity has to be 1, 2, or 4");
         process::exit(1);
     }
 
+    if let Err(err) = config.validate() {
+        eprintln!("invalid config: {}", err);
+        process::exit(1);
+    }
+
+    if let Some(target) = emit_unpacker {
+        let source = upkr::unpacker_source(target, &config)?;
+        IoTarget::from_filename(outfile.or(infile)).write(source.as_bytes())?;
+        return Ok(());
+    }
+
+    if heatmap_diff {
+        let read_heatmap = |filename| -> Result<upkr::Heatmap> {
+            let (data, config, _) = read_packed(
+                &IoTarget::from_filename(filename),
+                &config,
+                container,
+                reverse,
+            )?;
+            let mut heatmap = upkr::create_heatmap(&data, &config, max_unpacked_size)?;
+            if reverse {
+                heatmap.reverse();
+            }
+            Ok(heatmap)
+        };
+        if infile.is_none() || outfile.is_none() {
+            print_help(1);
+        }
+        let old = read_heatmap(infile)?;
+        let new = read_heatmap(outfile)?;
+        print_heatmap_diff(&upkr::diff_heatmaps(&old, &new));
+        return Ok(());
+    }
+
+    if multi {
+        if container || reverse {
+            anyhow::bail!("--multi can't be combined with --container or --reverse");
+        }
+        let files: Vec<PathBuf> = infile
+            .into_iter()
+            .chain(outfile)
+            .chain(more_files)
+            .collect();
+        if unpack {
+            let Some((packed_file, outfiles)) = files.split_first() else {
+                print_help(1);
+            };
+            let packed_data = IoTarget::from_filename(Some(packed_file.clone())).read()?;
+            let segments = upkr::unpack_multi(&packed_data, &config, max_unpacked_size)?;
+            if segments.len() != outfiles.len() {
+                anyhow::bail!(
+                    "packed data has {} segments, but {} output files were given",
+                    segments.len(),
+                    outfiles.len()
+                );
+            }
+            for (segment, outfile) in segments.iter().zip(outfiles) {
+                IoTarget::from_filename(Some(outfile.clone())).write(segment)?;
+            }
+        } else {
+            let Some((packed_file, infiles)) = files.split_last() else {
+                print_help(1);
+            };
+            if infiles.is_empty() {
+                print_help(1);
+            }
+            let mut segments = vec![];
+            for infile in infiles {
+                let data = IoTarget::from_filename(Some(infile.clone())).read()?;
+                if data.is_empty() {
+                    anyhow::bail!("{} is empty, segments can't be empty", infile.display());
+                }
+                segments.push(data);
+            }
+            let segments: Vec<&[u8]> = segments.iter().map(Vec::as_slice).collect();
+            let options = upkr::PackOptions {
+                level,
+                parser_config: parser_config(level, &parser_overrides),
+                threads,
+                window_size,
+                time_budget,
+                memory_limit,
+            };
+            let packed_data = upkr::pack_multi(&segments, &config, &options, None);
+            let size: usize = segments.iter().map(|segment| segment.len()).sum();
+            eprintln!(
+                "Compressed {} bytes in {} segments to {} bytes ({}%)",
+                size,
+                segments.len(),
+                packed_data.len(),
+                packed_data.len() as f32 * 100. / size as f32
+            );
+            IoTarget::from_filename(Some(packed_file.clone())).write(&packed_data)?;
+        }
+        return Ok(());
+    }
+
+    let infile = IoTarget::from_filename(infile);
+    let outfile = |tpe: OutFileType| infile.output(tpe, &outfile);
+
+    if trace {
+        let (data, config, _) = read_packed(&infile, &config, container, reverse)?;
+        print_trace(upkr::trace(&data, &config)?)?;
+        return Ok(());
+    }
+
+    if dump_ops {
+        let mut data = infile.read()?;
+        if reverse {
+            data.reverse();
+        }
+        let options = upkr::PackOptions {
+            level,
+            parser_config: parser_config(level, &parser_overrides),
+            threads,
+            window_size,
+            time_budget,
+            memory_limit,
+        };
+        let ops = upkr::parse_with_options(&data, &config, &options);
+        print_ops(&ops, &upkr::op_costs(&ops, &config), &data, &config);
+        return Ok(());
+    }
+
     if !unpack && !calculate_margin && !create_heatmap {
         let mut data = infile.read()?;
         if reverse {
             data.reverse();
         }
 
-        #[cfg(feature = "terminal")]
-        let mut packed_data = {
-            let mut pb = pbr::ProgressBar::on(std::io::stderr(), data.len() as u64);
-            pb.set_units(pbr::Units::Bytes);
-            let packed_data = upkr::pack(
-                &data,
+        let mut packed_data = if auto {
+            let search = explicit_search.unwrap_or(search);
+            let results = upkr::search_configs(&data, level, &config, &search);
+            eprintln!("   size  config options");
+            for (config, packed_data) in &results {
+                let options = config_options(config);
+                let options = if options.is_empty() {
+                    "(default)"
+                } else {
+                    &options
+                };
+                eprintln!("{:>7}  {}", packed_data.len(), options);
+            }
+            let (best_config, best_packed_data) = results.into_iter().next().ok_or_else(|| {
+                anyhow::anyhow!("--auto found no config to try, the update rates have to be less than --prob-bits")
+            })?;
+            config = best_config;
+            best_packed_data
+        } else {
+            let options = upkr::PackOptions {
                 level,
-                &config,
-                Some(&mut |pos| {
-                    pb.set(pos as u64);
-                }),
-            );
-            pb.finish();
-            eprintln!();
-            packed_data
+                parser_config: parser_config(level, &parser_overrides),
+                threads,
+                window_size,
+                time_budget,
+                memory_limit,
+            };
+            pack(&data, &config, &options)
         };
-        #[cfg(not(feature = "terminal"))]
-        let mut packed_data = upkr::pack(&data, level, &config, None);
+
+        let header = container.then(|| upkr::ContainerHeader::new(&data, &packed_data, &config));
 
         if reverse {
             packed_data.reverse();
         }
 
+        if let Some(header) = header {
+            let mut container_data = Vec::with_capacity(packed_data.len() + 32);
+            header.write(&mut container_data);
+            container_data.extend_from_slice(&packed_data);
+            packed_data = container_data;
+        }
+
         eprintln!(
             "Compressed {} bytes to {} bytes ({}%)",
             data.len(),
@@ -125,12 +431,12 @@ fn main() -> Result<()> {
         );
         outfile(OutFileType::Packed).write(&packed_data)?;
     } else {
-        let mut data = infile.read()?;
-        if reverse {
-            data.reverse();
-        }
+        let (data, config, header) = read_packed(&infile, &config, container, reverse)?;
         if unpack {
             let mut unpacked_data = upkr::unpack(&data, &config, max_unpacked_size)?;
+            if let Some(ref header) = header {
+                header.verify(&unpacked_data)?;
+            }
             if reverse {
                 unpacked_data.reverse();
             }
@@ -141,27 +447,37 @@ fn main() -> Result<()> {
             if reverse {
                 heatmap.reverse();
             }
-            match do_hexdump {
-                #[cfg(feature = "crossterm")]
-                true => {
-                    if report_raw_cost {
-                        heatmap.print_as_hex_raw_cost()?
-                    } else {
-                        heatmap.print_as_hex()?
-                    }
-                }
-                _ => {
-                    let mut heatmap_bin = Vec::with_capacity(heatmap.len());
-                    for i in 0..heatmap.len() {
-                        let cost = if report_raw_cost {
-                            heatmap.raw_cost(i)
+            if let Some(ref symbol_file) = symbol_file {
+                let symbols = upkr::parse_symbols(&std::fs::read(symbol_file)?)?;
+                print_symbol_costs(&heatmap.symbol_costs(&symbols, load_address));
+            }
+            if heatmap_html {
+                let mut html = vec![];
+                heatmap.write_html(&mut html)?;
+                outfile(OutFileType::HeatmapHtml).write(&html)?;
+            } else if symbol_file.is_none() || do_hexdump {
+                match do_hexdump {
+                    #[cfg(feature = "crossterm")]
+                    true => {
+                        if report_raw_cost {
+                            heatmap.print_as_hex_raw_cost()?
                         } else {
-                            heatmap.cost(i)
-                        };
-                        let cost = (cost.log2() * 8. + 64.).round().clamp(0., 127.) as u8;
-                        heatmap_bin.push((cost << 1) | heatmap.is_literal(i) as u8);
+                            heatmap.print_as_hex()?
+                        }
+                    }
+                    _ => {
+                        let mut heatmap_bin = Vec::with_capacity(heatmap.len());
+                        for i in 0..heatmap.len() {
+                            let cost = if report_raw_cost {
+                                heatmap.raw_cost(i)
+                            } else {
+                                heatmap.cost(i)
+                            };
+                            let cost = (cost.log2() * 8. + 64.).round().clamp(0., 127.) as u8;
+                            heatmap_bin.push((cost << 1) | heatmap.is_literal(i) as u8);
+                        }
+                        outfile(OutFileType::Heatmap).write(&heatmap_bin)?;
                     }
-                    outfile(OutFileType::Heatmap).write(&heatmap_bin)?;
                 }
             }
         }
@@ -173,10 +489,328 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+fn pack(data: &[u8], config: &upkr::Config, options: &upkr::PackOptions) -> Vec<u8> {
+    #[cfg(feature = "terminal")]
+    let packed_data = {
+        let mut pb = pbr::ProgressBar::on(std::io::stderr(), data.len() as u64);
+        pb.set_units(pbr::Units::Bytes);
+        let packed_data = upkr::pack_with_options(
+            data,
+            config,
+            options,
+            Some(&mut |pos| {
+                pb.set(pos as u64);
+                ControlFlow::Continue(())
+            }),
+        );
+        pb.finish();
+        eprintln!();
+        packed_data
+    };
+    #[cfg(not(feature = "terminal"))]
+    let packed_data = upkr::pack_with_options(data, config, options, None);
+    packed_data
+}
+
+// reads packed data, returning it together with the config from the container header if
+// the data starts with one. raw packed data is used with the given config, unless
+// `container` is set, in which case a missing header is an error.
+fn read_packed(
+    infile: &IoTarget,
+    config: &upkr::Config,
+    container: bool,
+    reverse: bool,
+) -> Result<(Vec<u8>, upkr::Config, Option<upkr::ContainerHeader>)> {
+    let mut data = infile.read()?;
+    let mut config = config.clone();
+    let mut header = None;
+    match upkr::read_container_header(&data) {
+        Ok((container_header, packed_data)) => {
+            config = container_header.config.clone();
+            header = Some(container_header);
+            data = packed_data.to_vec();
+        }
+        Err(err) if container => return Err(err.into()),
+        Err(_) => (),
+    }
+    if reverse {
+        data.reverse();
+    }
+    Ok((data, config, header))
+}
+
+fn op_text(op: upkr::Op, repeated_offset: bool) -> String {
+    match op {
+        upkr::Op::Literal(byte) if (32..127).contains(&byte) => {
+            format!("literal {:02x} '{}'", byte, byte as char)
+        }
+        upkr::Op::Literal(byte) => format!("literal {:02x}", byte),
+        upkr::Op::Match { offset, len } => format!(
+            "match {} {}{}",
+            offset,
+            len,
+            if repeated_offset {
+                " (repeated offset)"
+            } else {
+                ""
+            }
+        ),
+    }
+}
+
+fn print_ops(ops: &[upkr::Op], costs: &[f32], data: &[u8], config: &upkr::Config) {
+    println!("# {:>6} {:>8}  op", "pos", "bits");
+    let mut pos = 0;
+    let mut last_offset = 0;
+    let mut prev_was_match = false;
+    for (&op, cost) in ops.iter().zip(costs) {
+        let repeated_offset = match op {
+            upkr::Op::Literal(_) => false,
+            upkr::Op::Match { offset, .. } => {
+                offset == last_offset && !prev_was_match && !config.no_repeated_offsets
+            }
+        };
+        println!("{:>8} {:>8.2}  {}", pos, cost, op_text(op, repeated_offset));
+        match op {
+            upkr::Op::Literal(_) => {
+                pos += 1;
+                prev_was_match = false;
+            }
+            upkr::Op::Match { offset, len } => {
+                pos += len as usize;
+                last_offset = offset;
+                prev_was_match = true;
+            }
+        }
+    }
+    let total: f32 = costs.iter().sum();
+    let literals = ops
+        .iter()
+        .filter(|op| matches!(op, upkr::Op::Literal(_)))
+        .count();
+    println!(
+        "# {} bytes in {} literals and {} matches, {:.1} bits ({:.1} bytes)",
+        data.len(),
+        literals,
+        ops.len() - literals,
+        total,
+        total / 8.
+    );
+}
+
+fn print_trace(trace: upkr::Trace) -> Result<()> {
+    println!(
+        "# {:>6} {:>8} {:>8} {:>8}  op",
+        "pos", "packed", "state", "bits"
+    );
+    for entry in trace {
+        let entry = entry?;
+        println!(
+            "{:>8} {:>8} {:>8x} {:>8.2}  {}",
+            entry.position,
+            entry.packed_position,
+            entry.state,
+            entry.cost,
+            op_text(entry.op, entry.repeated_offset)
+        );
+    }
+    Ok(())
+}
+
+fn print_heatmap_diff(regions: &[upkr::DiffRegion]) {
+    let range = |range: &std::ops::Range<usize>| {
+        if range.is_empty() {
+            "-".to_string()
+        } else {
+            format!("{:06x}..{:06x}", range.start, range.end)
+        }
+    };
+    println!(
+        "{:>14}  {:>14}  {:>9} {:>9} {:>9}  change",
+        "old", "new", "old bits", "new bits", "delta"
+    );
+    for region in regions {
+        // unchanged regions are only listed when their cost changed noticeably
+        if region.kind == upkr::DiffKind::Unchanged && region.delta().abs() < 1. {
+            continue;
+        }
+        let change = match region.kind {
+            upkr::DiffKind::Unchanged => "unchanged",
+            upkr::DiffKind::Changed if region.old.is_empty() => "inserted",
+            upkr::DiffKind::Changed if region.new.is_empty() => "removed",
+            upkr::DiffKind::Changed => "replaced",
+            upkr::DiffKind::MatchToLiteral => "match -> literal",
+            upkr::DiffKind::LiteralToMatch => "literal -> match",
+        };
+        println!(
+            "{:>14}  {:>14}  {:>9.1} {:>9.1} {:>+9.1}  {}",
+            range(&region.old),
+            range(&region.new),
+            region.old_cost,
+            region.new_cost,
+            region.delta(),
+            change
+        );
+    }
+    let old_cost: f32 = regions.iter().map(|region| region.old_cost).sum();
+    let new_cost: f32 = regions.iter().map(|region| region.new_cost).sum();
+    println!(
+        "{:>14}  {:>14}  {:>9.1} {:>9.1} {:>+9.1}  ({:+.1} bytes)",
+        "total",
+        "",
+        old_cost,
+        new_cost,
+        new_cost - old_cost,
+        (new_cost - old_cost) / 8.
+    );
+}
+
+fn print_symbol_costs(costs: &upkr::SymbolCosts) {
+    let total: f32 = costs.symbols.iter().map(|cost| cost.cost).sum();
+    let print_table = |title: &str, costs: &[upkr::SymbolCost]| {
+        println!(
+            "{:>9} {:>7} {:>7} {:>6} {:>9}  {}",
+            "bits", "bytes", "packed", "share", "address", title
+        );
+        for cost in costs {
+            println!(
+                "{:>9.1} {:>7} {:>7.1} {:>5.1}% {:>9x}  {}",
+                cost.cost,
+                cost.bytes,
+                cost.cost / 8.,
+                cost.cost * 100. / total,
+                cost.address,
+                cost.name
+            );
+        }
+    };
+    if !costs.sections.is_empty() {
+        print_table("section", &costs.sections);
+        println!();
+    }
+    print_table("symbol", &costs.symbols);
+}
+
+fn parse_number(value: &str) -> Option<u64> {
+    if let Some(hex) = value.strip_prefix("0x").or_else(|| value.strip_prefix('$')) {
+        u64::from_str_radix(hex, 16).ok()
+    } else {
+        value.parse().ok()
+    }
+}
+
+// the preset of the level with the parser options applied, `None` without any
+fn parser_config(level: u8, overrides: &[ParserOverride]) -> Option<upkr::ParserConfig> {
+    (!overrides.is_empty()).then(|| {
+        let mut parser_config = upkr::ParserConfig::from_level(level);
+        for set in overrides {
+            set(&mut parser_config);
+        }
+        parser_config
+    })
+}
+
+// parses a count, `unlimited` meaning no limit
+fn parse_count(value: &str) -> Result<usize> {
+    if value == "unlimited" {
+        Ok(usize::MAX)
+    } else {
+        Ok(value.parse()?)
+    }
+}
+
+// a search which varies none of the config options. the presets use it, as their
+// unpackers hard-code all of them, so --auto can only try the explicitly listed options
+fn fixed_config_search() -> upkr::ConfigSearch {
+    upkr::ConfigSearch {
+        use_bitstream: false,
+        parity_contexts: false,
+        simplified_prob_update: false,
+        no_repeated_offsets: false,
+        eof_in_length: false,
+        update_rates: false,
+    }
+}
+
+fn parse_config_search(list: &str) -> Result<upkr::ConfigSearch> {
+    let mut search = fixed_config_search();
+    for option in list.split(',') {
+        match option {
+            "bitstream" => search.use_bitstream = true,
+            "parity" => search.parity_contexts = true,
+            "simplified-prob-update" => search.simplified_prob_update = true,
+            "no-repeated-offsets" => search.no_repeated_offsets = true,
+            "eof-in-length" => search.eof_in_length = true,
+            "update-rates" => search.update_rates = true,
+            _ => anyhow::bail!("unknown --auto option '{}'", option),
+        }
+    }
+    Ok(search)
+}
+
+fn config_options(config: &upkr::Config) -> String {
+    let mut options = vec![];
+    if config.bitstream_is_big_endian {
+        options.push("--big-endian-bitstream".to_string());
+    } else if config.use_bitstream {
+        options.push("-b".to_string());
+    }
+    if config.parity_contexts != 1 {
+        options.push(format!("-p {}", config.parity_contexts));
+    }
+    if config.literal_context_bits != 0 {
+        options.push(format!(
+            "--literal-context-bits {}",
+            config.literal_context_bits
+        ));
+    }
+    for (flag, option) in [
+        (!config.is_match_bit, "--invert-is-match-bit"),
+        (!config.new_offset_bit, "--invert-new-offset-bit"),
+        (!config.continue_value_bit, "--invert-continue-value-bit"),
+        (config.invert_bit_encoding, "--invert-bit-encoding"),
+        (config.simplified_prob_update, "--simplified-prob-update"),
+        (config.no_repeated_offsets, "--no-repeated-offsets"),
+        (config.eof_in_length, "--eof-in-length"),
+    ] {
+        if flag {
+            options.push(option.to_string());
+        }
+    }
+    if config.prob_bits != 8 {
+        options.push(format!("--prob-bits {}", config.prob_bits));
+    }
+    match config.update_rates() {
+        [4, 4, 4] => (),
+        [rate, flag_rate, length_rate] if rate == flag_rate && rate == length_rate => {
+            options.push(format!("--update-rate {}", rate));
+        }
+        [literal_rate, flag_rate, length_rate] => {
+            for (rate, option) in [
+                (literal_rate, "--literal-update-rate"),
+                (flag_rate, "--flag-update-rate"),
+                (length_rate, "--length-update-rate"),
+            ] {
+                if rate != 4 {
+                    options.push(format!("{} {}", option, rate));
+                }
+            }
+        }
+    }
+    if config.max_offset != usize::MAX {
+        options.push(format!("--max-offset {}", config.max_offset));
+    }
+    if config.max_length != usize::MAX {
+        options.push(format!("--max-length {}", config.max_length));
+    }
+    options.join(" ")
+}
+
 enum OutFileType {
     Packed,
     Unpacked,
     Heatmap,
+    HeatmapHtml,
 }
 
 enum IoTarget {
@@ -241,6 +875,9 @@ impl IoTarget {
                     OutFileType::Heatmap => {
                         name.set_extension("heatmap");
                     }
+                    OutFileType::HeatmapHtml => {
+                        name.set_extension("html");
+                    }
                 }
                 IoTarget::File(name)
             }
@@ -250,19 +887,48 @@ impl IoTarget {
 
 fn print_help(exit_code: i32) -> ! {
     eprintln!("Usage:");
-    eprintln!("  upkr [-l level(0-9)] [config options] <infile> [<outfile>]");
+    eprintln!("  upkr [-l level(0-12)] [config options] <infile> [<outfile>]");
     eprintln!("  upkr -u [config options] <infile> [<outfile>]");
+    eprintln!("  upkr --multi [-l level(0-12)] [config options] <infile>... <outfile>");
+    eprintln!("  upkr -u --multi [config options] <infile> <outfile>...");
     eprintln!("  upkr --heatmap [config options] <infile> [<outfile>]");
     eprintln!("  upkr --margin [config options] <infile>");
+    eprintln!("  upkr --emit-unpacker <target> [config options] [<outfile>]");
     eprintln!();
-    eprintln!(" -l, --level N       compression level 0-9");
+    eprintln!(" -l, --level N       compression level 0-12 (10-12 add refinement passes)");
     eprintln!(" -0, ..., -9         short form for setting compression level");
+    eprintln!(" -j, --threads N     parse N blocks in parallel (slightly worse compression)");
+    eprintln!(" -w, --window N      parse in windows of N bytes to bound the memory use on");
+    eprintln!("                     large inputs (matches can't reach before the window)");
+    eprintln!(" --time-budget S     finish packing in about S seconds, parsing faster and");
+    eprintln!("                     finally greedily when running out of time");
+    eprintln!(" --memory-limit MB   limit the memory the parser uses to about MB megabytes");
     eprintln!(" -d, --decompress    decompress infile");
+    eprintln!(" --multi             pack several files into one stream, each followed by an");
+    eprintln!("                     EOF marker, or unpack such a stream into several files");
     eprintln!(" --heatmap           calculate heatmap from compressed file");
     eprintln!("   --raw-cost        report raw cost of literals in heatmap");
     #[cfg(feature = "crossterm")]
     eprintln!("   --hexdump         print heatmap as colored hexdump");
+    eprintln!(" --heatmap-html      write heatmap as html page with colored hexdump");
+    eprintln!(" --dump-ops          print the parse of infile with the cost of each op, using");
+    eprintln!("                     the same packing options as when compressing");
+    eprintln!(" --trace             print the ops decoded from a compressed file, with their");
+    eprintln!("                     position in the packed data, rANS state and cost");
+    eprintln!(" --heatmap-diff      compare the heatmaps of two compressed files");
+    eprintln!(" --symbols FILE      print the packed size per symbol and section, read from");
+    eprintln!("                     an ELF file, linker map or sjasmplus symbol file");
+    eprintln!("   --load-address A  address of the unpacked data (0x or $ prefix for hex)");
     eprintln!(" --margin            calculate margin for overlapped unpacking of a packed file");
+    eprintln!(" --auto[=OPTIONS]    try all variants of the config options not set explicitly");
+    eprintln!("                     (or the comma separated OPTIONS: bitstream, parity,");
+    eprintln!("                     simplified-prob-update, no-repeated-offsets, eof-in-length,");
+    eprintln!("                     update-rates) and write the smallest result,");
+    eprintln!("                     update-rates is only tried when listed");
+    eprintln!("                     (--z80, --x86 and --x86b set all config options to those");
+    eprintln!("                     of their unpacker, so with them only OPTIONS are tried)");
+    eprintln!(" --emit-unpacker T   write the source of an unpacker for the config options,");
+    eprintln!("                     T is one of: c, riscv, arm32, armv6m, z80, x86-16");
     eprintln!();
     eprintln!("When no infile is given, or the infile is '-', read from stdin.");
     eprintln!(
@@ -271,6 +937,27 @@ fn print_help(exit_code: i32) -> ! {
     eprintln!();
     eprintln!("Version: {}", env!("CARGO_PKG_VERSION"));
     eprintln!();
+    eprintln!("Parser options (override the settings of the level, N can be 'unlimited'):");
+    eprintln!(" --arrivals N        keep N alternative parses per position (level 9: 128),");
+    eprintln!("                     only use 'unlimited' on tiny inputs with a small cost delta");
+    eprintln!(" --cost-delta B      drop parses costing B bits more than the best one");
+    eprintln!(" --offset-cost-delta B");
+    eprintln!("                     drop parses costing B bits more than the best one with the");
+    eprintln!("                     same last offset");
+    eprintln!(" --near-matches N    also try the N closest occurrences of the current byte");
+    eprintln!(" --greedy-size N     take matches of at least N bytes without alternatives");
+    eprintln!(" --hash-chain, --suffix-array");
+    eprintln!("                     select the match finder (levels 0 and 1 use hash chains");
+    eprintln!("                     when --max-offset is smaller than infile)");
+    eprintln!(" --queue-size N, --patience N");
+    eprintln!("                     collect up to N candidates per length, skip up to N");
+    eprintln!("                     unreachable suffixes in the suffix array");
+    eprintln!(" --chain N           compare up to N candidates in the hash chains");
+    eprintln!(" --matches-per-length N, --length-diff N");
+    eprintln!("                     consider N matches per length, up to N shorter than");
+    eprintln!("                     the longest one");
+    eprintln!(" --passes P          refine the parse in P passes");
+    eprintln!();
     eprintln!("Config presets for specific unpackers:");
     eprintln!(
         " --z80               --big-endian-bitstream --invert-bit-encoding --simplified-prob-update -9"
@@ -285,8 +972,21 @@ fn print_help(exit_code: i32) -> ! {
     eprintln!("Config options (need to match when packing/unpacking):");
     eprintln!(" -b, --bitstream     bitstream mode");
     eprintln!(" -p, --parity N      use N (2/4) parity contexts");
+    eprintln!(" --literal-context-bits N");
+    eprintln!("                     select literal contexts by the top N (0-8) bits");
+    eprintln!("                     of the previous byte");
+    eprintln!(" --prob-bits N       use N (up to 12) bits of probability precision");
+    eprintln!(" --update-rate N     adapt probabilities by 1/2^N (default 4)");
+    eprintln!(" --literal-update-rate N, --flag-update-rate N, --length-update-rate N");
+    eprintln!("                     set the update rate of the literal, is match and");
+    eprintln!("                     new offset, or offset and length contexts");
     eprintln!(" -r, --reverse       reverse input & output");
     eprintln!();
+    eprintln!(" -c, --container     write a container recording the config options,");
+    eprintln!("                     the unpacked size, the margin and a checksum");
+    eprintln!("                     (containers are detected automatically when reading,");
+    eprintln!("                     -c makes a missing container header an error)");
+    eprintln!();
     eprintln!("Config options to tailor output to specific optimized unpackers:");
     eprintln!(" --invert-is-match-bit");
     eprintln!(" --invert-new-offset-bit");
@@ -1,10 +1,21 @@
 use std::collections::BinaryHeap;
 use std::ops::Range;
+use std::sync::Arc;
 
+// A source of earlier positions matching the data at `pos`, longest matches first and
+// the closest position first among matches of the same length.
+pub trait FindMatches {
+    fn matches(&mut self, pos: usize) -> impl Iterator<Item = Match>;
+}
+
+// Finds matches in the suffix array of the whole data. Building it takes the same time
+// and memory regardless of the compression level, but it finds the longest matches at
+// any distance.
+#[derive(Clone)]
 pub struct MatchFinder {
-    suffixes: Vec<i32>,
-    rev_suffixes: Vec<u32>,
-    lcp: Vec<u32>,
+    suffixes: Arc<[i32]>,
+    rev_suffixes: Arc<[u32]>,
+    lcp: Arc<[u32]>,
 
     max_queue_size: usize,
     max_matches_per_length: usize,
@@ -42,9 +53,9 @@ impl MatchFinder {
         }
 
         MatchFinder {
-            suffixes,
-            rev_suffixes,
-            lcp,
+            suffixes: suffixes.into(),
+            rev_suffixes: rev_suffixes.into(),
+            lcp: lcp.into(),
             max_queue_size: 100,
             max_matches_per_length: 5,
             patience: 100,
@@ -72,8 +83,10 @@ impl MatchFinder {
         self.max_length_diff = v;
         self
     }
+}
 
-    pub fn matches(&mut self, pos: usize) -> Matches {
+impl FindMatches for MatchFinder {
+    fn matches(&mut self, pos: usize) -> impl Iterator<Item = Match> {
         let index = self.rev_suffixes[pos] as usize;
         self.queue.clear();
         let mut matches = Matches {
@@ -107,7 +120,7 @@ pub struct Matches<'a> {
     max_length: usize,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Match {
     pub pos: usize,
     pub length: usize,
@@ -0,0 +1,139 @@
+use crate::parsing_packer::ParserConfig;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// The options of `pack_with_options`.
+///
+/// Besides the level, threads and window size of the other pack functions, they can bound
+/// the time and memory packing takes. Within these bounds, the parse considers fewer
+/// alternatives, but always results in a valid stream.
+#[derive(Debug, Clone)]
+pub struct PackOptions {
+    /// The compression level (0-12), see `pack`
+    pub level: u8,
+    /// The parser settings to use instead of the preset of `level`
+    pub parser_config: Option<ParserConfig>,
+    /// The number of threads to parse with, see `pack_parallel`
+    pub threads: usize,
+    /// The size of the blocks the data is parsed in, see `pack_windowed`
+    pub window_size: usize,
+    /// The wall-clock time packing should take. When the parse falls behind, it considers
+    /// fewer alternatives, and once the time is up, no more refinement passes are started
+    /// and the remaining data is parsed greedily. Packing can still overrun the budget by
+    /// the time this and the final encoding take.
+    pub time_budget: Option<Duration>,
+    /// The approximate number of bytes the match finder and the coder states of the parse
+    /// may use. The window size and the number of alternatives the parse considers are
+    /// reduced to fit an estimate computed from the sizes of these data structures. Actual
+    /// usage is not measured and can exceed the limit.
+    pub memory_limit: Option<usize>,
+}
+
+impl Default for PackOptions {
+    fn default() -> PackOptions {
+        PackOptions {
+            level: 2,
+            parser_config: None,
+            threads: 1,
+            window_size: usize::MAX,
+            time_budget: None,
+            memory_limit: None,
+        }
+    }
+}
+
+impl PackOptions {
+    // the settings of the optimal parser, `None` for the greedy parse of level 0
+    pub(crate) fn parser_config(&self) -> Option<ParserConfig> {
+        match self.parser_config {
+            Some(ref parser_config) => Some(parser_config.clone()),
+            None if self.level == 0 => None,
+            None => Some(ParserConfig::from_level(self.level)),
+        }
+    }
+}
+
+// The time and memory limits of packing, shared by all parsing threads. Packing runs out
+// of time once the time budget is used up or the progress callback cancels it. When only
+// estimating the packed size, refinement passes are compared by their entropy instead of
+// being encoded.
+pub struct Budget {
+    start: Instant,
+    time_budget: Option<Duration>,
+    memory_limit: Option<usize>,
+    size: usize,
+    progress: AtomicUsize,
+    cancelled: AtomicBool,
+    estimate_only: bool,
+}
+
+impl Budget {
+    pub fn new(options: &PackOptions, size: usize) -> Budget {
+        Budget {
+            start: Instant::now(),
+            time_budget: options.time_budget,
+            memory_limit: options.memory_limit,
+            size,
+            progress: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false),
+            estimate_only: false,
+        }
+    }
+
+    pub fn unlimited() -> Budget {
+        Budget::new(&PackOptions::default(), 0)
+    }
+
+    pub fn estimate_only() -> Budget {
+        Budget {
+            estimate_only: true,
+            ..Budget::unlimited()
+        }
+    }
+
+    pub fn is_estimate_only(&self) -> bool {
+        self.estimate_only
+    }
+
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit
+    }
+
+    pub fn set_progress(&self, progress: usize) {
+        self.progress.store(progress, Ordering::Relaxed);
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_out_of_time(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self
+                .time_budget
+                .is_some_and(|budget| self.start.elapsed() >= budget)
+    }
+
+    // Whether packing at the average speed so far would take more than 90% of the time
+    // budget, leaving some time for the final encoding. Only judged after the first 5% of
+    // the budget, when the speed is somewhat known.
+    pub fn is_behind(&self) -> bool {
+        self.time_used_per_progress()
+            .is_some_and(|(time_used, progress)| time_used >= 0.05 && time_used > progress * 0.9)
+    }
+
+    // Whether packing is far enough ahead of the time budget to spend more effort again.
+    pub fn is_ahead(&self) -> bool {
+        self.time_used_per_progress()
+            .is_some_and(|(time_used, progress)| time_used < progress * 0.7)
+    }
+
+    // the fractions of the time budget used and of the data processed
+    fn time_used_per_progress(&self) -> Option<(f64, f64)> {
+        self.time_budget.map(|budget| {
+            let time_used = self.start.elapsed().as_secs_f64() / budget.as_secs_f64();
+            let progress = self.progress.load(Ordering::Relaxed) as f64 / self.size.max(1) as f64;
+            (time_used, progress)
+        })
+    }
+}
@@ -1,62 +1,569 @@
 use std::collections::{HashMap, HashSet};
 use std::mem;
+use std::ops::Range;
 use std::rc::Rc;
+use std::sync::mpsc;
 
-use crate::match_finder::MatchFinder;
-use crate::rans::{CostCounter, RansCoder};
-use crate::{ProgressCallback, lz};
+use crate::greedy_packer;
+use crate::hash_chain_finder::HashChainFinder;
+use crate::match_finder::{FindMatches, MatchFinder};
+use crate::pack_options::Budget;
+use crate::rans::{CostCounter, EntropyCoder, RansCoder};
+use crate::{ParseProgress, lz};
 
-pub fn pack(
+// The bytes per position of the suffix array match finder (the suffix array, its inverse
+// and the longest common prefixes) and of the parse arena. The arena is compacted once it
+// has doubled, so it holds up to twice the live nodes, and at most about one node per
+// position was live at once on the benchmark corpus.
+const BYTES_PER_POSITION: usize =
+    size_of::<i32>() + 2 * size_of::<u32>() + 2 * size_of::<ParseNode>();
+// The number of coder states kept alive per arrival, they are shared by the arrivals
+// reached from the same one. Measured as the peak number of live states over
+// `max_arrivals` on the first 16kb of each file of the benchmark corpus at levels 2 to 9,
+// which ranged from 5 to 11.
+const STATES_PER_ARRIVAL: usize = 12;
+const MIN_WINDOW_SIZE: usize = 16 * 1024;
+// the highest compression level, levels 10 to 12 add refinement passes to level 9
+const MAX_LEVEL: u8 = 12;
+
+// Returns the window size for which parsing a window and its history takes at most half of
+// `memory_limit`, leaving the other half to the coder states of the arrivals.
+pub fn max_window_size(memory_limit: usize) -> usize {
+    (memory_limit / 2 / (2 * BYTES_PER_POSITION)).max(MIN_WINDOW_SIZE)
+}
+
+// Parses `data[start..]`, allowing matches to reference `data[..start]`. With refinement
+// passes, the parse which encodes to the smallest size is returned. The cost model starts
+// with the probabilities of `initial_state` if given.
+#[allow(clippy::too_many_arguments)]
+pub fn parse(
+    data: &[u8],
+    start: usize,
+    parser_config: &ParserConfig,
+    config: &crate::Config,
+    threads: usize,
+    budget: &Budget,
+    initial_state: Option<&lz::CoderState>,
+    progress_cb: Option<ParseProgress>,
+) -> Vec<lz::Op> {
+    parse_segments(
+        data,
+        start,
+        &[data.len()],
+        parser_config,
+        config,
+        threads,
+        budget,
+        initial_state,
+        progress_cb,
+    )
+    .pop()
+    .unwrap()
+}
+
+// Parses `data[start..]` in segments ending at `segment_ends`, each of which is followed by
+// an EOF marker and restarts the coder state like in `pack_multi`. The match finder is built
+// once for all segments.
+#[allow(clippy::too_many_arguments)]
+pub fn parse_segments(
+    data: &[u8],
+    start: usize,
+    segment_ends: &[usize],
+    parser_config: &ParserConfig,
+    config: &crate::Config,
+    threads: usize,
+    budget: &Budget,
+    initial_state: Option<&lz::CoderState>,
+    progress_cb: Option<ParseProgress>,
+) -> Vec<Vec<lz::Op>> {
+    let mut parse_config = parser_config.clone();
+    parse_config.passes = parse_config.passes.max(1);
+    if let Some(limit) = budget.memory_limit() {
+        let arrival_size = lz::CoderState::memory_size(config) * STATES_PER_ARRIVAL;
+        let max_arrivals = limit / 2 / threads.max(1) / arrival_size;
+        parse_config.max_arrivals = parse_config.max_arrivals.min(max_arrivals);
+    }
+    if parse_config.uses_hash_chain(data, config) {
+        let match_finder = HashChainFinder::new(data, config.max_offset)
+            .with_max_chain(parse_config.max_chain)
+            .with_max_matches_per_length(parse_config.max_matches_per_length)
+            .with_max_length_diff(parse_config.max_length_diff);
+        parse_segments_with_finder(
+            data,
+            start,
+            segment_ends,
+            match_finder,
+            &parse_config,
+            config,
+            threads,
+            budget,
+            initial_state,
+            progress_cb,
+        )
+    } else {
+        let match_finder = MatchFinder::new(data)
+            .with_max_queue_size(parse_config.max_queue_size)
+            .with_patience(parse_config.patience)
+            .with_max_matches_per_length(parse_config.max_matches_per_length)
+            .with_max_length_diff(parse_config.max_length_diff);
+        parse_segments_with_finder(
+            data,
+            start,
+            segment_ends,
+            match_finder,
+            &parse_config,
+            config,
+            threads,
+            budget,
+            initial_state,
+            progress_cb,
+        )
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn parse_segments_with_finder<F: FindMatches + Clone + Send>(
     data: &[u8],
-    level: u8,
+    start: usize,
+    segment_ends: &[usize],
+    mut match_finder: F,
+    parse_config: &ParserConfig,
     config: &crate::Config,
-    progress_cb: Option<ProgressCallback>,
-) -> Vec<u8> {
-    let mut parse = parse(data, Config::from_level(level), config, progress_cb);
-    let mut ops = vec![];
-    while let Some(link) = parse {
-        ops.push(link.op);
-        parse = link.prev.clone();
+    threads: usize,
+    budget: &Budget,
+    initial_state: Option<&lz::CoderState>,
+    mut progress_cb: Option<ParseProgress>,
+) -> Vec<Vec<lz::Op>> {
+    let mut segments = vec![];
+    let mut state = initial_state.cloned();
+    let mut segment_start = start;
+    for (index, &segment_end) in segment_ends.iter().enumerate() {
+        let mut segment_progress_cb = |pos: usize| {
+            if let Some(ref mut cb) = progress_cb {
+                cb(segment_start - start + pos);
+            }
+        };
+        if budget.is_out_of_time() {
+            // parse the remaining segments greedily, with one match finder for all of them
+            segments.extend(greedy_packer::parse_segments(
+                data,
+                segment_start,
+                &segment_ends[index..],
+                config,
+                Some(&mut segment_progress_cb),
+            ));
+            break;
+        }
+        // the match finder covers all segments, parsing stops at the end of this one
+        let segment_data = &data[..segment_end];
+        let ops = parse_with_finder(
+            segment_data,
+            segment_start,
+            &mut match_finder,
+            parse_config,
+            config,
+            threads,
+            budget,
+            state.as_ref(),
+            Some(&mut segment_progress_cb),
+        );
+        if segment_end < data.len() {
+            // the probabilities after the EOF marker carry over to the next segment
+            let mut segment_state = state.map_or_else(
+                || lz::CoderState::new(config).restart(segment_start),
+                |state| state.restart(segment_start),
+            );
+            let mut cost_counter = CostCounter::new(config);
+            encode_pass(&ops, &mut cost_counter, &mut segment_state, segment_data, config);
+            state = Some(segment_state.restart(segment_end));
+        }
+        segments.push(ops);
+        segment_start = segment_end;
     }
-    let mut state = lz::CoderState::new(config);
-    let mut coder = RansCoder::new(config);
-    for op in ops.into_iter().rev() {
-        op.encode(&mut coder, &mut state, config);
+    segments
+}
+
+#[allow(clippy::too_many_arguments)]
+fn parse_with_finder<F: FindMatches + Clone + Send>(
+    data: &[u8],
+    start: usize,
+    match_finder: &mut F,
+    parse_config: &ParserConfig,
+    config: &crate::Config,
+    threads: usize,
+    budget: &Budget,
+    initial_state: Option<&lz::CoderState>,
+    mut progress_cb: Option<ParseProgress>,
+) -> Vec<lz::Op> {
+    let size = data.len() - start;
+    let num_blocks = threads.min(size / MIN_BLOCK_SIZE).max(1);
+    let mut best: Option<(f64, Vec<lz::Op>)> = None;
+    let mut learned_state: Option<lz::CoderState> = initial_state.cloned();
+    for pass in 0..parse_config.passes {
+        if pass > 0 && budget.is_out_of_time() {
+            break;
+        }
+        let mut pass_progress_cb = |pos: usize| {
+            if let Some(ref mut cb) = progress_cb {
+                cb((pass * size + pos) / parse_config.passes);
+            }
+        };
+        let ops = if num_blocks == 1 {
+            parse_range(
+                data,
+                start..data.len(),
+                learned_state.as_ref().map_or_else(
+                    || lz::CoderState::new(config).restart(start),
+                    |state| state.restart(start),
+                ),
+                match_finder,
+                parse_config,
+                config,
+                budget,
+                Some(&mut pass_progress_cb),
+            )
+        } else {
+            parse_blocks(
+                data,
+                start,
+                num_blocks,
+                learned_state.as_ref(),
+                match_finder,
+                parse_config,
+                config,
+                budget,
+                &mut pass_progress_cb,
+            )
+        };
+
+        if parse_config.passes == 1 {
+            return ops;
+        }
+
+        let mut state = initial_state.map_or_else(
+            || lz::CoderState::new(config).restart(start),
+            |state| state.restart(start),
+        );
+        let packed_size = if budget.is_estimate_only() {
+            let mut cost_counter = CostCounter::new(config);
+            encode_pass(&ops, &mut cost_counter, &mut state, data, config);
+            cost_counter.cost()
+        } else {
+            let mut coder = RansCoder::new(config);
+            encode_pass(&ops, &mut coder, &mut state, data, config);
+            coder.finish().len() as f64 * 8.
+        };
+
+        // seed the cost model of the next pass with the final probabilities of this one
+        learned_state = Some(state);
+        if best.as_ref().is_none_or(|(best_size, _)| packed_size < *best_size) {
+            best = Some((packed_size, ops));
+        }
     }
-    lz::encode_eof(&mut coder, &mut state, config);
-    coder.finish()
+    best.unwrap().1
 }
 
-struct Parse {
-    prev: Option<Rc<Parse>>,
+// Encodes the ops of a pass, leaving `state` with the probabilities learned from them.
+fn encode_pass(
+    ops: &[lz::Op],
+    coder: &mut dyn EntropyCoder,
+    state: &mut lz::CoderState,
+    data: &[u8],
+    config: &crate::Config,
+) {
+    for &op in ops {
+        lz::encode_op(op, coder, state, data, config);
+    }
+    lz::encode_eof(coder, state, data, config);
+}
+
+const MIN_BLOCK_SIZE: usize = 16 * 1024;
+const WARMUP_SIZE: usize = 4096;
+const PROGRESS_STEP: usize = 1024;
+// the number of positions between adjusting the search effort to the time budget
+const BUDGET_CHECK_STEP: usize = 256;
+const MAX_EFFORT_SHIFT: u32 = 8;
+
+// Parses `data[start..]` in `num_blocks` equally sized blocks concurrently, each on its
+// own thread. The contexts at the start of each block are warmed up by greedily encoding
+// the preceding bytes, the actual encoding of the preceding block is not known yet.
+// In refinement passes, the learned state of the previous pass is used instead.
+#[allow(clippy::too_many_arguments)]
+fn parse_blocks<F: FindMatches + Clone + Send>(
+    data: &[u8],
+    start: usize,
+    num_blocks: usize,
+    learned_state: Option<&lz::CoderState>,
+    match_finder: &F,
+    config: &ParserConfig,
+    encoding_config: &crate::Config,
+    budget: &Budget,
+    progress_cb: ParseProgress,
+) -> Vec<lz::Op> {
+    let block_start = |index: usize| start + (data.len() - start) * index / num_blocks;
+    std::thread::scope(|scope| {
+        let (progress_tx, progress_rx) = mpsc::channel();
+        let workers: Vec<_> = (0..num_blocks)
+            .map(|index| {
+                let range = block_start(index)..block_start(index + 1);
+                let mut match_finder = match_finder.clone();
+                let progress_tx = progress_tx.clone();
+                scope.spawn(move || {
+                    let state = match learned_state {
+                        Some(state) => state.restart(range.start),
+                        None if index == 0 => lz::CoderState::new(encoding_config).restart(start),
+                        None => warmup(
+                            data,
+                            range.start.saturating_sub(WARMUP_SIZE)..range.start,
+                            &mut match_finder,
+                            encoding_config,
+                        ),
+                    };
+                    let len = range.len();
+                    let mut reported = 0;
+                    let ops = parse_range(
+                        data,
+                        range,
+                        state,
+                        &mut match_finder,
+                        config,
+                        encoding_config,
+                        budget,
+                        Some(&mut |progress| {
+                            if progress >= reported + PROGRESS_STEP {
+                                reported = progress;
+                                let _ = progress_tx.send((index, reported));
+                            }
+                        }),
+                    );
+                    let _ = progress_tx.send((index, len));
+                    ops
+                })
+            })
+            .collect();
+        drop(progress_tx);
+
+        let mut block_progress = vec![0; num_blocks];
+        for (index, progress) in progress_rx {
+            block_progress[index] = progress;
+            progress_cb(block_progress.iter().sum());
+        }
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+fn warmup(
+    data: &[u8],
+    range: Range<usize>,
+    match_finder: &mut impl FindMatches,
+    config: &crate::Config,
+) -> lz::CoderState {
+    let mut state = lz::CoderState::new(config).restart(range.start);
+    let mut cost_counter = CostCounter::new(config);
+    let mut pos = range.start;
+    while pos < range.end {
+        let op = match match_finder.matches(pos).next() {
+            Some(m)
+                if pos - m.pos <= config.max_offset
+                    && m.length >= config.min_length()
+                    && pos + m.length <= range.end =>
+            {
+                lz::Op::Match {
+                    offset: (pos - m.pos) as u32,
+                    len: m.length.min(config.max_length) as u32,
+                }
+            }
+            _ => lz::Op::Literal(data[pos]),
+        };
+        lz::encode_op(op, &mut cost_counter, &mut state, data, config);
+        pos = state.pos();
+    }
+    state
+}
+
+const NO_PARSE: u32 = u32::MAX;
+const MIN_COLLECT_SIZE: usize = 1 << 12;
+const MIN_RING_SIZE: usize = 256;
+
+// The ops of all parses, each node linking to the node of the op before it.
+struct ParseArena {
+    nodes: Vec<ParseNode>,
+    collect_at: usize,
+}
+
+struct ParseNode {
+    prev: u32,
     op: lz::Op,
 }
 
+impl ParseArena {
+    fn new() -> ParseArena {
+        ParseArena {
+            nodes: Vec::new(),
+            collect_at: MIN_COLLECT_SIZE,
+        }
+    }
+
+    fn push(&mut self, prev: u32, op: lz::Op) -> u32 {
+        self.nodes.push(ParseNode { prev, op });
+        (self.nodes.len() - 1) as u32
+    }
+
+    // Once the arena has doubled in size since the last collection, drops all nodes which
+    // can't be reached from the pending `arrivals` anymore. Nodes are created after the
+    // node they link to, so compacting them in order keeps the links valid.
+    fn collect(&mut self, arrivals: &mut ArrivalRing) {
+        if self.nodes.len() < self.collect_at {
+            return;
+        }
+        let mut new_index = vec![NO_PARSE; self.nodes.len()];
+        for arrival in arrivals.iter_mut() {
+            let mut index = arrival.parse;
+            while index != NO_PARSE && new_index[index as usize] == NO_PARSE {
+                new_index[index as usize] = 0;
+                index = self.nodes[index as usize].prev;
+            }
+        }
+        let mut len = 0;
+        for index in 0..self.nodes.len() {
+            if new_index[index] != NO_PARSE {
+                let prev = self.nodes[index].prev;
+                self.nodes[len] = ParseNode {
+                    prev: if prev == NO_PARSE {
+                        NO_PARSE
+                    } else {
+                        new_index[prev as usize]
+                    },
+                    op: self.nodes[index].op,
+                };
+                new_index[index] = len as u32;
+                len += 1;
+            }
+        }
+        self.nodes.truncate(len);
+        for arrival in arrivals.iter_mut() {
+            if arrival.parse != NO_PARSE {
+                arrival.parse = new_index[arrival.parse as usize];
+            }
+        }
+        self.collect_at = (len * 2).max(MIN_COLLECT_SIZE);
+    }
+
+    fn ops(&self, mut index: u32) -> Vec<lz::Op> {
+        let mut ops = vec![];
+        while index != NO_PARSE {
+            let node = &self.nodes[index as usize];
+            ops.push(node.op);
+            index = node.prev;
+        }
+        ops.reverse();
+        ops
+    }
+}
+
+// An arrival at a position, reached by `op` after the ops of `parse` which end in `state`.
+// The state after `op` is only computed once the arrival is processed, so the contexts
+// are not copied for the many arrivals which are dropped before.
 struct Arrival {
-    parse: Option<Rc<Parse>>,
-    state: lz::CoderState,
+    parse: u32,
+    op: Option<lz::Op>,
+    state: Rc<lz::CoderState>,
+    last_offset: u32,
     cost: f64,
 }
 
-type Arrivals = HashMap<usize, Vec<Arrival>>;
+impl Arrival {
+    // Returns the parse and coder state including `op`.
+    fn resolve(
+        self,
+        arena: &mut ParseArena,
+        cost_counter: &mut CostCounter,
+        data: &[u8],
+        config: &crate::Config,
+    ) -> (u32, lz::CoderState) {
+        let mut state = Rc::unwrap_or_clone(self.state);
+        match self.op {
+            Some(op) => {
+                lz::encode_op(op, cost_counter, &mut state, data, config);
+                (arena.push(self.parse, op), state)
+            }
+            None => (self.parse, state),
+        }
+    }
+}
+
+// The pending arrivals ahead of the parse, in a ring buffer indexed by position. It grows
+// when a match reaches beyond its end.
+struct ArrivalRing {
+    slots: Vec<Vec<Arrival>>,
+    start: usize,
+}
+
+impl ArrivalRing {
+    fn new(start: usize) -> ArrivalRing {
+        ArrivalRing {
+            slots: (0..MIN_RING_SIZE).map(|_| Vec::new()).collect(),
+            start,
+        }
+    }
+
+    fn slot_mut(&mut self, pos: usize) -> &mut Vec<Arrival> {
+        if pos - self.start >= self.slots.len() {
+            let size = (pos - self.start + 1).next_power_of_two();
+            let mut slots: Vec<Vec<Arrival>> = (0..size).map(|_| Vec::new()).collect();
+            let old_mask = self.slots.len() - 1;
+            for p in self.start..self.start + self.slots.len() {
+                slots[p & (size - 1)] = mem::take(&mut self.slots[p & old_mask]);
+            }
+            self.slots = slots;
+        }
+        let mask = self.slots.len() - 1;
+        &mut self.slots[pos & mask]
+    }
+
+    // Moves the arrivals at `pos` into the empty `arrivals`, reusing its allocation for
+    // the slot. Positions have to be taken in increasing order.
+    fn take(&mut self, pos: usize, arrivals: &mut Vec<Arrival>) {
+        self.start = pos;
+        mem::swap(self.slot_mut(pos), arrivals);
+    }
 
-fn parse(
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut Arrival> {
+        self.slots.iter_mut().flatten()
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn parse_range(
     data: &[u8],
-    config: Config,
+    range: Range<usize>,
+    initial_state: lz::CoderState,
+    match_finder: &mut impl FindMatches,
+    config: &ParserConfig,
     encoding_config: &crate::Config,
-    mut progress_cb: Option<ProgressCallback>,
-) -> Option<Rc<Parse>> {
-    let mut match_finder = MatchFinder::new(data)
-        .with_max_queue_size(config.max_queue_size)
-        .with_patience(config.patience)
-        .with_max_matches_per_length(config.max_matches_per_length)
-        .with_max_length_diff(config.max_length_diff);
+    budget: &Budget,
+    mut progress_cb: Option<ParseProgress>,
+) -> Vec<lz::Op> {
     let mut near_matches = [usize::MAX; 1024];
     let mut last_seen = [usize::MAX; 256];
+    for pos in 0..range.start {
+        near_matches[pos % near_matches.len()] = last_seen[data[pos] as usize];
+        last_seen[data[pos] as usize] = pos;
+    }
+    let data = &data[..range.end];
 
-    let max_arrivals = config.max_arrivals;
+    // halved on the fly while the parse is behind its time budget, and restored once ahead
+    let mut effort_shift = 0;
+    let mut max_arrivals = config.max_arrivals;
+    let mut num_near_matches = config.num_near_matches;
+    let mut next_budget_check = range.start;
 
-    let mut arrivals: Arrivals = HashMap::new();
+    let mut arena = ParseArena::new();
+    let mut arrivals = ArrivalRing::new(range.start);
     fn sort_arrivals(vec: &mut Vec<Arrival>, max_arrivals: usize) {
         if max_arrivals == 0 {
             return;
@@ -69,7 +576,7 @@ fn parse(
         let mut seen_offsets = HashSet::new();
         let mut remaining = Vec::new();
         for arr in mem::take(vec) {
-            if seen_offsets.insert(arr.state.last_offset()) {
+            if seen_offsets.insert(arr.last_offset) {
                 if vec.len() < max_arrivals {
                     vec.push(arr);
                 }
@@ -85,8 +592,8 @@ fn parse(
         }
     }
 
-    fn add_arrival(arrivals: &mut Arrivals, pos: usize, arrival: Arrival, max_arrivals: usize) {
-        let vec = arrivals.entry(pos).or_default();
+    fn add_arrival(arrivals: &mut ArrivalRing, pos: usize, arrival: Arrival, max_arrivals: usize) {
+        let vec = arrivals.slot_mut(pos);
         if max_arrivals == 0 {
             if vec.is_empty() {
                 vec.push(arrival);
@@ -96,51 +603,37 @@ fn parse(
             return;
         }
         vec.push(arrival);
-        if vec.len() > max_arrivals * 2 {
+        if vec.len() > max_arrivals.saturating_mul(2) {
             sort_arrivals(vec, max_arrivals);
         }
     }
     fn add_match(
-        arrivals: &mut Arrivals,
+        next_ops: &mut Vec<(lz::Op, f64)>,
         cost_counter: &mut CostCounter,
-        pos: usize,
+        data: &[u8],
         offset: usize,
         mut length: usize,
-        arrival: &Arrival,
-        max_arrivals: usize,
+        state: &mut lz::CoderState,
         config: &crate::Config,
     ) {
         if length < config.min_length() {
             return;
         }
         length = length.min(config.max_length);
-        cost_counter.reset();
-        let mut state = arrival.state.clone();
         let op = lz::Op::Match {
             offset: offset as u32,
             len: length as u32,
         };
-        op.encode(cost_counter, &mut state, config);
-        add_arrival(
-            arrivals,
-            pos + length,
-            Arrival {
-                parse: Some(Rc::new(Parse {
-                    prev: arrival.parse.clone(),
-                    op,
-                })),
-                state,
-                cost: arrival.cost + cost_counter.cost(),
-            },
-            max_arrivals,
-        );
+        next_ops.push((op, lz::op_cost(op, cost_counter, state, data, config)));
     }
     add_arrival(
         &mut arrivals,
-        0,
+        range.start,
         Arrival {
-            parse: None,
-            state: lz::CoderState::new(encoding_config),
+            parse: NO_PARSE,
+            op: None,
+            last_offset: initial_state.last_offset(),
+            state: Rc::new(initial_state),
             cost: 0.0,
         },
         max_arrivals,
@@ -148,7 +641,11 @@ fn parse(
 
     let cost_counter = &mut CostCounter::new(encoding_config);
     let mut best_per_offset = HashMap::new();
-    for pos in 0..data.len() {
+    let mut here_arrivals = Vec::new();
+    // the ops following an arrival with their costs, which are only added as arrivals
+    // once the state they share is no longer needed to calculate costs
+    let mut next_ops = Vec::new();
+    for pos in range.clone() {
         let match_length = |offset: usize| {
             data[pos..]
                 .iter()
@@ -157,141 +654,214 @@ fn parse(
                 .count()
         };
 
-        let here_arrivals = if let Some(mut arr) = arrivals.remove(&pos) {
-            sort_arrivals(&mut arr, max_arrivals);
-            arr
-        } else {
+        arena.collect(&mut arrivals);
+        arrivals.take(pos, &mut here_arrivals);
+        if here_arrivals.is_empty() {
             continue;
-        };
+        }
+        sort_arrivals(&mut here_arrivals, max_arrivals);
+
+        if budget.is_out_of_time() {
+            // finish the best parse so far greedily
+            let best = here_arrivals.swap_remove(0);
+            let mut ops = arena.ops(best.parse);
+            ops.extend(best.op);
+            ops.extend(greedy_packer::parse(data, pos, encoding_config, None));
+            return ops;
+        }
+        if pos >= next_budget_check {
+            next_budget_check = pos + BUDGET_CHECK_STEP;
+            if budget.is_behind() {
+                effort_shift = (effort_shift + 1).min(MAX_EFFORT_SHIFT);
+            } else if budget.is_ahead() {
+                effort_shift = effort_shift.saturating_sub(1);
+            }
+            max_arrivals = (config.max_arrivals >> effort_shift).max(config.max_arrivals.min(1));
+            num_near_matches = config.num_near_matches >> effort_shift;
+        }
         best_per_offset.clear();
         let mut best_cost = f64::MAX;
         for arrival in &here_arrivals {
             best_cost = best_cost.min(arrival.cost);
             let per_offset = best_per_offset
-                .entry(arrival.state.last_offset())
+                .entry(arrival.last_offset)
                 .or_insert(f64::MAX);
             *per_offset = per_offset.min(arrival.cost);
         }
 
-        'arrival_loop: for arrival in here_arrivals {
+        'arrival_loop: for arrival in here_arrivals.drain(..) {
             if arrival.cost
                 > (best_cost + config.max_cost_delta).min(
-                    *best_per_offset.get(&arrival.state.last_offset()).unwrap()
+                    *best_per_offset.get(&arrival.last_offset).unwrap()
                         + config.max_offset_cost_delta,
                 )
             {
                 continue;
             }
+            let cost = arrival.cost;
+            let last_offset = arrival.last_offset;
+            let (parse, mut state) =
+                arrival.resolve(&mut arena, cost_counter, data, encoding_config);
+
             let mut found_last_offset = false;
             let mut closest_match = None;
+            let mut greedy_match = false;
             for m in match_finder.matches(pos) {
                 closest_match = Some(closest_match.unwrap_or(0).max(m.pos));
                 let offset = pos - m.pos;
                 if offset <= encoding_config.max_offset {
-                    found_last_offset |= offset as u32 == arrival.state.last_offset();
+                    found_last_offset |= offset as u32 == last_offset;
                     add_match(
-                        &mut arrivals,
+                        &mut next_ops,
                         cost_counter,
-                        pos,
+                        data,
                         offset,
-                        m.length,
-                        &arrival,
-                        max_arrivals,
+                        m.length.min(data.len() - pos),
+                        &mut state,
                         encoding_config,
                     );
                     if m.length >= config.greedy_size {
-                        break 'arrival_loop;
+                        greedy_match = true;
+                        break;
                     }
                 }
             }
 
-            let mut near_matches_left = config.num_near_matches;
-            let mut match_pos = last_seen[data[pos] as usize];
-            while near_matches_left > 0
-                && match_pos != usize::MAX
-                && closest_match.iter().all(|p| *p < match_pos)
-            {
-                let offset = pos - match_pos;
-                if offset > encoding_config.max_offset {
-                    break;
-                }
-                let length = match_length(offset);
-                assert!(length > 0);
-                add_match(
-                    &mut arrivals,
-                    cost_counter,
-                    pos,
-                    offset,
-                    length,
-                    &arrival,
-                    max_arrivals,
-                    encoding_config,
-                );
-                found_last_offset |= offset as u32 == arrival.state.last_offset();
-                if offset < near_matches.len() {
-                    match_pos = near_matches[match_pos % near_matches.len()];
-                }
-                near_matches_left -= 1;
-            }
-
-            if !found_last_offset && arrival.state.last_offset() > 0 {
-                let offset = arrival.state.last_offset() as usize;
-                let length = match_length(offset);
-                if length > 0 {
+            if !greedy_match {
+                let mut near_matches_left = num_near_matches;
+                let mut match_pos = last_seen[data[pos] as usize];
+                while near_matches_left > 0
+                    && match_pos != usize::MAX
+                    && closest_match.iter().all(|p| *p < match_pos)
+                {
+                    let offset = pos - match_pos;
+                    if offset > encoding_config.max_offset {
+                        break;
+                    }
+                    let length = match_length(offset);
+                    assert!(length > 0);
                     add_match(
-                        &mut arrivals,
+                        &mut next_ops,
                         cost_counter,
-                        pos,
+                        data,
                         offset,
                         length,
-                        &arrival,
-                        max_arrivals,
+                        &mut state,
                         encoding_config,
                     );
+                    found_last_offset |= offset as u32 == last_offset;
+                    if offset < near_matches.len() {
+                        match_pos = near_matches[match_pos % near_matches.len()];
+                    }
+                    near_matches_left -= 1;
                 }
+
+                if !found_last_offset && last_offset > 0 {
+                    let offset = last_offset as usize;
+                    let length = match_length(offset);
+                    if length > 0 {
+                        add_match(
+                            &mut next_ops,
+                            cost_counter,
+                            data,
+                            offset,
+                            length,
+                            &mut state,
+                            encoding_config,
+                        );
+                    }
+                }
+
+                let op = lz::Op::Literal(data[pos]);
+                next_ops.push((
+                    op,
+                    lz::op_cost(op, cost_counter, &mut state, data, encoding_config),
+                ));
             }
 
-            cost_counter.reset();
-            let mut state = arrival.state;
-            let op = lz::Op::Literal(data[pos]);
-            op.encode(cost_counter, &mut state, encoding_config);
-            add_arrival(
-                &mut arrivals,
-                pos + 1,
-                Arrival {
-                    parse: Some(Rc::new(Parse {
-                        prev: arrival.parse,
-                        op,
-                    })),
-                    state,
-                    cost: arrival.cost + cost_counter.cost(),
-                },
-                max_arrivals,
-            );
+            let state = Rc::new(state);
+            for (op, op_cost) in next_ops.drain(..) {
+                let (len, last_offset) = match op {
+                    lz::Op::Literal(_) => (1, last_offset),
+                    lz::Op::Match { offset, len } => (len as usize, offset),
+                };
+                add_arrival(
+                    &mut arrivals,
+                    pos + len,
+                    Arrival {
+                        parse,
+                        op: Some(op),
+                        state: state.clone(),
+                        last_offset,
+                        cost: cost + op_cost,
+                    },
+                    max_arrivals,
+                );
+            }
+            if greedy_match {
+                break 'arrival_loop;
+            }
         }
         near_matches[pos % near_matches.len()] = last_seen[data[pos] as usize];
         last_seen[data[pos] as usize] = pos;
         if let Some(ref mut cb) = progress_cb {
-            cb(pos + 1);
+            cb(pos + 1 - range.start);
         }
     }
-    arrivals.remove(&data.len()).unwrap()[0].parse.clone()
+    arrivals.take(data.len(), &mut here_arrivals);
+    let best = &here_arrivals[0];
+    let mut ops = arena.ops(best.parse);
+    ops.extend(best.op);
+    ops
 }
 
-struct Config {
-    max_arrivals: usize,
-    max_cost_delta: f64,
-    max_offset_cost_delta: f64,
-    num_near_matches: usize,
-    greedy_size: usize,
-    max_queue_size: usize,
-    patience: usize,
-    max_matches_per_length: usize,
-    max_length_diff: usize,
+/// The settings of the optimal parser, trading compression ratio for packing time and memory.
+///
+/// The compression levels 1 to 12 are presets returned by `from_level`, but any combination
+/// can be used, for example far more arrivals than level 9 when packing a tiny intro.
+#[derive(Debug, Clone)]
+pub struct ParserConfig {
+    /// Find matches with hash chains instead of a suffix array, faster but finding fewer.
+    /// `None` uses hash chains only when `Config::max_offset` is smaller than the data, where
+    /// the longest matches in the suffix array are often out of reach.
+    pub hash_chain: Option<bool>,
+    /// The number of arrivals (alternative parses) kept per position, 0 keeps only the
+    /// cheapest one. Keeping all with `usize::MAX` is only feasible for tiny inputs with a
+    /// small `max_cost_delta`, their number grows exponentially otherwise.
+    pub max_arrivals: usize,
+    /// Arrivals costing more bits than this over the cheapest one are dropped
+    pub max_cost_delta: f64,
+    /// Arrivals costing more bits than this over the cheapest one with the same last offset
+    /// are dropped
+    pub max_offset_cost_delta: f64,
+    /// The number of recent occurrences of the current byte tried as matches, in addition
+    /// to those of the match finder
+    pub num_near_matches: usize,
+    /// Matches at least this long are taken without considering alternatives
+    pub greedy_size: usize,
+    /// The number of match candidates of one length the suffix array finder collects
+    pub max_queue_size: usize,
+    /// The number of suffixes outside the reachable window the suffix array finder skips
+    /// before giving up on shorter matches
+    pub patience: usize,
+    /// The number of candidates the hash chain finder compares per position
+    pub max_chain: usize,
+    /// The number of matches returned per match length
+    pub max_matches_per_length: usize,
+    /// Matches shorter than the longest one by more than this are not returned
+    pub max_length_diff: usize,
+    /// The number of parsing passes, each refining the cost model with the result of the
+    /// previous one
+    pub passes: usize,
 }
 
-impl Config {
-    fn from_level(level: u8) -> Config {
+impl ParserConfig {
+    /// Returns the settings of a compression level. Levels above 9 only add refinement
+    /// passes, levels above 12 are treated as 12.
+    pub fn from_level(level: u8) -> ParserConfig {
+        // levels above 9 only add refinement passes
+        let passes = level.clamp(9, MAX_LEVEL) as usize - 8;
+        let level = level.min(9);
         let max_arrivals = match level {
             0..=1 => 0,
             2 => 2,
@@ -317,7 +887,10 @@ impl Config {
             6..=7 => 3,
             _ => 4,
         };
-        Config {
+        ParserConfig {
+            // the suffix array only pays off with the wider search of higher levels, or when
+            // all matches are in reach
+            hash_chain: if level <= 1 { None } else { Some(false) },
             max_arrivals,
             max_cost_delta,
             max_offset_cost_delta,
@@ -325,8 +898,22 @@ impl Config {
             greedy_size,
             max_queue_size: level as usize * 100,
             patience: level as usize * 100,
+            max_chain: 256,
             max_matches_per_length: level as usize,
             max_length_diff,
+            passes,
         }
     }
+
+    // whether matches in `data` are found with hash chains
+    pub(crate) fn uses_hash_chain(&self, data: &[u8], config: &crate::Config) -> bool {
+        self.hash_chain
+            .unwrap_or_else(|| HashChainFinder::is_preferred(data, config.max_offset))
+    }
+}
+
+impl Default for ParserConfig {
+    fn default() -> ParserConfig {
+        ParserConfig::from_level(2)
+    }
 }
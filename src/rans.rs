@@ -1,8 +1,6 @@
-use crate::{context_state::Context, Config};
-use thiserror::Error;
-
-pub const PROB_BITS: u32 = 8;
-pub const ONE_PROB: u32 = 1 << PROB_BITS;
+use crate::Config;
+use upkr_decoder::Context;
+pub use upkr_decoder::{ByteSource, RansDecoder, UnexpectedEOF};
 
 pub trait EntropyCoder {
     fn encode_bit(&mut self, bit: bool, prob: u16);
@@ -18,6 +16,7 @@ pub struct RansCoder {
     use_bitstream: bool,
     bitstream_is_big_endian: bool,
     invert_bit_encoding: bool,
+    prob_bits: u32,
 }
 
 impl EntropyCoder for RansCoder {
@@ -35,11 +34,14 @@ impl RansCoder {
             use_bitstream: config.use_bitstream,
             bitstream_is_big_endian: config.bitstream_is_big_endian,
             invert_bit_encoding: config.invert_bit_encoding,
+            prob_bits: config.prob_bits as u32,
         }
     }
 
     pub fn finish(self) -> Vec<u8> {
         let mut buffer = vec![];
+        let prob_bits = self.prob_bits;
+        let one_prob = 1 << prob_bits;
         let l_bits: u32 = if self.use_bitstream { 15 } else { 12 };
         let mut state = 1 << l_bits;
 
@@ -77,19 +79,19 @@ impl RansCoder {
         };
 
         let num_flush_bits = if self.use_bitstream { 1 } else { 8 };
-        let max_state_factor: u32 = 1 << (l_bits + num_flush_bits - PROB_BITS);
+        let max_state_factor: u32 = 1 << (l_bits + num_flush_bits - prob_bits);
         for step in self.bits.into_iter().rev() {
             let prob = step as u32 & 32767;
             let (start, prob) = if step & 32768 != 0 {
                 (0, prob)
             } else {
-                (prob, ONE_PROB - prob)
+                (prob, one_prob - prob)
             };
             let max_state = max_state_factor * prob;
             while state >= max_state {
                 flush_state(&mut state);
             }
-            state = ((state / prob) << PROB_BITS) + (state % prob) + start;
+            state = ((state / prob) << prob_bits) + (state % prob) + start;
         }
 
         while state > 0 {
@@ -111,13 +113,15 @@ pub struct CostCounter {
     cost: f64,
     log2_table: Vec<f64>,
     invert_bit_encoding: bool,
+    one_prob: u32,
 }
 
 impl CostCounter {
     pub fn new(config: &Config) -> CostCounter {
-        let log2_table = (0..ONE_PROB)
+        let one_prob = config.one_prob();
+        let log2_table = (0..one_prob)
             .map(|prob| {
-                let inv_prob = ONE_PROB as f64 / prob as f64;
+                let inv_prob = one_prob as f64 / prob as f64;
                 inv_prob.log2()
             })
             .collect();
@@ -125,6 +129,7 @@ impl CostCounter {
             cost: 0.0,
             log2_table,
             invert_bit_encoding: config.invert_bit_encoding,
+            one_prob,
         }
     }
 
@@ -142,107 +147,14 @@ impl EntropyCoder for CostCounter {
         let prob = if bit ^ self.invert_bit_encoding {
             prob as u32
         } else {
-            ONE_PROB - prob as u32
+            self.one_prob - prob as u32
         };
         self.cost += self.log2_table[prob as usize];
     }
 }
 
-#[derive(Clone)]
-pub struct RansDecoder<'a> {
-    data: &'a [u8],
-    pos: usize,
-    state: u32,
-    use_bitstream: bool,
-    byte: u8,
-    bits_left: u8,
-    invert_bit_encoding: bool,
-    bitstream_is_big_endian: bool,
-}
-
-const PROB_MASK: u32 = ONE_PROB - 1;
-
-#[derive(Debug, Error)]
-#[error("Unexpected end of input")]
-pub struct UnexpectedEOF;
-
-impl<'a> RansDecoder<'a> {
-    pub fn new(data: &'a [u8], config: &Config) -> Result<RansDecoder<'a>, UnexpectedEOF> {
-        let mut decoder = RansDecoder {
-            data,
-            pos: 0,
-            state: 0,
-            use_bitstream: config.use_bitstream,
-            byte: 0,
-            bits_left: 0,
-            invert_bit_encoding: config.invert_bit_encoding,
-            bitstream_is_big_endian: config.bitstream_is_big_endian,
-        };
-        decoder.refill()?;
-        Ok(decoder)
-    }
-
-    pub fn pos(&self) -> usize {
-        self.pos
-    }
-
-    pub fn decode_with_context(&mut self, context: &mut Context) -> Result<bool, UnexpectedEOF> {
-        let bit = self.decode_bit(context.prob())?;
-        context.update(bit);
-        Ok(bit)
-    }
-
-    fn refill(&mut self) -> Result<(), UnexpectedEOF> {
-        if self.use_bitstream {
-            while self.state < 32768 {
-                if self.bits_left == 0 {
-                    if self.pos >= self.data.len() {
-                        return Err(UnexpectedEOF);
-                    }
-                    self.byte = self.data[self.pos];
-                    self.pos += 1;
-                    self.bits_left = 8;
-                }
-                if self.bitstream_is_big_endian {
-                    self.state = (self.state << 1) | (self.byte >> 7) as u32;
-                    self.byte <<= 1;
-                } else {
-                    self.state = (self.state << 1) | (self.byte & 1) as u32;
-                    self.byte >>= 1;
-                }
-                self.bits_left -= 1;
-            }
-        } else {
-            while self.state < 4096 {
-                if self.pos >= self.data.len() {
-                    return Err(UnexpectedEOF);
-                }
-                self.state = (self.state << 8) | self.data[self.pos] as u32;
-                self.pos += 1;
-            }
-        }
-        Ok(())
-    }
-
-    pub fn decode_bit(&mut self, prob: u16) -> Result<bool, UnexpectedEOF> {
-        self.refill()?;
-
-        let prob = prob as u32;
-
-        let bit = (self.state & PROB_MASK) < prob;
-
-        let (start, prob) = if bit {
-            (0, prob)
-        } else {
-            (prob, ONE_PROB - prob)
-        };
-        self.state = prob * (self.state >> PROB_BITS) + (self.state & PROB_MASK) - start;
-
-        Ok(bit ^ self.invert_bit_encoding)
-    }
-
-    pub fn cost(&self, prev: &RansDecoder) -> f32 {
-        f32::log2(prev.state as f32) - f32::log2(self.state as f32)
-            + (self.pos - prev.pos) as f32 * 8.
-    }
+/// The cost in bits of the symbols decoded between `prev` and `decoder`.
+pub fn decoding_cost<S: ByteSource>(decoder: &RansDecoder<S>, prev: &RansDecoder<S>) -> f32 {
+    f32::log2(prev.state() as f32) - f32::log2(decoder.state() as f32)
+        + (decoder.pos() - prev.pos()) as f32 * 8.
 }
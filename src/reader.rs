@@ -0,0 +1,185 @@
+use std::io::{self, Read};
+
+use crate::Config;
+use crate::lz::{Op, OpDecoder, UnpackError, new_op_decoder};
+use crate::rans::{ByteSource, RansDecoder};
+
+/// A streaming uncompressor reading packed data from any `std::io::Read`.
+///
+/// The uncompressed data is produced on demand, keeping only a window of the last
+/// `Config::max_offset` bytes in memory. The packed data is read one byte at a time,
+/// so it is a good idea to wrap unbuffered readers in a `std::io::BufReader`.
+///
+/// Corrupt or truncated packed data is reported as an `std::io::Error` wrapping the
+/// same `UnpackError` `unpack` would return. Truncated data uses
+/// `std::io::ErrorKind::UnexpectedEof`, all other errors `std::io::ErrorKind::InvalidData`.
+///
+/// Unlike `unpack`, the reader relies on `Config::max_offset`: it has to be at least the
+/// value the data was packed with. A match reaching further back than the window is
+/// reported as `UnpackError::OffsetBeyondWindow`.
+///
+/// # Example
+/// ```rust
+/// use std::io::Read;
+///
+/// let config = upkr::Config::default();
+/// let packed_data = upkr::pack(b"Hello, World! Yellow world!", 0, &config, None);
+/// let mut reader = upkr::UnpackReader::new(&packed_data[..], &config);
+/// let mut unpacked_data = vec![];
+/// reader.read_to_end(&mut unpacked_data).unwrap();
+/// assert_eq!(unpacked_data, b"Hello, World! Yellow world!");
+/// ```
+pub struct UnpackReader<R> {
+    decoder: RansDecoder<ReadSource<R>>,
+    op_decoder: OpDecoder,
+    config: Config,
+    window: Vec<u8>,
+    match_offset: usize,
+    match_left: usize,
+    pending_error: Option<io::Error>,
+    finished: bool,
+}
+
+impl<R: Read> UnpackReader<R> {
+    /// Creates a new `UnpackReader` uncompressing the packed data read from `reader`.
+    ///
+    /// `config` has to be the exact compression format config used to compress the data.
+    /// If it fails `Config::validate`, the first read returns the error.
+    pub fn new(reader: R, config: &Config) -> UnpackReader<R> {
+        let invalid_config = config.validate().err();
+        // nothing is decoded with an invalid config, so the default one stands in for it
+        let config = match invalid_config {
+            Some(_) => Config::default(),
+            None => config.clone(),
+        };
+        UnpackReader {
+            decoder: RansDecoder::new_lazy(
+                ReadSource {
+                    reader,
+                    error: None,
+                },
+                &config,
+            ),
+            op_decoder: new_op_decoder(&config),
+            config,
+            window: Vec::new(),
+            match_offset: 0,
+            match_left: 0,
+            pending_error: invalid_config
+                .map(|err| io::Error::new(io::ErrorKind::InvalidData, UnpackError::from(err))),
+            finished: invalid_config.is_some(),
+        }
+    }
+
+    /// Returns the inner reader.
+    ///
+    /// Any packed data following the EOF marker that was already read is lost.
+    pub fn into_inner(self) -> R {
+        self.decoder.into_source().reader
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.window.len() >= self.config.max_offset.saturating_mul(2) {
+            let excess = self.window.len() - self.config.max_offset;
+            self.window.drain(..excess);
+        }
+        self.window.push(byte);
+    }
+
+    fn next_byte(&mut self) -> Result<Option<u8>, io::Error> {
+        while self.match_left == 0 {
+            if self.finished {
+                return Ok(None);
+            }
+            let prev_byte = self.window.last().copied().unwrap_or(0);
+            match self
+                .op_decoder
+                .next_op(&mut self.decoder, prev_byte, &self.config)
+            {
+                Ok(Some(Op::Literal(byte))) => {
+                    self.push(byte);
+                    return Ok(Some(byte));
+                }
+                Ok(Some(Op::Match { offset, len })) => {
+                    let offset = offset as usize;
+                    if offset > self.window.len() {
+                        // the op decoder already rejects offsets beyond the start of the data
+                        self.finished = true;
+                        return Err(self.error(UnpackError::OffsetBeyondWindow {
+                            offset,
+                            max_offset: self.config.max_offset,
+                        }));
+                    }
+                    self.match_offset = offset;
+                    self.match_left = len as usize;
+                }
+                Ok(None) => self.finished = true,
+                Err(err) => {
+                    self.finished = true;
+                    return Err(self.error(err.into()));
+                }
+            }
+        }
+        let byte = self.window[self.window.len() - self.match_offset];
+        self.match_left -= 1;
+        self.push(byte);
+        Ok(Some(byte))
+    }
+
+    fn error(&mut self, err: UnpackError) -> io::Error {
+        if let Some(io_error) = self.decoder.source_mut().error.take() {
+            return io_error;
+        }
+        let kind = match err {
+            UnpackError::UnexpectedEOF { .. } => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+impl<R: Read> Read for UnpackReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(err) = self.pending_error.take() {
+            return Err(err);
+        }
+        let mut size = 0;
+        while size < buf.len() {
+            match self.next_byte() {
+                Ok(Some(byte)) => {
+                    buf[size] = byte;
+                    size += 1;
+                }
+                Ok(None) => break,
+                Err(err) if size == 0 => return Err(err),
+                Err(err) => {
+                    self.pending_error = Some(err);
+                    break;
+                }
+            }
+        }
+        Ok(size)
+    }
+}
+
+struct ReadSource<R> {
+    reader: R,
+    error: Option<io::Error>,
+}
+
+impl<R: Read> ByteSource for ReadSource<R> {
+    fn next_byte(&mut self) -> Option<u8> {
+        let mut byte = 0;
+        loop {
+            match self.reader.read(std::slice::from_mut(&mut byte)) {
+                Ok(0) => return None,
+                Ok(_) => return Some(byte),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => {
+                    self.error = Some(err);
+                    return None;
+                }
+            }
+        }
+    }
+}
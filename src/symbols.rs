@@ -0,0 +1,483 @@
+use crate::heatmap::Heatmap;
+use thiserror::Error;
+
+/// A symbol read from a symbol file, see `parse_symbols`.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    /// The name of the symbol
+    pub name: String,
+    /// The name of the section containing the symbol, if known
+    pub section: Option<String>,
+    /// The address of the symbol
+    pub address: u64,
+    /// The size of the symbol in bytes. When unknown, the symbol is assumed to extend
+    /// up to the next symbol.
+    pub size: Option<u64>,
+}
+
+/// The error returned by `parse_symbols` for files it can't read.
+#[derive(Error, Debug)]
+#[error("Invalid symbol file: {0}")]
+pub struct SymbolFileError(&'static str);
+
+/// The compressed size of the bytes attributed to a symbol or section.
+#[derive(Debug, Clone)]
+pub struct SymbolCost {
+    /// The name of the symbol or section, `[unknown]` for bytes not covered by any symbol
+    pub name: String,
+    /// The lowest address of the attributed bytes
+    pub address: u64,
+    /// The number of attributed bytes
+    pub bytes: usize,
+    /// The number of attributed bytes encoded as literals
+    pub literals: usize,
+    /// The summed `Heatmap::cost` of the attributed bytes, in (fractional) bits
+    pub cost: f32,
+}
+
+/// The costs per symbol and per section calculated by `Heatmap::symbol_costs`.
+#[derive(Debug, Clone)]
+pub struct SymbolCosts {
+    /// The cost of each symbol, most expensive first
+    pub symbols: Vec<SymbolCost>,
+    /// The cost of each section, most expensive first. Empty if the symbol file
+    /// doesn't contain section information.
+    pub sections: Vec<SymbolCost>,
+}
+
+const UNKNOWN: &str = "[unknown]";
+
+/// Reads the symbols from an ELF file, a GNU ld or lld linker map or a sjasmplus
+/// symbol file (`--sym` or `--exp` output).
+///
+/// The format is detected from the contents.
+///
+/// # Example
+/// ```rust
+/// let symbols = upkr::parse_symbols(b"main: EQU 0x8000\nmain.loop: EQU $8004\n").unwrap();
+/// assert_eq!(symbols[1].name, "main.loop");
+/// assert_eq!(symbols[1].address, 0x8004);
+/// ```
+pub fn parse_symbols(data: &[u8]) -> Result<Vec<Symbol>, SymbolFileError> {
+    if data.starts_with(b"\x7fELF") {
+        return parse_elf(data);
+    }
+    let text = std::str::from_utf8(data).map_err(|_| SymbolFileError("not an ELF or text file"))?;
+    let symbols = if text.lines().any(|line| {
+        line.split_whitespace()
+            .nth(1)
+            .is_some_and(|t| t.eq_ignore_ascii_case("equ"))
+    }) {
+        parse_sjasmplus(text)
+    } else if text.lines().any(|line| {
+        line.split_whitespace()
+            .take(4)
+            .eq(["VMA", "LMA", "Size", "Align"])
+    }) {
+        parse_lld_map(text)
+    } else {
+        parse_ld_map(text)?
+    };
+    if symbols.is_empty() {
+        return Err(SymbolFileError("no symbols found"));
+    }
+    Ok(symbols)
+}
+
+// parses 0x1234, $1234, #1234, 1234h and decimal numbers
+fn parse_number(text: &str) -> Option<u64> {
+    if let Some(hex) = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('$'))
+        .or_else(|| text.strip_prefix('#'))
+        .or_else(|| text.strip_suffix(['h', 'H']))
+    {
+        u64::from_str_radix(hex, 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+// lines of the form `label: EQU 0x00008000`
+fn parse_sjasmplus(text: &str) -> Vec<Symbol> {
+    let mut symbols = vec![];
+    for line in text.lines() {
+        let mut tokens = line.split_whitespace();
+        if let (Some(name), Some(equ), Some(value)) = (tokens.next(), tokens.next(), tokens.next())
+            && equ.eq_ignore_ascii_case("equ")
+            && let Some(address) = parse_number(value)
+        {
+            symbols.push(Symbol {
+                name: name.trim_end_matches(':').to_string(),
+                section: None,
+                address,
+                size: None,
+            });
+        }
+    }
+    symbols
+}
+
+// Output sections start at the beginning of a line, followed by their address and size
+// (on the next line for long section names). Symbols are indented lines consisting of
+// just an address and a name, and are limited to the end of their section.
+fn parse_ld_map(text: &str) -> Result<Vec<Symbol>, SymbolFileError> {
+    let mut symbols = vec![];
+    let mut section: Option<(String, Option<u64>)> = None;
+    for line in text.lines() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let hex = |index: usize| {
+            tokens
+                .get(index)
+                .filter(|token| token.starts_with("0x"))
+                .and_then(|token| parse_number(token))
+        };
+        // the end address of a section from the address and size at `index`
+        let section_end = |index: usize| match hex(index).zip(hex(index + 1)) {
+            Some((address, size)) => address
+                .checked_add(size)
+                .map(Some)
+                .ok_or(SymbolFileError("section size out of range in linker map")),
+            None => Ok(None),
+        };
+        if !line.starts_with(char::is_whitespace) {
+            section = match tokens.first() {
+                Some(name) if name.starts_with('.') => Some((name.to_string(), section_end(1)?)),
+                _ => None,
+            };
+        } else if let Some((ref name, ref mut end)) = section {
+            if end.is_none() {
+                // without its address, the symbols of the section would be lost
+                let missing = SymbolFileError("missing section address in linker map");
+                *end = Some(section_end(0)?.ok_or(missing)?);
+            } else if let [_, symbol] = tokens[..]
+                && let Some(address) = hex(0)
+            {
+                symbols.push(Symbol {
+                    name: symbol.to_string(),
+                    section: Some(name.clone()),
+                    address,
+                    size: end.map(|end| end.saturating_sub(address)),
+                });
+            }
+        }
+    }
+    Ok(symbols)
+}
+
+// Each line has the columns VMA, LMA, Size and Align, followed by the output section,
+// input section or symbol, distinguished by their indentation.
+fn parse_lld_map(text: &str) -> Vec<Symbol> {
+    let mut symbols = vec![];
+    let mut section = None;
+    for line in text.lines() {
+        let mut rest = line;
+        let mut columns = [0; 4];
+        let mut valid = true;
+        for column in &mut columns {
+            rest = rest.trim_start();
+            let end = rest.find(' ').unwrap_or(rest.len());
+            match u64::from_str_radix(&rest[..end], 16) {
+                Ok(value) => *column = value,
+                Err(_) => valid = false,
+            }
+            rest = &rest[end..];
+        }
+        let name = rest.trim();
+        if !valid || name.is_empty() {
+            continue;
+        }
+        match rest.len() - rest.trim_start().len() {
+            0..8 => section = Some(name.to_string()),
+            8..16 => (),
+            _ => symbols.push(Symbol {
+                name: name.to_string(),
+                section: section.clone(),
+                address: columns[0],
+                size: Some(columns[2]).filter(|&size| size > 0),
+            }),
+        }
+    }
+    symbols
+}
+
+struct ElfReader<'a> {
+    data: &'a [u8],
+    is_64: bool,
+    big_endian: bool,
+}
+
+impl ElfReader<'_> {
+    fn bytes<const N: usize>(&self, offset: u64) -> Result<[u8; N], SymbolFileError> {
+        usize::try_from(offset)
+            .ok()
+            .and_then(|offset| self.data.get(offset..offset.checked_add(N)?))
+            .map(|bytes| bytes.try_into().unwrap())
+            .ok_or(SymbolFileError("truncated ELF file"))
+    }
+
+    fn u8(&self, offset: u64) -> Result<u8, SymbolFileError> {
+        Ok(self.bytes::<1>(offset)?[0])
+    }
+
+    fn u16(&self, offset: u64) -> Result<u16, SymbolFileError> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: u64) -> Result<u32, SymbolFileError> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    // a 32 or 64 bit value, depending on the ELF class
+    fn word(&self, offset: u64) -> Result<u64, SymbolFileError> {
+        if !self.is_64 {
+            return Ok(self.u32(offset)? as u64);
+        }
+        let bytes = self.bytes(offset)?;
+        Ok(if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+
+    // the string at `offset` in the string table at `table`
+    fn string(&self, table: u64, offset: u32) -> Result<&str, SymbolFileError> {
+        let start = table
+            .checked_add(offset as u64)
+            .and_then(|start| usize::try_from(start).ok())
+            .filter(|&start| start <= self.data.len())
+            .ok_or(SymbolFileError("truncated ELF file"))?;
+        let bytes = &self.data[start..];
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        std::str::from_utf8(&bytes[..end]).map_err(|_| SymbolFileError("invalid symbol name"))
+    }
+
+    // the offset of entry `index` of the table at `table`. it is checked to be within the
+    // file, so adding the offset of a field of the entry can't overflow.
+    fn entry(&self, table: u64, index: u64, entry_size: u64) -> Result<u64, SymbolFileError> {
+        index
+            .checked_mul(entry_size)
+            .and_then(|offset| offset.checked_add(table))
+            .filter(|&offset| offset < self.data.len() as u64)
+            .ok_or(SymbolFileError("truncated ELF file"))
+    }
+}
+
+struct ElfSection {
+    name: u32,
+    kind: u32,
+    offset: u64,
+    size: u64,
+    link: u32,
+    entry_size: u64,
+}
+
+fn parse_elf(data: &[u8]) -> Result<Vec<Symbol>, SymbolFileError> {
+    const SHT_SYMTAB: u32 = 2;
+    const SHT_DYNSYM: u32 = 11;
+    const STT_SECTION: u8 = 3;
+    const STT_FILE: u8 = 4;
+    const SHN_LORESERVE: u16 = 0xff00;
+
+    let elf = ElfReader {
+        data,
+        is_64: match data.get(4) {
+            Some(1) => false,
+            Some(2) => true,
+            _ => return Err(SymbolFileError("unknown ELF class")),
+        },
+        big_endian: data.get(5) == Some(&2),
+    };
+    let (header_offset, section_offset) = if elf.is_64 {
+        (0x28, 0x3a)
+    } else {
+        (0x20, 0x2e)
+    };
+    let section_header_offset = elf.word(header_offset)?;
+    let section_header_size = elf.u16(section_offset)? as u64;
+    let num_sections = elf.u16(section_offset + 2)? as u64;
+    let section_names = elf.u16(section_offset + 4)? as usize;
+
+    let mut sections = vec![];
+    for index in 0..num_sections {
+        let header = elf.entry(section_header_offset, index, section_header_size)?;
+        let (w, s) = if elf.is_64 { (8, 0x14) } else { (4, 0) };
+        sections.push(ElfSection {
+            name: elf.u32(header)?,
+            kind: elf.u32(header + 4)?,
+            offset: elf.word(header + 8 + 2 * w)?,
+            size: elf.word(header + 8 + 3 * w)?,
+            link: elf.u32(header + 8 + 4 * w)?,
+            entry_size: elf.word(header + 0x24 + s)?,
+        });
+    }
+    let section_name = |index: usize| -> Result<Option<String>, SymbolFileError> {
+        match (sections.get(section_names), sections.get(index)) {
+            (Some(names), Some(section)) => Ok(Some(
+                elf.string(names.offset, section.name)?.to_string(),
+            )),
+            _ => Ok(None),
+        }
+    };
+
+    let symbol_table = sections
+        .iter()
+        .find(|section| section.kind == SHT_SYMTAB)
+        .or_else(|| sections.iter().find(|section| section.kind == SHT_DYNSYM))
+        .ok_or(SymbolFileError("no symbol table found"))?;
+    let strings = sections
+        .get(symbol_table.link as usize)
+        .ok_or(SymbolFileError("invalid string table"))?;
+    if symbol_table.entry_size == 0 {
+        return Err(SymbolFileError("invalid symbol table"));
+    }
+
+    let mut symbols = vec![];
+    for index in 1..symbol_table.size / symbol_table.entry_size {
+        let entry = elf.entry(symbol_table.offset, index, symbol_table.entry_size)?;
+        let (name, info, section_index, address, size) = if elf.is_64 {
+            (
+                elf.u32(entry)?,
+                elf.u8(entry + 4)?,
+                elf.u16(entry + 6)?,
+                elf.word(entry + 8)?,
+                elf.word(entry + 16)?,
+            )
+        } else {
+            (
+                elf.u32(entry)?,
+                elf.u8(entry + 12)?,
+                elf.u16(entry + 14)?,
+                elf.word(entry + 4)?,
+                elf.word(entry + 8)?,
+            )
+        };
+        let name = elf.string(strings.offset, name)?;
+        // skip undefined, absolute and common symbols, as well as section, file and
+        // ARM mapping symbols
+        if section_index == 0
+            || section_index >= SHN_LORESERVE
+            || matches!(info & 15, STT_SECTION | STT_FILE)
+            || name.is_empty()
+            || name.starts_with('$')
+        {
+            continue;
+        }
+        symbols.push(Symbol {
+            name: name.to_string(),
+            section: section_name(section_index as usize)?,
+            address,
+            size: Some(size).filter(|&size| size > 0),
+        });
+    }
+    if symbols.is_empty() {
+        return Err(SymbolFileError("no symbols found"));
+    }
+    Ok(symbols)
+}
+
+impl Heatmap {
+    /// Attributes the cost of each byte to the symbol it belongs to.
+    ///
+    /// `load_address` is the address of the first byte of the uncompressed data. Each byte
+    /// is attributed to the symbol with the highest address not above it, as long as it is
+    /// within the size of the symbol. Bytes not covered by any symbol are collected in a
+    /// `[unknown]` entry. The cost of the symbols is also summed per section.
+    pub fn symbol_costs(&self, symbols: &[Symbol], load_address: u64) -> SymbolCosts {
+        let mut symbols: Vec<&Symbol> = symbols.iter().collect();
+        symbols.sort_by_key(|symbol| symbol.address);
+
+        let mut costs: Vec<SymbolCost> = symbols
+            .iter()
+            .map(|symbol| SymbolCost {
+                name: symbol.name.clone(),
+                address: symbol.address,
+                bytes: 0,
+                literals: 0,
+                cost: 0.,
+            })
+            .collect();
+        let mut unknown = SymbolCost {
+            name: UNKNOWN.to_string(),
+            address: u64::MAX,
+            bytes: 0,
+            literals: 0,
+            cost: 0.,
+        };
+
+        let mut next = 0;
+        for index in 0..self.len() {
+            // bytes beyond the end of the address space can't belong to any symbol
+            let address = load_address.checked_add(index as u64);
+            if let Some(address) = address {
+                while next < symbols.len() && symbols[next].address <= address {
+                    next += 1;
+                }
+            }
+            let entry = match (address, next.checked_sub(1)) {
+                (Some(address), Some(i))
+                    if symbols[i]
+                        .size
+                        .is_none_or(|size| address - symbols[i].address < size) =>
+                {
+                    &mut costs[i]
+                }
+                _ => {
+                    unknown.address = unknown.address.min(address.unwrap_or(u64::MAX));
+                    &mut unknown
+                }
+            };
+            entry.bytes += 1;
+            entry.literals += self.is_literal(index) as usize;
+            entry.cost += self.cost(index);
+        }
+
+        let mut sections: Vec<SymbolCost> = vec![];
+        for (symbol, cost) in symbols.iter().zip(&costs) {
+            let Some(ref name) = symbol.section else {
+                continue;
+            };
+            if cost.bytes == 0 {
+                continue;
+            }
+            match sections.iter_mut().find(|section| section.name == *name) {
+                Some(section) => {
+                    section.address = section.address.min(cost.address);
+                    section.bytes += cost.bytes;
+                    section.literals += cost.literals;
+                    section.cost += cost.cost;
+                }
+                None => sections.push(SymbolCost {
+                    name: name.clone(),
+                    ..cost.clone()
+                }),
+            }
+        }
+        if !sections.is_empty() && unknown.bytes > 0 {
+            sections.push(unknown.clone());
+        }
+
+        costs.retain(|cost| cost.bytes > 0);
+        if unknown.bytes > 0 {
+            costs.push(unknown);
+        }
+        costs.sort_by(|a, b| b.cost.total_cmp(&a.cost));
+        sections.sort_by(|a, b| b.cost.total_cmp(&a.cost));
+        SymbolCosts {
+            symbols: costs,
+            sections,
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use std::fmt;
+use thiserror::Error;
+
+use crate::Config;
+
+/// The unpacker implementations `unpacker_source` can generate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnpackerTarget {
+    /// Portable C, supporting all `Config` variants
+    C,
+    /// RISC-V assembler (GNU as syntax)
+    RiscV,
+    /// 32 bit ARM assembler (GNU as syntax)
+    Arm32,
+    /// ARMv6-M thumb assembler (GNU as syntax)
+    ArmV6M,
+    /// Z80 assembler (sjasmplus syntax)
+    Z80,
+    /// 16 bit x86 DOS .com stub (nasm syntax)
+    X86_16,
+}
+
+impl UnpackerTarget {
+    /// All targets, in the order they are listed in the command line help.
+    pub const ALL: [UnpackerTarget; 6] = [
+        UnpackerTarget::C,
+        UnpackerTarget::RiscV,
+        UnpackerTarget::Arm32,
+        UnpackerTarget::ArmV6M,
+        UnpackerTarget::Z80,
+        UnpackerTarget::X86_16,
+    ];
+
+    /// The name of the target as used by `upkr --emit-unpacker`.
+    pub fn name(self) -> &'static str {
+        match self {
+            UnpackerTarget::C => "c",
+            UnpackerTarget::RiscV => "riscv",
+            UnpackerTarget::Arm32 => "arm32",
+            UnpackerTarget::ArmV6M => "armv6m",
+            UnpackerTarget::Z80 => "z80",
+            UnpackerTarget::X86_16 => "x86-16",
+        }
+    }
+
+    /// Looks up a target by its name.
+    pub fn from_name(name: &str) -> Option<UnpackerTarget> {
+        UnpackerTarget::ALL
+            .into_iter()
+            .find(|target| target.name() == name)
+    }
+
+    fn template(self) -> &'static str {
+        match self {
+            UnpackerTarget::C => include_str!("unpacker_source/unpack.c"),
+            UnpackerTarget::RiscV => include_str!("unpacker_source/unpack_riscv.S"),
+            UnpackerTarget::Arm32 => include_str!("unpacker_source/unpack_arm32.S"),
+            UnpackerTarget::ArmV6M => include_str!("unpacker_source/unpack_armv6m.S"),
+            UnpackerTarget::Z80 => include_str!("unpacker_source/unpack_z80.asm"),
+            UnpackerTarget::X86_16 => include_str!("unpacker_source/unpack_x86_16_DOS.asm"),
+        }
+    }
+
+    // The asm unpackers are hand optimized for one variant of the rANS coder, but
+    // the bit polarities, the EOF marker and repeated offsets can be adjusted freely.
+    fn check_config(self, config: &Config) -> Result<(), UnsupportedConfig> {
+        let unsupported = |reason| {
+            Err(UnsupportedConfig {
+                target: self,
+                reason,
+            })
+        };
+        let (bitstream, simplified_prob_update) = match self {
+            UnpackerTarget::C => return Ok(()),
+            UnpackerTarget::RiscV | UnpackerTarget::Arm32 | UnpackerTarget::ArmV6M => {
+                (false, false)
+            }
+            UnpackerTarget::Z80 => (true, true),
+            UnpackerTarget::X86_16 => (true, false),
+        };
+        if config.parity_contexts != 1 {
+            return unsupported("only supports parity_contexts = 1");
+        }
+        if config.literal_context_bits != 0 {
+            return unsupported("only supports literal_context_bits = 0");
+        }
+        if config.prob_bits != 8 {
+            return unsupported("only supports prob_bits = 8");
+        }
+        if config.update_rates() != [4; 3] {
+            return unsupported("only supports update rates of 4");
+        }
+        if config.use_bitstream != bitstream {
+            return unsupported(if bitstream {
+                "requires use_bitstream"
+            } else {
+                "doesn't support use_bitstream"
+            });
+        }
+        if config.simplified_prob_update != simplified_prob_update {
+            return unsupported(if simplified_prob_update {
+                "requires simplified_prob_update"
+            } else {
+                "doesn't support simplified_prob_update"
+            });
+        }
+        if self == UnpackerTarget::X86_16 && config.bitstream_is_big_endian {
+            return unsupported("doesn't support bitstream_is_big_endian");
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for UnpackerTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The error returned by `unpacker_source` for configs the target doesn't implement.
+#[derive(Error, Debug)]
+#[error("The {target} unpacker {reason}")]
+pub struct UnsupportedConfig {
+    /// the target the source was requested for
+    pub target: UnpackerTarget,
+    /// a description of the unsupported config option
+    pub reason: &'static str,
+}
+
+/// Generates the source code of an unpacker for data compressed with `config`.
+///
+/// All format variations of `config` are baked into the generated code, so it can
+/// only unpack data compressed with the same config. The C unpacker supports all
+/// configs, the asm unpackers are limited to the rANS variant they were optimized
+/// for and return `UnsupportedConfig` for everything else.
+///
+/// # Example
+/// ```rust
+/// let mut config = upkr::Config::default();
+/// config.parity_contexts = 2;
+/// let source = upkr::unpacker_source(upkr::UnpackerTarget::C, &config).unwrap();
+/// assert!(source.contains("u8 upkr_probs[641];"));
+/// assert!(upkr::unpacker_source(upkr::UnpackerTarget::Z80, &config).is_err());
+/// ```
+pub fn unpacker_source(
+    target: UnpackerTarget,
+    config: &Config,
+) -> Result<String, UnsupportedConfig> {
+    target.check_config(config)?;
+
+    // with differing update rates, the rate is passed to upkr_decode_bit
+    let [literal_rate, flag_rate, length_rate] = config.update_rates();
+    let per_class_rates = literal_rate != flag_rate || literal_rate != length_rate;
+    let rate_arg = |rate: u8| {
+        if per_class_rates {
+            format!(", {}", rate)
+        } else {
+            String::new()
+        }
+    };
+    let (update_rate, update_add, update_offset) = if per_class_rates {
+        (
+            "update_rate".to_string(),
+            "(1 << (update_rate - 1))".to_string(),
+            format!("({} >> update_rate)", config.one_prob()),
+        )
+    } else {
+        (
+            literal_rate.to_string(),
+            (1 << (literal_rate - 1)).to_string(),
+            (config.one_prob() >> literal_rate).to_string(),
+        )
+    };
+
+    let flags = [
+        ("use_bitstream", config.use_bitstream),
+        ("parity", config.parity_contexts > 1),
+        ("prev_byte_contexts", config.literal_context_bits > 0),
+        ("invert_bit_encoding", config.invert_bit_encoding),
+        ("is_match_bit", config.is_match_bit),
+        ("new_offset_bit", config.new_offset_bit),
+        ("continue_value_bit", config.continue_value_bit),
+        ("bitstream_is_big_endian", config.bitstream_is_big_endian),
+        ("simplified_prob_update", config.simplified_prob_update),
+        ("no_repeated_offsets", config.no_repeated_offsets),
+        ("eof_in_length", config.eof_in_length),
+        ("wide_probs", config.prob_bits > 8),
+        ("per_class_rates", per_class_rates),
+    ];
+    let parity = config.parity_contexts;
+    let num_literal_contexts = config.num_literal_contexts();
+    let (is_match_context, literal_context) = if num_literal_contexts > 256 {
+        ("literal_base", "literal_base + byte")
+    } else {
+        ("0", "byte")
+    };
+    let values = [
+        ("parity_contexts", parity.to_string()),
+        ("parity_mask", (parity - 1).to_string()),
+        (
+            "literal_context_bits",
+            config.literal_context_bits.to_string(),
+        ),
+        (
+            "prev_byte_shift",
+            (8 - config.literal_context_bits).to_string(),
+        ),
+        (
+            "num_contexts",
+            (num_literal_contexts + 1 + 64 + 64).to_string(),
+        ),
+        ("is_match_context", is_match_context.to_string()),
+        ("literal_context", literal_context.to_string()),
+        ("new_offset_context", num_literal_contexts.to_string()),
+        ("offset_context", (num_literal_contexts + 1).to_string()),
+        ("length_context", (num_literal_contexts + 65).to_string()),
+        ("prob_bits", config.prob_bits.to_string()),
+        ("prob_mask", (config.one_prob() - 1).to_string()),
+        ("one_prob", config.one_prob().to_string()),
+        ("init_prob", (config.one_prob() / 2).to_string()),
+        ("update_rate", update_rate),
+        ("update_add", update_add),
+        ("update_offset", update_offset),
+        ("literal_rate_arg", rate_arg(literal_rate)),
+        ("flag_rate_arg", rate_arg(flag_rate)),
+        ("length_rate_arg", rate_arg(length_rate)),
+    ];
+
+    let mut source = String::new();
+    // one entry per open {{#if}}, whether its current branch is taken
+    let mut conditions: Vec<bool> = vec![];
+    for line in target.template().lines() {
+        let directive = line.trim();
+        if let Some(name) = directive
+            .strip_prefix("{{#if ")
+            .and_then(|d| d.strip_suffix("}}"))
+        {
+            let (name, negated) = match name.strip_prefix('!') {
+                Some(name) => (name, true),
+                None => (name, false),
+            };
+            let value = flags
+                .iter()
+                .find(|(flag, _)| *flag == name)
+                .unwrap_or_else(|| panic!("unknown template flag {}", name))
+                .1;
+            conditions.push(value != negated);
+        } else if directive == "{{else}}" {
+            let condition = conditions.last_mut().expect("{{else}} outside of {{#if}}");
+            *condition = !*condition;
+        } else if directive == "{{/if}}" {
+            conditions.pop().expect("{{/if}} outside of {{#if}}");
+        } else if conditions.iter().all(|&taken| taken) {
+            if let Some((prefix, _)) = line.split_once("{{config}}") {
+                for line in config_description(config) {
+                    source.push_str(prefix);
+                    source.push_str(&line);
+                    source.push('\n');
+                }
+                continue;
+            }
+            let mut line = line.to_string();
+            for (name, value) in &values {
+                line = line.replace(&format!("{{{{{}}}}}", name), value);
+            }
+            source.push_str(&line);
+            source.push('\n');
+        }
+    }
+    assert!(
+        conditions.is_empty(),
+        "unterminated if in unpacker template"
+    );
+    Ok(source)
+}
+
+fn config_description(config: &Config) -> Vec<String> {
+    let fields = [
+        ("use_bitstream", config.use_bitstream.to_string()),
+        ("parity_contexts", config.parity_contexts.to_string()),
+        (
+            "literal_context_bits",
+            config.literal_context_bits.to_string(),
+        ),
+        (
+            "invert_bit_encoding",
+            config.invert_bit_encoding.to_string(),
+        ),
+        ("is_match_bit", config.is_match_bit.to_string()),
+        ("new_offset_bit", config.new_offset_bit.to_string()),
+        ("continue_value_bit", config.continue_value_bit.to_string()),
+        (
+            "bitstream_is_big_endian",
+            config.bitstream_is_big_endian.to_string(),
+        ),
+        (
+            "simplified_prob_update",
+            config.simplified_prob_update.to_string(),
+        ),
+        (
+            "no_repeated_offsets",
+            config.no_repeated_offsets.to_string(),
+        ),
+        ("eof_in_length", config.eof_in_length.to_string()),
+        ("prob_bits", config.prob_bits.to_string()),
+        (
+            "literal_update_rate",
+            config.literal_update_rate.to_string(),
+        ),
+        ("flag_update_rate", config.flag_update_rate.to_string()),
+        ("length_update_rate", config.length_update_rate.to_string()),
+    ];
+    fields
+        .iter()
+        .map(|(name, value)| format!("{:<24} {}", format!("{}:", name), value))
+        .collect()
+}
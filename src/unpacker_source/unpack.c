@@ -0,0 +1,195 @@
+/*
+    A C unpacker for upkr compressed data, generated by `upkr --emit-unpacker c`.
+
+    It only unpacks data compressed with this config:
+
+        {{config}}
+
+    See c_unpacker/unpack.c in the upkr repository for a description of the
+    compressed format.
+*/
+
+typedef unsigned char u8;
+typedef unsigned short u16;
+typedef unsigned long u32;
+
+u8* upkr_data_ptr;
+{{#if wide_probs}}
+u16 upkr_probs[{{num_contexts}}];
+{{else}}
+u8 upkr_probs[{{num_contexts}}];
+{{/if}}
+{{#if use_bitstream}}
+u16 upkr_state;
+u8 upkr_current_byte;
+int upkr_bits_left;
+{{else}}
+u32 upkr_state;
+{{/if}}
+
+{{#if per_class_rates}}
+int upkr_decode_bit(int context_index, int update_rate) {
+{{else}}
+int upkr_decode_bit(int context_index) {
+{{/if}}
+{{#if use_bitstream}}
+    // shift in single bits until rANS state is >= 32768
+    while(upkr_state < 32768) {
+        if(upkr_bits_left == 0) {
+            upkr_current_byte = *upkr_data_ptr++;
+            upkr_bits_left = 8;
+        }
+{{#if bitstream_is_big_endian}}
+        upkr_state = (upkr_state << 1) + (upkr_current_byte >> 7);
+        upkr_current_byte <<= 1;
+{{else}}
+        upkr_state = (upkr_state << 1) + (upkr_current_byte & 1);
+        upkr_current_byte >>= 1;
+{{/if}}
+        --upkr_bits_left;
+    }
+{{else}}
+    // shift in a full byte until rANS state is >= 4096
+    while(upkr_state < 4096) {
+        upkr_state = (upkr_state << 8) | *upkr_data_ptr++;
+    }
+{{/if}}
+
+    int prob = upkr_probs[context_index];
+    int bit = (upkr_state & {{prob_mask}}) < prob ? 1 : 0;
+
+    // rANS state and context probability update
+    if(bit) {
+        upkr_state = prob * (upkr_state >> {{prob_bits}}) + (upkr_state & {{prob_mask}});
+{{#if simplified_prob_update}}
+        prob += {{update_offset}} - ((prob + {{update_add}}) >> {{update_rate}});
+{{else}}
+        prob += ({{one_prob}} - prob + {{update_add}}) >> {{update_rate}};
+{{/if}}
+    } else {
+        upkr_state = ({{one_prob}} - prob) * (upkr_state >> {{prob_bits}}) + (upkr_state & {{prob_mask}}) - prob;
+        prob -= (prob + {{update_add}}) >> {{update_rate}};
+    }
+    upkr_probs[context_index] = prob;
+
+{{#if invert_bit_encoding}}
+    return !bit;
+{{else}}
+    return bit;
+{{/if}}
+}
+
+int upkr_decode_length(int context_index) {
+    int length = 0;
+    int bit_pos = 0;
+{{#if continue_value_bit}}
+    while(upkr_decode_bit(context_index{{length_rate_arg}})) {
+{{else}}
+    while(!upkr_decode_bit(context_index{{length_rate_arg}})) {
+{{/if}}
+        length |= upkr_decode_bit(context_index + 1{{length_rate_arg}}) << bit_pos++;
+        context_index += 2;
+    }
+    return length | (1 << bit_pos);
+}
+
+void* upkr_unpack(void* destination, void* compressed_data) {
+    upkr_data_ptr = (u8*)compressed_data;
+    upkr_state = 0;
+{{#if use_bitstream}}
+    upkr_bits_left = 0;
+{{/if}}
+    // all contexts are initialized to {{init_prob}} = equal probability of 0 and 1
+    for(int i = 0; i < {{num_contexts}}; ++i)
+        upkr_probs[i] = {{init_prob}};
+
+    u8* write_ptr = (u8*)destination;
+
+{{#if !no_repeated_offsets}}
+    int prev_was_match = 0;
+{{/if}}
+    int offset = 0;
+    for(;;) {
+{{#if prev_byte_contexts}}
+        // literals and the is match bit use one set of contexts per value of the
+        // top {{literal_context_bits}} bits of the previous byte
+        int prev_byte = write_ptr == (u8*)destination ? 0 : write_ptr[-1];
+{{#if parity}}
+        // and per write position modulo {{parity_contexts}}
+        int literal_base = ((prev_byte >> {{prev_byte_shift}}) * {{parity_contexts}}
+            + ((write_ptr - (u8*)destination) & {{parity_mask}})) * 256;
+{{else}}
+        int literal_base = (prev_byte >> {{prev_byte_shift}}) * 256;
+{{/if}}
+{{else}}
+{{#if parity}}
+        // literals and the is match bit use one set of contexts per write position modulo {{parity_contexts}}
+        int literal_base = ((write_ptr - (u8*)destination) & {{parity_mask}}) * 256;
+{{/if}}
+{{/if}}
+        // is match
+{{#if is_match_bit}}
+        if(upkr_decode_bit({{is_match_context}}{{flag_rate_arg}})) {
+{{else}}
+        if(!upkr_decode_bit({{is_match_context}}{{flag_rate_arg}})) {
+{{/if}}
+{{#if no_repeated_offsets}}
+{{#if eof_in_length}}
+            offset = upkr_decode_length({{offset_context}});
+{{else}}
+            offset = upkr_decode_length({{offset_context}}) - 1;
+            if(offset == 0) {
+                // a 0 offset signals the end of the compressed data
+                break;
+            }
+{{/if}}
+{{else}}
+            // has offset
+{{#if new_offset_bit}}
+            if(prev_was_match || upkr_decode_bit({{new_offset_context}}{{flag_rate_arg}})) {
+{{else}}
+            if(prev_was_match || !upkr_decode_bit({{new_offset_context}}{{flag_rate_arg}})) {
+{{/if}}
+{{#if eof_in_length}}
+                offset = upkr_decode_length({{offset_context}});
+{{else}}
+                offset = upkr_decode_length({{offset_context}}) - 1;
+                if(offset == 0) {
+                    // a 0 offset signals the end of the compressed data
+                    break;
+                }
+{{/if}}
+            }
+{{/if}}
+            int length = upkr_decode_length({{length_context}});
+{{#if eof_in_length}}
+            if(length == 1) {
+                // a length of 1 signals the end of the compressed data
+                break;
+            }
+{{/if}}
+            while(length--) {
+                *write_ptr = write_ptr[-offset];
+                ++write_ptr;
+            }
+{{#if !no_repeated_offsets}}
+            prev_was_match = 1;
+{{/if}}
+        } else {
+            // byte contains the previously read bits and indicates the number of
+            // read bits by the set top bit. Therefore it can be directly used as the
+            // context index. The set top bit ends up at bit position 8 and is not stored.
+            int byte = 1;
+            while(byte < 256) {
+                int bit = upkr_decode_bit({{literal_context}}{{literal_rate_arg}});
+                byte = (byte << 1) + bit;
+            }
+            *write_ptr++ = byte;
+{{#if !no_repeated_offsets}}
+            prev_was_match = 0;
+{{/if}}
+        }
+    }
+
+    return write_ptr;
+}
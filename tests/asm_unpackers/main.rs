@@ -0,0 +1,277 @@
+// Runs the assembled z80 and DOS x86 unpackers in small emulators and compares
+// their output with `upkr::unpack`.
+//
+// The binaries in `bin/` are assembled from the shipped `z80_unpacker` and
+// `dos_unpacker` sources, and from `upkr --emit-unpacker` output for the other
+// configs, see `bin/Makefile` for how to regenerate them. Run with `--nocapture`
+// to see the number of cycles each unpacker takes.
+
+mod x86;
+mod z80;
+
+use upkr::Config;
+
+struct TestFile {
+    name: &'static str,
+    data: Vec<u8>,
+}
+
+fn test_files() -> Vec<TestFile> {
+    // text with lots of short matches and repeated offsets
+    let mut text = Vec::new();
+    let mut seed = 12345u32;
+    for _ in 0..600 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let word = [
+            "upkr ", "rans ", "z80 ", "x86 ", "unpack ", "offset ", "length ",
+        ][(seed >> 16) as usize % 7];
+        text.extend_from_slice(word.as_bytes());
+        if seed & 0x300 == 0 {
+            text.push(b'0' + (seed >> 24) as u8 % 10);
+        }
+    }
+    vec![
+        TestFile {
+            name: "test_data.bin",
+            data: include_bytes!("../../asm_unpackers/test_data.bin").to_vec(),
+        },
+        TestFile {
+            name: "poison.scr",
+            data: include_bytes!("../../z80_unpacker/example/screens/Schafft - Poison (2017).scr")
+                .to_vec(),
+        },
+        TestFile {
+            name: "words.txt",
+            data: text,
+        },
+        TestFile {
+            name: "zeros",
+            data: vec![0; 3000],
+        },
+    ]
+}
+
+struct Preset {
+    name: &'static str,
+    binary: &'static [u8],
+    // the address the data is unpacked to
+    output: u16,
+    config: Config,
+}
+
+fn bitstream() -> Config {
+    Config {
+        use_bitstream: true,
+        ..Config::default()
+    }
+}
+
+fn z80_presets() -> Vec<Preset> {
+    let z80 = Config {
+        bitstream_is_big_endian: true,
+        invert_bit_encoding: true,
+        simplified_prob_update: true,
+        ..bitstream()
+    };
+    vec![
+        Preset {
+            name: "z80",
+            binary: include_bytes!("bin/z80.bin"),
+            output: 0x8000,
+            config: z80.clone(),
+        },
+        Preset {
+            name: "z80_little_endian",
+            binary: include_bytes!("bin/z80_little_endian.bin"),
+            output: 0x8000,
+            config: Config {
+                bitstream_is_big_endian: false,
+                ..z80.clone()
+            },
+        },
+        Preset {
+            name: "z80_inverted_bits",
+            binary: include_bytes!("bin/z80_inverted_bits.bin"),
+            output: 0x8000,
+            config: Config {
+                is_match_bit: false,
+                new_offset_bit: false,
+                continue_value_bit: false,
+                ..z80.clone()
+            },
+        },
+        Preset {
+            name: "z80_eof_in_length",
+            binary: include_bytes!("bin/z80_eof_in_length.bin"),
+            output: 0x8000,
+            config: Config {
+                invert_bit_encoding: false,
+                eof_in_length: true,
+                ..z80.clone()
+            },
+        },
+        Preset {
+            name: "z80_no_repeated_offsets",
+            binary: include_bytes!("bin/z80_no_repeated_offsets.bin"),
+            output: 0x8000,
+            config: Config {
+                no_repeated_offsets: true,
+                eof_in_length: true,
+                continue_value_bit: false,
+                ..z80
+            },
+        },
+    ]
+}
+
+fn x86_presets() -> Vec<Preset> {
+    let x86 = Config {
+        is_match_bit: false,
+        new_offset_bit: false,
+        continue_value_bit: false,
+        ..bitstream()
+    };
+    vec![
+        Preset {
+            name: "x86",
+            binary: include_bytes!("bin/x86.bin"),
+            output: 0x100,
+            config: x86.clone(),
+        },
+        Preset {
+            name: "x86b",
+            binary: include_bytes!("bin/x86b.bin"),
+            output: 0x100,
+            config: Config {
+                continue_value_bit: false,
+                no_repeated_offsets: true,
+                ..bitstream()
+            },
+        },
+        Preset {
+            name: "x86_no_relocation",
+            binary: include_bytes!("bin/x86_no_relocation.bin"),
+            output: 0x3ffe,
+            config: x86.clone(),
+        },
+        Preset {
+            name: "x86_default",
+            binary: include_bytes!("bin/x86_default.bin"),
+            output: 0x100,
+            config: bitstream(),
+        },
+        Preset {
+            name: "x86_eof_in_length",
+            binary: include_bytes!("bin/x86_eof_in_length.bin"),
+            output: 0x100,
+            config: Config {
+                eof_in_length: true,
+                ..x86.clone()
+            },
+        },
+        Preset {
+            name: "x86_invert_bit_encoding",
+            binary: include_bytes!("bin/x86_invert_bit_encoding.bin"),
+            output: 0x100,
+            config: Config {
+                invert_bit_encoding: true,
+                ..x86
+            },
+        },
+        Preset {
+            name: "x86_mixed",
+            binary: include_bytes!("bin/x86_mixed.bin"),
+            output: 0x100,
+            config: Config {
+                new_offset_bit: false,
+                eof_in_length: true,
+                invert_bit_encoding: true,
+                ..bitstream()
+            },
+        },
+    ]
+}
+
+// packs `data` and checks that the reference unpacker agrees before handing it to an emulator
+fn pack(data: &[u8], level: u8, config: &Config) -> Vec<u8> {
+    let packed = upkr::pack(data, level, config, None);
+    let unpacked = upkr::unpack(&packed, config, data.len()).unwrap();
+    assert!(unpacked == data);
+    packed
+}
+
+fn report(preset: &Preset, file: &TestFile, level: u8, packed_size: usize, cycles: u64) {
+    eprintln!(
+        "{:<24} {:<14} -{} {:>5} -> {:>5} bytes {:>9} cycles ({:.1}/byte)",
+        preset.name,
+        file.name,
+        level,
+        file.data.len(),
+        packed_size,
+        cycles,
+        cycles as f64 / file.data.len() as f64
+    );
+}
+
+#[test]
+fn z80_unpackers() {
+    const CODE: u16 = 0xf000;
+    const PACKED: u16 = 0x0100;
+    for preset in z80_presets() {
+        for file in test_files() {
+            for level in [0, 2] {
+                let packed = pack(&file.data, level, &preset.config);
+                assert!(PACKED as usize + packed.len() <= preset.output as usize);
+
+                let mut cpu = z80::Z80::new();
+                cpu.load(CODE, preset.binary);
+                cpu.load(PACKED, &packed);
+                cpu.ix = PACKED;
+                cpu.exx();
+                cpu.set_de(preset.output);
+                cpu.exx();
+                cpu.call(CODE, 100_000_000);
+
+                let output = &cpu.memory[preset.output as usize..];
+                assert!(
+                    output[..file.data.len()] == file.data[..],
+                    "{} failed to unpack {} at level {}",
+                    preset.name,
+                    file.name,
+                    level
+                );
+                assert!(cpu.ix as usize <= PACKED as usize + packed.len());
+                report(&preset, &file, level, packed.len(), cpu.cycles);
+            }
+        }
+    }
+}
+
+#[test]
+fn x86_unpackers() {
+    for preset in x86_presets() {
+        for file in test_files() {
+            for level in [0, 2] {
+                let packed = pack(&file.data, level, &preset.config);
+                // the stub only relocates 16kb and its bit offsets are signed 16 bit values
+                assert!(file.data.len() <= 16384 && packed.len() < 4096);
+
+                let mut program = preset.binary.to_vec();
+                program.extend_from_slice(&packed);
+                let mut cpu = x86::X86::new_com(&program);
+                // the unpacker jumps to the unpacked program when done
+                cpu.run_until(preset.output, 100_000_000);
+
+                let output = &cpu.memory[preset.output as usize..];
+                assert!(
+                    output[..file.data.len()] == file.data[..],
+                    "{} failed to unpack {} at level {}",
+                    preset.name,
+                    file.name,
+                    level
+                );
+                report(&preset, &file, level, packed.len(), cpu.cycles);
+            }
+        }
+    }
+}
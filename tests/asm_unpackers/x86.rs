@@ -0,0 +1,743 @@
+// A 16 bit x86 real mode interpreter for DOS .COM programs.
+//
+// All segment registers point at the same 64KB segment. It covers the integer
+// instructions of the 8086 plus the few 386 additions the DOS unpacker uses
+// (pusha/popa, shifts by immediates, movzx and bt). The cycle counts are the
+// 80386 clock counts, with the memory operand variants of bt taken for the
+// register bit offsets the unpacker uses.
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Reg(u8),
+    Mem(u16),
+}
+
+pub struct X86 {
+    // AX, CX, DX, BX, SP, BP, SI, DI
+    pub regs: [u16; 8],
+    pub ip: u16,
+    pub cf: bool,
+    pub pf: bool,
+    pub zf: bool,
+    pub sf: bool,
+    pub of: bool,
+    pub df: bool,
+    pub memory: Vec<u8>,
+    pub cycles: u64,
+}
+
+const AX: usize = 0;
+const CX: usize = 1;
+const SP: usize = 4;
+const SI: usize = 6;
+const DI: usize = 7;
+
+impl X86 {
+    /// Loads a .COM program at 0x100, with the registers set up like DOS does.
+    pub fn new_com(program: &[u8]) -> X86 {
+        let mut x86 = X86 {
+            regs: [0, 0x00ff, 0, 0, 0xfffe, 0x091c, 0x0100, 0xfffe],
+            ip: 0x100,
+            cf: false,
+            pf: false,
+            zf: false,
+            sf: false,
+            of: false,
+            df: false,
+            memory: vec![0; 0x10000],
+            cycles: 0,
+        };
+        assert!(0x100 + program.len() <= 0xff00);
+        x86.memory[0x100..0x100 + program.len()].copy_from_slice(program);
+        x86
+    }
+
+    /// Runs until execution reaches `address`.
+    pub fn run_until(&mut self, address: u16, max_instructions: u64) {
+        for _ in 0..max_instructions {
+            self.step();
+            if self.ip == address {
+                return;
+            }
+        }
+        panic!(
+            "x86: didn't reach {:04x} after {} instructions",
+            address, max_instructions
+        );
+    }
+
+    fn read8(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn write8(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+
+    fn read16(&self, address: u16) -> u16 {
+        u16::from_le_bytes([self.read8(address), self.read8(address.wrapping_add(1))])
+    }
+
+    fn write16(&mut self, address: u16, value: u16) {
+        let [lo, hi] = value.to_le_bytes();
+        self.write8(address, lo);
+        self.write8(address.wrapping_add(1), hi);
+    }
+
+    fn fetch8(&mut self) -> u8 {
+        let value = self.read8(self.ip);
+        self.ip = self.ip.wrapping_add(1);
+        value
+    }
+
+    fn fetch16(&mut self) -> u16 {
+        let value = self.read16(self.ip);
+        self.ip = self.ip.wrapping_add(2);
+        value
+    }
+
+    fn push(&mut self, value: u16) {
+        self.regs[SP] = self.regs[SP].wrapping_sub(2);
+        self.write16(self.regs[SP], value);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let value = self.read16(self.regs[SP]);
+        self.regs[SP] = self.regs[SP].wrapping_add(2);
+        value
+    }
+
+    fn reg8(&self, reg: u8) -> u8 {
+        let value = self.regs[(reg & 3) as usize];
+        if reg < 4 {
+            value as u8
+        } else {
+            (value >> 8) as u8
+        }
+    }
+
+    fn set_reg8(&mut self, reg: u8, value: u8) {
+        let r = &mut self.regs[(reg & 3) as usize];
+        if reg < 4 {
+            *r = (*r & 0xff00) | value as u16;
+        } else {
+            *r = (*r & 0x00ff) | ((value as u16) << 8);
+        }
+    }
+
+    fn flags(&self) -> u16 {
+        0x0002
+            | self.cf as u16
+            | (self.pf as u16) << 2
+            | (self.zf as u16) << 6
+            | (self.sf as u16) << 7
+            | (self.df as u16) << 10
+            | (self.of as u16) << 11
+    }
+
+    fn set_flags(&mut self, flags: u16) {
+        self.cf = flags & 1 != 0;
+        self.pf = flags & 4 != 0;
+        self.zf = flags & 0x40 != 0;
+        self.sf = flags & 0x80 != 0;
+        self.df = flags & 0x400 != 0;
+        self.of = flags & 0x800 != 0;
+    }
+
+    fn condition(&self, cc: u8) -> bool {
+        let result = match cc >> 1 {
+            0 => self.of,
+            1 => self.cf,
+            2 => self.zf,
+            3 => self.cf || self.zf,
+            4 => self.sf,
+            5 => self.pf,
+            6 => self.sf != self.of,
+            _ => self.zf || self.sf != self.of,
+        };
+        result != (cc & 1 != 0)
+    }
+
+    // decodes a modrm byte, returning the reg field and the r/m operand
+    fn modrm(&mut self) -> (u8, Operand, u32) {
+        let modrm = self.fetch8();
+        let mode = modrm >> 6;
+        let reg = (modrm >> 3) & 7;
+        let rm = modrm & 7;
+        if mode == 3 {
+            return (reg, Operand::Reg(rm), 0);
+        }
+        let r = &self.regs;
+        let base = match rm {
+            0 => r[3].wrapping_add(r[6]),
+            1 => r[3].wrapping_add(r[7]),
+            2 => r[5].wrapping_add(r[6]),
+            3 => r[5].wrapping_add(r[7]),
+            4 => r[6],
+            5 => r[7],
+            6 => r[5],
+            _ => r[3],
+        };
+        let address = match mode {
+            0 if rm == 6 => self.fetch16(),
+            0 => base,
+            1 => base.wrapping_add(self.fetch8() as i8 as u16),
+            _ => base.wrapping_add(self.fetch16()),
+        };
+        (reg, Operand::Mem(address), 1)
+    }
+
+    fn get(&self, operand: Operand, wide: bool) -> u16 {
+        match (operand, wide) {
+            (Operand::Reg(reg), true) => self.regs[reg as usize],
+            (Operand::Reg(reg), false) => self.reg8(reg) as u16,
+            (Operand::Mem(address), true) => self.read16(address),
+            (Operand::Mem(address), false) => self.read8(address) as u16,
+        }
+    }
+
+    fn set(&mut self, operand: Operand, wide: bool, value: u16) {
+        match (operand, wide) {
+            (Operand::Reg(reg), true) => self.regs[reg as usize] = value,
+            (Operand::Reg(reg), false) => self.set_reg8(reg, value as u8),
+            (Operand::Mem(address), true) => self.write16(address, value),
+            (Operand::Mem(address), false) => self.write8(address, value as u8),
+        }
+    }
+
+    fn set_result_flags(&mut self, result: u16, wide: bool) {
+        let sign_bit = if wide { 0x8000 } else { 0x80 };
+        let mask = if wide { 0xffff } else { 0xff };
+        self.zf = result & mask == 0;
+        self.sf = result & sign_bit != 0;
+        self.pf = (result as u8).count_ones() & 1 == 0;
+    }
+
+    // add, or, adc, sbb, and, sub, xor, cmp
+    fn alu(&mut self, op: u8, a: u16, b: u16, wide: bool) -> Option<u16> {
+        let (mask, sign_bit) = if wide {
+            (0xffffu32, 0x8000u32)
+        } else {
+            (0xff, 0x80)
+        };
+        let (a, b) = (a as u32 & mask, b as u32 & mask);
+        let carry = self.cf as u32;
+        let result = match op {
+            0 | 2 => {
+                let carry = if op == 2 { carry } else { 0 };
+                let result = a + b + carry;
+                self.cf = result > mask;
+                self.of = (a ^ result) & (b ^ result) & sign_bit != 0;
+                result
+            }
+            3 | 5 | 7 => {
+                let carry = if op == 3 { carry } else { 0 };
+                let result = a.wrapping_sub(b).wrapping_sub(carry);
+                self.cf = a < b + carry;
+                self.of = (a ^ b) & (a ^ result) & sign_bit != 0;
+                result
+            }
+            _ => {
+                self.cf = false;
+                self.of = false;
+                match op {
+                    1 => a | b,
+                    4 => a & b,
+                    _ => a ^ b,
+                }
+            }
+        };
+        let result = (result & mask) as u16;
+        self.set_result_flags(result, wide);
+        (op != 7).then_some(result)
+    }
+
+    // rol, ror, rcl, rcr, shl, shr, sal, sar
+    fn shift(&mut self, op: u8, value: u16, count: u8, wide: bool) -> u16 {
+        let bits = if wide { 16 } else { 8 };
+        let mask = if wide { 0xffffu32 } else { 0xff };
+        let sign_bit = bits - 1;
+        let mut value = value as u32 & mask;
+        let count = count & 31;
+        if count == 0 {
+            return value as u16;
+        }
+        for _ in 0..count {
+            let top = (value >> sign_bit) & 1;
+            let bottom = value & 1;
+            value = match op {
+                0 => {
+                    self.cf = top != 0;
+                    ((value << 1) | top) & mask
+                }
+                1 => {
+                    self.cf = bottom != 0;
+                    (value >> 1) | (bottom << sign_bit)
+                }
+                2 => {
+                    let result = ((value << 1) | self.cf as u32) & mask;
+                    self.cf = top != 0;
+                    result
+                }
+                3 => {
+                    let result = (value >> 1) | ((self.cf as u32) << sign_bit);
+                    self.cf = bottom != 0;
+                    result
+                }
+                4 | 6 => {
+                    self.cf = top != 0;
+                    (value << 1) & mask
+                }
+                5 => {
+                    self.cf = bottom != 0;
+                    value >> 1
+                }
+                _ => {
+                    self.cf = bottom != 0;
+                    (value >> 1) | (top << sign_bit)
+                }
+            };
+        }
+        let top = (value >> sign_bit) & 1 != 0;
+        self.of = match op {
+            0 | 2 | 4 | 6 => top != self.cf,
+            _ => top != ((value >> (sign_bit - 1)) & 1 != 0),
+        };
+        if op >= 4 {
+            self.set_result_flags(value as u16, wide);
+        }
+        value as u16
+    }
+
+    fn jump_relative(&mut self, displacement: u16) {
+        self.ip = self.ip.wrapping_add(displacement);
+    }
+
+    /// Executes a single instruction.
+    pub fn step(&mut self) {
+        let mut opcode = self.fetch8();
+        let mut rep = false;
+        if opcode == 0xf3 {
+            rep = true;
+            opcode = self.fetch8();
+        }
+        let cycles = self.execute(opcode, rep);
+        self.cycles += cycles as u64;
+    }
+
+    // returns the number of clocks
+    fn execute(&mut self, opcode: u8, rep: bool) -> u32 {
+        let wide = opcode & 1 != 0;
+        match opcode {
+            0x00..=0x3f if opcode & 7 < 6 => {
+                let op = opcode >> 3;
+                match opcode & 7 {
+                    0 | 1 => {
+                        let (reg, rm, mem) = self.modrm();
+                        let a = self.get(rm, wide);
+                        let b = self.get(Operand::Reg(reg), wide);
+                        if let Some(result) = self.alu(op, a, b, wide) {
+                            self.set(rm, wide, result);
+                        }
+                        if mem == 0 {
+                            2
+                        } else if op == 7 {
+                            5
+                        } else {
+                            7
+                        }
+                    }
+                    2 | 3 => {
+                        let (reg, rm, mem) = self.modrm();
+                        let a = self.get(Operand::Reg(reg), wide);
+                        let b = self.get(rm, wide);
+                        if let Some(result) = self.alu(op, a, b, wide) {
+                            self.set(Operand::Reg(reg), wide, result);
+                        }
+                        2 + 4 * mem
+                    }
+                    _ => {
+                        let b = if wide {
+                            self.fetch16()
+                        } else {
+                            self.fetch8() as u16
+                        };
+                        let a = self.get(Operand::Reg(0), wide);
+                        if let Some(result) = self.alu(op, a, b, wide) {
+                            self.set(Operand::Reg(0), wide, result);
+                        }
+                        2
+                    }
+                }
+            }
+            0x0f => self.execute_0f(),
+            0x40..=0x4f => {
+                let reg = (opcode & 7) as usize;
+                let cf = self.cf;
+                let value = self.regs[reg];
+                let result = self.alu(if opcode < 0x48 { 0 } else { 5 }, value, 1, true);
+                self.regs[reg] = result.unwrap();
+                self.cf = cf;
+                2
+            }
+            0x50..=0x57 => {
+                let value = self.regs[(opcode & 7) as usize];
+                self.push(value);
+                2
+            }
+            0x58..=0x5f => {
+                let value = self.pop();
+                self.regs[(opcode & 7) as usize] = value;
+                4
+            }
+            0x60 => {
+                let sp = self.regs[SP];
+                for reg in 0..8 {
+                    let value = if reg == SP { sp } else { self.regs[reg] };
+                    self.push(value);
+                }
+                18
+            }
+            0x61 => {
+                for reg in (0..8).rev() {
+                    let value = self.pop();
+                    if reg != SP {
+                        self.regs[reg] = value;
+                    }
+                }
+                24
+            }
+            0x70..=0x7f => {
+                let displacement = self.fetch8() as i8 as u16;
+                if self.condition(opcode & 15) {
+                    self.jump_relative(displacement);
+                    7
+                } else {
+                    3
+                }
+            }
+            0x80 | 0x81 | 0x83 => {
+                let (op, rm, mem) = self.modrm();
+                let b = match opcode {
+                    0x80 => self.fetch8() as u16,
+                    0x81 => self.fetch16(),
+                    _ => self.fetch8() as i8 as u16,
+                };
+                let a = self.get(rm, wide);
+                if let Some(result) = self.alu(op, a, b, wide) {
+                    self.set(rm, wide, result);
+                }
+                if mem == 0 {
+                    2
+                } else if op == 7 {
+                    5
+                } else {
+                    7
+                }
+            }
+            0x84 | 0x85 => {
+                let (reg, rm, mem) = self.modrm();
+                let a = self.get(rm, wide);
+                let b = self.get(Operand::Reg(reg), wide);
+                self.alu(4, a, b, wide);
+                2 + 3 * mem
+            }
+            0x86 | 0x87 => {
+                let (reg, rm, mem) = self.modrm();
+                let a = self.get(rm, wide);
+                let b = self.get(Operand::Reg(reg), wide);
+                self.set(rm, wide, b);
+                self.set(Operand::Reg(reg), wide, a);
+                3 + 2 * mem
+            }
+            0x88 | 0x89 => {
+                let (reg, rm, _) = self.modrm();
+                let value = self.get(Operand::Reg(reg), wide);
+                self.set(rm, wide, value);
+                2
+            }
+            0x8a | 0x8b => {
+                let (reg, rm, mem) = self.modrm();
+                let value = self.get(rm, wide);
+                self.set(Operand::Reg(reg), wide, value);
+                2 + 2 * mem
+            }
+            0x90..=0x97 => {
+                let reg = (opcode & 7) as usize;
+                self.regs.swap(AX, reg);
+                3
+            }
+            0x98 => {
+                self.regs[AX] = self.regs[AX] as u8 as i8 as u16;
+                3
+            }
+            0x99 => {
+                self.regs[2] = if self.regs[AX] & 0x8000 != 0 {
+                    0xffff
+                } else {
+                    0
+                };
+                2
+            }
+            0x9c => {
+                self.push(self.flags());
+                4
+            }
+            0x9d => {
+                let flags = self.pop();
+                self.set_flags(flags);
+                5
+            }
+            0xa4 | 0xa5 | 0xaa | 0xab | 0xac | 0xad => self.string_op(opcode, rep),
+            0xb0..=0xb7 => {
+                let value = self.fetch8();
+                self.set_reg8(opcode & 7, value);
+                2
+            }
+            0xb8..=0xbf => {
+                self.regs[(opcode & 7) as usize] = self.fetch16();
+                2
+            }
+            0xc0 | 0xc1 | 0xd0..=0xd3 => {
+                let (op, rm, mem) = self.modrm();
+                let count = match opcode {
+                    0xc0 | 0xc1 => self.fetch8(),
+                    0xd0 | 0xd1 => 1,
+                    _ => self.reg8(1),
+                };
+                let value = self.get(rm, wide);
+                let result = self.shift(op, value, count, wide);
+                self.set(rm, wide, result);
+                match (op, mem) {
+                    (2 | 3, 0) => 9,
+                    (2 | 3, _) => 10,
+                    (_, 0) => 3,
+                    _ => 7,
+                }
+            }
+            0xc3 => {
+                self.ip = self.pop();
+                10
+            }
+            0xc6 | 0xc7 => {
+                let (_, rm, _) = self.modrm();
+                let value = if wide {
+                    self.fetch16()
+                } else {
+                    self.fetch8() as u16
+                };
+                self.set(rm, wide, value);
+                2
+            }
+            0xe2 => {
+                let displacement = self.fetch8() as i8 as u16;
+                self.regs[CX] = self.regs[CX].wrapping_sub(1);
+                if self.regs[CX] != 0 {
+                    self.jump_relative(displacement);
+                }
+                11
+            }
+            0xe3 => {
+                let displacement = self.fetch8() as i8 as u16;
+                if self.regs[CX] == 0 {
+                    self.jump_relative(displacement);
+                    9
+                } else {
+                    5
+                }
+            }
+            0xe8 => {
+                let displacement = self.fetch16();
+                self.push(self.ip);
+                self.jump_relative(displacement);
+                7
+            }
+            0xe9 => {
+                let displacement = self.fetch16();
+                self.jump_relative(displacement);
+                7
+            }
+            0xeb => {
+                let displacement = self.fetch8() as i8 as u16;
+                self.jump_relative(displacement);
+                7
+            }
+            0xf5 => {
+                self.cf = !self.cf;
+                2
+            }
+            0xf6 | 0xf7 => self.execute_group3(wide),
+            0xf8 | 0xf9 => {
+                self.cf = opcode == 0xf9;
+                2
+            }
+            0xfc | 0xfd => {
+                self.df = opcode == 0xfd;
+                2
+            }
+            0xfe | 0xff => {
+                let (op, rm, mem) = self.modrm();
+                match op {
+                    0 | 1 => {
+                        let cf = self.cf;
+                        let value = self.get(rm, wide);
+                        let result = self.alu(if op == 0 { 0 } else { 5 }, value, 1, wide);
+                        self.set(rm, wide, result.unwrap());
+                        self.cf = cf;
+                        2 + 4 * mem
+                    }
+                    2 if wide => {
+                        let target = self.get(rm, true);
+                        self.push(self.ip);
+                        self.ip = target;
+                        7 + 3 * mem
+                    }
+                    4 if wide => {
+                        self.ip = self.get(rm, true);
+                        7 + 3 * mem
+                    }
+                    6 if wide => {
+                        let value = self.get(rm, true);
+                        self.push(value);
+                        2 + 3 * mem
+                    }
+                    _ => self.unsupported(opcode),
+                }
+            }
+            _ => self.unsupported(opcode),
+        }
+    }
+
+    fn execute_group3(&mut self, wide: bool) -> u32 {
+        let (op, rm, mem) = self.modrm();
+        let value = self.get(rm, wide);
+        match op {
+            0 => {
+                let b = if wide {
+                    self.fetch16()
+                } else {
+                    self.fetch8() as u16
+                };
+                self.alu(4, value, b, wide);
+                2 + 3 * mem
+            }
+            2 => {
+                self.set(rm, wide, !value);
+                2 + 4 * mem
+            }
+            3 => {
+                let result = self.alu(5, 0, value, wide).unwrap();
+                self.set(rm, wide, result);
+                2 + 4 * mem
+            }
+            4 => {
+                if wide {
+                    let result = self.regs[AX] as u32 * value as u32;
+                    self.regs[AX] = result as u16;
+                    self.regs[2] = (result >> 16) as u16;
+                    self.cf = result > 0xffff;
+                } else {
+                    let result = (self.regs[AX] & 0xff) * (value & 0xff);
+                    self.regs[AX] = result;
+                    self.cf = result > 0xff;
+                }
+                self.of = self.cf;
+                // the 386 takes 9-14 clocks for 8 bit, 9-22 clocks for 16 bit multiplies
+                (if wide { 16 } else { 12 }) + 3 * mem
+            }
+            _ => self.unsupported(0xf6 | wide as u8),
+        }
+    }
+
+    fn execute_0f(&mut self) -> u32 {
+        let opcode = self.fetch8();
+        match opcode {
+            0x80..=0x8f => {
+                let displacement = self.fetch16();
+                if self.condition(opcode & 15) {
+                    self.jump_relative(displacement);
+                    7
+                } else {
+                    3
+                }
+            }
+            0xa3 => {
+                // bt r/m16, r16: a memory operand addresses a bit string
+                let (reg, rm, _) = self.modrm();
+                let bit = self.regs[reg as usize];
+                match rm {
+                    Operand::Reg(rm) => {
+                        self.cf = (self.regs[rm as usize] >> (bit & 15)) & 1 != 0;
+                        3
+                    }
+                    Operand::Mem(address) => {
+                        let address = address.wrapping_add(((bit as i16) >> 3) as u16);
+                        self.cf = (self.read8(address) >> (bit & 7)) & 1 != 0;
+                        12
+                    }
+                }
+            }
+            0xb6 | 0xb7 => {
+                let (reg, rm, mem) = self.modrm();
+                let value = self.get(rm, opcode == 0xb7);
+                self.regs[reg as usize] = value;
+                3 + 3 * mem
+            }
+            _ => panic!(
+                "x86: unsupported instruction 0f {:02x} at {:04x}",
+                opcode,
+                self.ip.wrapping_sub(2)
+            ),
+        }
+    }
+
+    fn string_op(&mut self, opcode: u8, rep: bool) -> u32 {
+        let wide = opcode & 1 != 0;
+        let step = match (wide, self.df) {
+            (false, false) => 1,
+            (false, true) => 0xffff,
+            (true, false) => 2,
+            (true, true) => 0xfffe,
+        };
+        let count = if rep {
+            std::mem::take(&mut self.regs[CX])
+        } else {
+            1
+        };
+        for _ in 0..count {
+            match opcode {
+                0xa4 | 0xa5 => {
+                    let value = self.get(Operand::Mem(self.regs[SI]), wide);
+                    self.set(Operand::Mem(self.regs[DI]), wide, value);
+                    self.regs[SI] = self.regs[SI].wrapping_add(step);
+                    self.regs[DI] = self.regs[DI].wrapping_add(step);
+                }
+                0xaa | 0xab => {
+                    let value = self.regs[AX];
+                    self.set(Operand::Mem(self.regs[DI]), wide, value);
+                    self.regs[DI] = self.regs[DI].wrapping_add(step);
+                }
+                _ => {
+                    let value = self.get(Operand::Mem(self.regs[SI]), wide);
+                    self.set(Operand::Reg(0), wide, value);
+                    self.regs[SI] = self.regs[SI].wrapping_add(step);
+                }
+            }
+        }
+        let count = count as u32;
+        match (opcode, rep) {
+            (0xa4 | 0xa5, false) => 7,
+            (0xa4 | 0xa5, true) => 7 + 4 * count,
+            (0xaa | 0xab, false) => 4,
+            (0xaa | 0xab, true) => 5 + 5 * count,
+            _ => 5,
+        }
+    }
+
+    fn unsupported(&self, opcode: u8) -> u32 {
+        panic!(
+            "x86: unsupported instruction {:02x} near {:04x}",
+            opcode, self.ip
+        );
+    }
+}
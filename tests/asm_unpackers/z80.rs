@@ -0,0 +1,843 @@
+// A Z80 interpreter covering the documented instruction set, counting T-states.
+//
+// Interrupts, I/O and the undocumented flag bits 3 and 5 are not emulated.
+
+const FLAG_C: u8 = 0x01;
+const FLAG_N: u8 = 0x02;
+const FLAG_PV: u8 = 0x04;
+const FLAG_H: u8 = 0x10;
+const FLAG_Z: u8 = 0x40;
+const FLAG_S: u8 = 0x80;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Index {
+    HL,
+    IX,
+    IY,
+}
+
+pub struct Z80 {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub shadow: [u8; 8],
+    pub ix: u16,
+    pub iy: u16,
+    pub sp: u16,
+    pub pc: u16,
+    pub memory: Vec<u8>,
+    pub cycles: u64,
+}
+
+impl Z80 {
+    pub fn new() -> Z80 {
+        Z80 {
+            a: 0xff,
+            f: 0xff,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+            shadow: [0; 8],
+            ix: 0,
+            iy: 0,
+            sp: 0,
+            pc: 0,
+            memory: vec![0; 0x10000],
+            cycles: 0,
+        }
+    }
+
+    pub fn load(&mut self, address: u16, data: &[u8]) {
+        let address = address as usize;
+        assert!(address + data.len() <= self.memory.len());
+        self.memory[address..address + data.len()].copy_from_slice(data);
+    }
+
+    /// Calls the subroutine at `address` and runs until it returns.
+    pub fn call(&mut self, address: u16, max_instructions: u64) {
+        const RETURN_ADDRESS: u16 = 0;
+        self.push(RETURN_ADDRESS);
+        self.pc = address;
+        for _ in 0..max_instructions {
+            if self.pc == RETURN_ADDRESS {
+                return;
+            }
+            self.step();
+        }
+        panic!("z80: no return after {} instructions", max_instructions);
+    }
+
+    pub fn de(&self) -> u16 {
+        u16::from_le_bytes([self.e, self.d])
+    }
+
+    pub fn set_de(&mut self, value: u16) {
+        [self.e, self.d] = value.to_le_bytes();
+    }
+
+    pub fn exx(&mut self) {
+        let [b, c, d, e, h, l, ..] = self.shadow;
+        self.shadow[..6].copy_from_slice(&[self.b, self.c, self.d, self.e, self.h, self.l]);
+        (self.b, self.c, self.d, self.e, self.h, self.l) = (b, c, d, e, h, l);
+    }
+
+    fn read(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+
+    fn read16(&self, address: u16) -> u16 {
+        u16::from_le_bytes([self.read(address), self.read(address.wrapping_add(1))])
+    }
+
+    fn write16(&mut self, address: u16, value: u16) {
+        let [lo, hi] = value.to_le_bytes();
+        self.write(address, lo);
+        self.write(address.wrapping_add(1), hi);
+    }
+
+    fn fetch(&mut self) -> u8 {
+        let value = self.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        value
+    }
+
+    fn fetch16(&mut self) -> u16 {
+        let value = self.read16(self.pc);
+        self.pc = self.pc.wrapping_add(2);
+        value
+    }
+
+    fn push(&mut self, value: u16) {
+        self.sp = self.sp.wrapping_sub(2);
+        self.write16(self.sp, value);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let value = self.read16(self.sp);
+        self.sp = self.sp.wrapping_add(2);
+        value
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        self.f & flag != 0
+    }
+
+    fn condition(&self, cc: u8) -> bool {
+        match cc {
+            0 => !self.flag(FLAG_Z),
+            1 => self.flag(FLAG_Z),
+            2 => !self.flag(FLAG_C),
+            3 => self.flag(FLAG_C),
+            4 => !self.flag(FLAG_PV),
+            5 => self.flag(FLAG_PV),
+            6 => !self.flag(FLAG_S),
+            _ => self.flag(FLAG_S),
+        }
+    }
+
+    fn index_reg(&self, index: Index) -> u16 {
+        match index {
+            Index::HL => u16::from_le_bytes([self.l, self.h]),
+            Index::IX => self.ix,
+            Index::IY => self.iy,
+        }
+    }
+
+    fn set_index_reg(&mut self, index: Index, value: u16) {
+        match index {
+            Index::HL => [self.l, self.h] = value.to_le_bytes(),
+            Index::IX => self.ix = value,
+            Index::IY => self.iy = value,
+        }
+    }
+
+    // register pairs BC, DE, HL/IX/IY, SP
+    fn rp(&self, p: u8, index: Index) -> u16 {
+        match p {
+            0 => u16::from_le_bytes([self.c, self.b]),
+            1 => self.de(),
+            2 => self.index_reg(index),
+            _ => self.sp,
+        }
+    }
+
+    fn set_rp(&mut self, p: u8, index: Index, value: u16) {
+        match p {
+            0 => [self.c, self.b] = value.to_le_bytes(),
+            1 => self.set_de(value),
+            2 => self.set_index_reg(index, value),
+            _ => self.sp = value,
+        }
+    }
+
+    // register pairs BC, DE, HL/IX/IY, AF
+    fn rp2(&self, p: u8, index: Index) -> u16 {
+        if p == 3 {
+            u16::from_le_bytes([self.f, self.a])
+        } else {
+            self.rp(p, index)
+        }
+    }
+
+    fn set_rp2(&mut self, p: u8, index: Index, value: u16) {
+        if p == 3 {
+            [self.f, self.a] = value.to_le_bytes();
+        } else {
+            self.set_rp(p, index, value);
+        }
+    }
+
+    // the address of (HL) or (IX+d)/(IY+d), fetching the displacement if needed
+    fn memory_operand(&mut self, index: Index) -> u16 {
+        match index {
+            Index::HL => self.index_reg(Index::HL),
+            _ => {
+                let displacement = self.fetch() as i8;
+                self.index_reg(index).wrapping_add(displacement as u16)
+            }
+        }
+    }
+
+    // 8 bit registers B, C, D, E, H, L, -, A with H and L replaced by the halves of IX/IY
+    fn r(&self, r: u8, index: Index) -> u8 {
+        match r {
+            0 => self.b,
+            1 => self.c,
+            2 => self.d,
+            3 => self.e,
+            4 => (self.index_reg(index) >> 8) as u8,
+            5 => self.index_reg(index) as u8,
+            7 => self.a,
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_r(&mut self, r: u8, index: Index, value: u8) {
+        match r {
+            0 => self.b = value,
+            1 => self.c = value,
+            2 => self.d = value,
+            3 => self.e = value,
+            4 => {
+                let reg = self.index_reg(index);
+                self.set_index_reg(index, (reg & 0xff) | ((value as u16) << 8));
+            }
+            5 => {
+                let reg = self.index_reg(index);
+                self.set_index_reg(index, (reg & 0xff00) | value as u16);
+            }
+            7 => self.a = value,
+            _ => unreachable!(),
+        }
+    }
+
+    fn szp_flags(value: u8) -> u8 {
+        let mut f = value & FLAG_S;
+        if value == 0 {
+            f |= FLAG_Z;
+        }
+        if value.count_ones() & 1 == 0 {
+            f |= FLAG_PV;
+        }
+        f
+    }
+
+    fn add8(&mut self, value: u8, carry: bool) -> u8 {
+        let carry = carry as u8;
+        let result = self.a.wrapping_add(value).wrapping_add(carry);
+        let mut f = result & FLAG_S;
+        if result == 0 {
+            f |= FLAG_Z;
+        }
+        if (self.a & 0xf) + (value & 0xf) + carry > 0xf {
+            f |= FLAG_H;
+        }
+        if (self.a ^ result) & (value ^ result) & 0x80 != 0 {
+            f |= FLAG_PV;
+        }
+        if self.a as u16 + value as u16 + carry as u16 > 0xff {
+            f |= FLAG_C;
+        }
+        self.f = f;
+        result
+    }
+
+    fn sub8(&mut self, value: u8, carry: bool) -> u8 {
+        let carry = carry as u8;
+        let result = self.a.wrapping_sub(value).wrapping_sub(carry);
+        let mut f = (result & FLAG_S) | FLAG_N;
+        if result == 0 {
+            f |= FLAG_Z;
+        }
+        if (self.a & 0xf) < (value & 0xf) + carry {
+            f |= FLAG_H;
+        }
+        if (self.a ^ value) & (self.a ^ result) & 0x80 != 0 {
+            f |= FLAG_PV;
+        }
+        if (self.a as u16) < value as u16 + carry as u16 {
+            f |= FLAG_C;
+        }
+        self.f = f;
+        result
+    }
+
+    fn alu(&mut self, op: u8, value: u8) {
+        let carry = self.flag(FLAG_C);
+        match op {
+            0 => self.a = self.add8(value, false),
+            1 => self.a = self.add8(value, carry),
+            2 => self.a = self.sub8(value, false),
+            3 => self.a = self.sub8(value, carry),
+            4 => {
+                self.a &= value;
+                self.f = Self::szp_flags(self.a) | FLAG_H;
+            }
+            5 => {
+                self.a ^= value;
+                self.f = Self::szp_flags(self.a);
+            }
+            6 => {
+                self.a |= value;
+                self.f = Self::szp_flags(self.a);
+            }
+            _ => {
+                self.sub8(value, false);
+            }
+        }
+    }
+
+    fn inc8(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_add(1);
+        let mut f = (self.f & FLAG_C) | (result & FLAG_S);
+        if result == 0 {
+            f |= FLAG_Z;
+        }
+        if value & 0xf == 0xf {
+            f |= FLAG_H;
+        }
+        if result == 0x80 {
+            f |= FLAG_PV;
+        }
+        self.f = f;
+        result
+    }
+
+    fn dec8(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_sub(1);
+        let mut f = (self.f & FLAG_C) | (result & FLAG_S) | FLAG_N;
+        if result == 0 {
+            f |= FLAG_Z;
+        }
+        if value & 0xf == 0 {
+            f |= FLAG_H;
+        }
+        if result == 0x7f {
+            f |= FLAG_PV;
+        }
+        self.f = f;
+        result
+    }
+
+    fn rotate(&mut self, op: u8, value: u8) -> u8 {
+        let carry_in = self.flag(FLAG_C) as u8;
+        let (result, carry) = match op {
+            0 => (value.rotate_left(1), value >> 7),
+            1 => (value.rotate_right(1), value & 1),
+            2 => ((value << 1) | carry_in, value >> 7),
+            3 => ((value >> 1) | (carry_in << 7), value & 1),
+            4 => (value << 1, value >> 7),
+            5 => ((value >> 1) | (value & 0x80), value & 1),
+            6 => ((value << 1) | 1, value >> 7),
+            _ => (value >> 1, value & 1),
+        };
+        self.f = Self::szp_flags(result) | carry;
+        result
+    }
+
+    fn add16(&mut self, a: u16, b: u16) -> u16 {
+        let result = a.wrapping_add(b);
+        let mut f = self.f & (FLAG_S | FLAG_Z | FLAG_PV);
+        if (a & 0xfff) + (b & 0xfff) > 0xfff {
+            f |= FLAG_H;
+        }
+        if a as u32 + b as u32 > 0xffff {
+            f |= FLAG_C;
+        }
+        self.f = f;
+        result
+    }
+
+    fn adc_sbc16(&mut self, value: u16, subtract: bool) -> u16 {
+        let hl = self.index_reg(Index::HL);
+        let carry = self.flag(FLAG_C) as u32;
+        let (result, half, overflow, carry_out) = if subtract {
+            let result = (hl as u32).wrapping_sub(value as u32).wrapping_sub(carry);
+            (
+                result as u16,
+                (hl & 0xfff) < (value & 0xfff) + carry as u16,
+                (hl ^ value) & (hl ^ result as u16) & 0x8000 != 0,
+                result > 0xffff,
+            )
+        } else {
+            let result = hl as u32 + value as u32 + carry;
+            (
+                result as u16,
+                (hl & 0xfff) + (value & 0xfff) + carry as u16 > 0xfff,
+                !(hl ^ value) & (hl ^ result as u16) & 0x8000 != 0,
+                result > 0xffff,
+            )
+        };
+        let mut f = ((result >> 8) as u8 & FLAG_S) | if subtract { FLAG_N } else { 0 };
+        if result == 0 {
+            f |= FLAG_Z;
+        }
+        if half {
+            f |= FLAG_H;
+        }
+        if overflow {
+            f |= FLAG_PV;
+        }
+        if carry_out {
+            f |= FLAG_C;
+        }
+        self.f = f;
+        result
+    }
+
+    /// Executes a single instruction.
+    pub fn step(&mut self) {
+        let mut opcode = self.fetch();
+        let mut index = Index::HL;
+        let mut cycles = 0;
+        while opcode == 0xdd || opcode == 0xfd {
+            index = if opcode == 0xdd { Index::IX } else { Index::IY };
+            cycles += 4;
+            opcode = self.fetch();
+        }
+        cycles += match opcode {
+            0xcb => self.execute_cb(index),
+            0xed => self.execute_ed(),
+            _ => self.execute(opcode, index),
+        };
+        self.cycles += cycles as u64;
+    }
+
+    // returns the number of T-states, not including prefixes
+    fn execute(&mut self, opcode: u8, index: Index) -> u32 {
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 7;
+        let z = opcode & 7;
+        let p = y >> 1;
+        let q = y & 1;
+        // (IX+d) memory accesses take 8 more T-states than (HL)
+        let extra = if index == Index::HL { 0 } else { 8 };
+        match (x, z) {
+            (0, 0) => match y {
+                0 => 4,
+                1 => {
+                    let (a, f) = (self.shadow[6], self.shadow[7]);
+                    (self.shadow[6], self.shadow[7]) = (self.a, self.f);
+                    (self.a, self.f) = (a, f);
+                    4
+                }
+                2 => {
+                    let displacement = self.fetch() as i8;
+                    self.b = self.b.wrapping_sub(1);
+                    if self.b != 0 {
+                        self.pc = self.pc.wrapping_add(displacement as u16);
+                        13
+                    } else {
+                        8
+                    }
+                }
+                _ => {
+                    let displacement = self.fetch() as i8;
+                    if y == 3 || self.condition(y - 4) {
+                        self.pc = self.pc.wrapping_add(displacement as u16);
+                        12
+                    } else {
+                        7
+                    }
+                }
+            },
+            (0, 1) => {
+                if q == 0 {
+                    let value = self.fetch16();
+                    self.set_rp(p, index, value);
+                    10
+                } else {
+                    let value = self.rp(p, index);
+                    let result = self.add16(self.index_reg(index), value);
+                    self.set_index_reg(index, result);
+                    11
+                }
+            }
+            (0, 2) => {
+                match y {
+                    0 => self.write(self.rp(0, index), self.a),
+                    1 => self.a = self.read(self.rp(0, index)),
+                    2 => self.write(self.rp(1, index), self.a),
+                    3 => self.a = self.read(self.rp(1, index)),
+                    4 => {
+                        let address = self.fetch16();
+                        self.write16(address, self.index_reg(index));
+                        return 16;
+                    }
+                    5 => {
+                        let address = self.fetch16();
+                        let value = self.read16(address);
+                        self.set_index_reg(index, value);
+                        return 16;
+                    }
+                    6 => {
+                        let address = self.fetch16();
+                        self.write(address, self.a);
+                        return 13;
+                    }
+                    _ => {
+                        let address = self.fetch16();
+                        self.a = self.read(address);
+                        return 13;
+                    }
+                }
+                7
+            }
+            (0, 3) => {
+                let value = self.rp(p, index);
+                let value = if q == 0 {
+                    value.wrapping_add(1)
+                } else {
+                    value.wrapping_sub(1)
+                };
+                self.set_rp(p, index, value);
+                6
+            }
+            (0, 4) | (0, 5) => {
+                if y == 6 {
+                    let address = self.memory_operand(index);
+                    let value = self.read(address);
+                    let value = if z == 4 {
+                        self.inc8(value)
+                    } else {
+                        self.dec8(value)
+                    };
+                    self.write(address, value);
+                    11 + extra
+                } else {
+                    let value = self.r(y, index);
+                    let value = if z == 4 {
+                        self.inc8(value)
+                    } else {
+                        self.dec8(value)
+                    };
+                    self.set_r(y, index, value);
+                    4
+                }
+            }
+            (0, 6) => {
+                if y == 6 {
+                    let address = self.memory_operand(index);
+                    let value = self.fetch();
+                    self.write(address, value);
+                    if index == Index::HL { 10 } else { 15 }
+                } else {
+                    let value = self.fetch();
+                    self.set_r(y, index, value);
+                    7
+                }
+            }
+            (0, 7) => {
+                let keep = self.f & (FLAG_S | FLAG_Z | FLAG_PV);
+                match y {
+                    0..=3 => {
+                        let sz_flags = self.f;
+                        self.a = self.rotate(y, self.a);
+                        self.f = (sz_flags & (FLAG_S | FLAG_Z | FLAG_PV)) | (self.f & FLAG_C);
+                    }
+                    4 => {
+                        let mut correction = 0;
+                        let mut carry = self.flag(FLAG_C);
+                        if self.flag(FLAG_H) || self.a & 0xf > 9 {
+                            correction |= 0x06;
+                        }
+                        if carry || self.a > 0x99 {
+                            correction |= 0x60;
+                            carry = true;
+                        }
+                        let a = self.a;
+                        let subtract = self.flag(FLAG_N);
+                        self.a = if subtract {
+                            a.wrapping_sub(correction)
+                        } else {
+                            a.wrapping_add(correction)
+                        };
+                        let half = if subtract {
+                            self.flag(FLAG_H) && a & 0xf < 6
+                        } else {
+                            a & 0xf > 9
+                        };
+                        self.f = Self::szp_flags(self.a)
+                            | (self.f & FLAG_N)
+                            | if half { FLAG_H } else { 0 }
+                            | carry as u8;
+                    }
+                    5 => {
+                        self.a = !self.a;
+                        self.f |= FLAG_H | FLAG_N;
+                    }
+                    6 => self.f = keep | FLAG_C,
+                    _ => {
+                        let carry = self.f & FLAG_C;
+                        self.f = keep | if carry != 0 { FLAG_H } else { FLAG_C };
+                    }
+                }
+                4
+            }
+            (1, _) => {
+                if y == 6 && z == 6 {
+                    panic!("z80: halt at {:04x}", self.pc.wrapping_sub(1));
+                } else if y == 6 {
+                    let address = self.memory_operand(index);
+                    self.write(address, self.r(z, Index::HL));
+                    7 + extra
+                } else if z == 6 {
+                    let address = self.memory_operand(index);
+                    let value = self.read(address);
+                    self.set_r(y, Index::HL, value);
+                    7 + extra
+                } else {
+                    let value = self.r(z, index);
+                    self.set_r(y, index, value);
+                    4
+                }
+            }
+            (2, _) => {
+                if z == 6 {
+                    let address = self.memory_operand(index);
+                    let value = self.read(address);
+                    self.alu(y, value);
+                    7 + extra
+                } else {
+                    let value = self.r(z, index);
+                    self.alu(y, value);
+                    4
+                }
+            }
+            (3, 0) => {
+                if self.condition(y) {
+                    self.pc = self.pop();
+                    11
+                } else {
+                    5
+                }
+            }
+            (3, 1) => {
+                if q == 0 {
+                    let value = self.pop();
+                    self.set_rp2(p, index, value);
+                    10
+                } else {
+                    match p {
+                        0 => {
+                            self.pc = self.pop();
+                            10
+                        }
+                        1 => {
+                            self.exx();
+                            4
+                        }
+                        2 => {
+                            self.pc = self.index_reg(index);
+                            4
+                        }
+                        _ => {
+                            self.sp = self.index_reg(index);
+                            6
+                        }
+                    }
+                }
+            }
+            (3, 2) => {
+                let address = self.fetch16();
+                if self.condition(y) {
+                    self.pc = address;
+                }
+                10
+            }
+            (3, 3) => match y {
+                0 => {
+                    self.pc = self.fetch16();
+                    10
+                }
+                2 | 3 => {
+                    self.fetch();
+                    11
+                }
+                4 => {
+                    let value = self.read16(self.sp);
+                    self.write16(self.sp, self.index_reg(index));
+                    self.set_index_reg(index, value);
+                    19
+                }
+                5 => {
+                    let de = self.de();
+                    self.set_de(self.index_reg(Index::HL));
+                    self.set_index_reg(Index::HL, de);
+                    4
+                }
+                _ => 4,
+            },
+            (3, 4) => {
+                let address = self.fetch16();
+                if self.condition(y) {
+                    self.push(self.pc);
+                    self.pc = address;
+                    17
+                } else {
+                    10
+                }
+            }
+            (3, 5) => {
+                if q == 0 {
+                    self.push(self.rp2(p, index));
+                    11
+                } else {
+                    let address = self.fetch16();
+                    self.push(self.pc);
+                    self.pc = address;
+                    17
+                }
+            }
+            (3, 6) => {
+                let value = self.fetch();
+                self.alu(y, value);
+                7
+            }
+            _ => {
+                self.push(self.pc);
+                self.pc = y as u16 * 8;
+                11
+            }
+        }
+    }
+
+    fn execute_cb(&mut self, index: Index) -> u32 {
+        // with an index prefix, the displacement comes before the opcode
+        let address = (index != Index::HL).then(|| self.memory_operand(index));
+        let opcode = self.fetch();
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 7;
+        let z = opcode & 7;
+        let memory = address.is_some() || z == 6;
+        let address = address.unwrap_or_else(|| self.index_reg(Index::HL));
+        let value = if memory {
+            self.read(address)
+        } else {
+            self.r(z, Index::HL)
+        };
+        let result = match x {
+            0 => self.rotate(y, value),
+            1 => {
+                let bit = value & (1 << y) != 0;
+                let mut f = (self.f & FLAG_C) | FLAG_H;
+                if !bit {
+                    f |= FLAG_Z | FLAG_PV;
+                }
+                if bit && y == 7 {
+                    f |= FLAG_S;
+                }
+                self.f = f;
+                return if memory {
+                    if index == Index::HL { 12 } else { 16 }
+                } else {
+                    8
+                };
+            }
+            2 => value & !(1 << y),
+            _ => value | (1 << y),
+        };
+        if memory {
+            self.write(address, result);
+            if index == Index::HL { 15 } else { 19 }
+        } else {
+            self.set_r(z, Index::HL, result);
+            8
+        }
+    }
+
+    fn execute_ed(&mut self) -> u32 {
+        let opcode = self.fetch();
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 7;
+        let z = opcode & 7;
+        let p = y >> 1;
+        let q = y & 1;
+        match (x, z) {
+            (1, 2) => {
+                let value = self.rp(p, Index::HL);
+                let result = self.adc_sbc16(value, q == 0);
+                self.set_index_reg(Index::HL, result);
+                15
+            }
+            (1, 3) => {
+                let address = self.fetch16();
+                if q == 0 {
+                    self.write16(address, self.rp(p, Index::HL));
+                } else {
+                    let value = self.read16(address);
+                    self.set_rp(p, Index::HL, value);
+                }
+                20
+            }
+            (1, 4) => {
+                let value = self.a;
+                self.a = 0;
+                self.a = self.sub8(value, false);
+                8
+            }
+            (1, 5) => {
+                self.pc = self.pop();
+                14
+            }
+            (2, 0) if y >= 4 => {
+                // LDI, LDD, LDIR, LDDR
+                let hl = self.index_reg(Index::HL);
+                let de = self.de();
+                self.write(de, self.read(hl));
+                let step = if y & 1 == 0 { 1u16 } else { 0xffff };
+                self.set_index_reg(Index::HL, hl.wrapping_add(step));
+                self.set_de(de.wrapping_add(step));
+                let bc = self.rp(0, Index::HL).wrapping_sub(1);
+                self.set_rp(0, Index::HL, bc);
+                self.f &= FLAG_S | FLAG_Z | FLAG_C;
+                if bc != 0 {
+                    self.f |= FLAG_PV;
+                }
+                if y >= 6 && bc != 0 {
+                    self.pc = self.pc.wrapping_sub(2);
+                    21
+                } else {
+                    16
+                }
+            }
+            _ => panic!(
+                "z80: unsupported instruction ed {:02x} at {:04x}",
+                opcode,
+                self.pc.wrapping_sub(2)
+            ),
+        }
+    }
+}
@@ -0,0 +1,30 @@
+// Deterministic text-like test data of `len` bytes with plenty of matches.
+pub fn test_data(len: usize) -> Vec<u8> {
+    let words: [&[u8]; 12] = [
+        b"upkr ",
+        b"packs ",
+        b"data ",
+        b"with ",
+        b"rANS ",
+        b"and ",
+        b"LZ ",
+        b"matches. ",
+        b"small ",
+        b"unpackers ",
+        b"for ",
+        b"8bit\n",
+    ];
+    let mut seed = 12345u32;
+    let mut data = vec![];
+    while data.len() < len {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let word = words[(seed >> 16) as usize % words.len()];
+        data.extend_from_slice(word);
+        // mix in some noise so not everything is a match
+        if seed >> 28 == 0 {
+            data.push((seed >> 8) as u8);
+        }
+    }
+    data.truncate(len);
+    data
+}
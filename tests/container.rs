@@ -0,0 +1,47 @@
+use upkr::Config;
+
+#[test]
+fn round_trip_non_default_config() {
+    let data = b"The container records the config, so unpacking needs no options. ".repeat(20);
+    let config = Config {
+        use_bitstream: true,
+        parity_contexts: 15,
+        literal_context_bits: 8,
+        invert_bit_encoding: true,
+        is_match_bit: false,
+        new_offset_bit: false,
+        continue_value_bit: false,
+        bitstream_is_big_endian: true,
+        simplified_prob_update: true,
+        no_repeated_offsets: true,
+        eof_in_length: true,
+        prob_bits: 12,
+        literal_update_rate: 5,
+        flag_update_rate: 3,
+        length_update_rate: 11,
+        max_offset: 300,
+        max_length: 200,
+    };
+    let container = upkr::pack_container(&data, 2, &config, None);
+    let (header, packed_data) = upkr::read_container_header(&container).unwrap();
+    assert_eq!(format!("{:?}", header.config), format!("{:?}", config));
+    assert_eq!(header.unpacked_size, data.len());
+    assert_eq!(
+        upkr::unpack(packed_data, &config, data.len()).unwrap(),
+        data
+    );
+    assert_eq!(
+        upkr::unpack_container(&container, data.len()).unwrap(),
+        data
+    );
+}
+
+#[test]
+#[should_panic(expected = "parity_contexts has to be between 1 and 15")]
+fn parity_contexts_beyond_header_nibble() {
+    let config = Config {
+        parity_contexts: 16,
+        ..Config::default()
+    };
+    upkr::pack_container(b"Hello, World!", 0, &config, None);
+}
@@ -0,0 +1,20 @@
+mod common;
+
+use upkr::Config;
+
+#[test]
+fn estimate_is_a_close_lower_bound() {
+    let data = common::test_data(800);
+    for (level, prob_bits) in [(2, 8), (2, 12), (10, 8)] {
+        let config = Config {
+            prob_bits,
+            ..Config::default()
+        };
+        let estimate = upkr::estimate_packed_size(&data, level, &config);
+        let packed_size = upkr::pack(&data, level, &config, None).len() as f64;
+        assert!(
+            estimate <= packed_size && packed_size - estimate < 16.,
+            "level {level}, prob_bits {prob_bits}: estimated {estimate}, packed {packed_size}"
+        );
+    }
+}
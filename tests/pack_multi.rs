@@ -0,0 +1,76 @@
+mod common;
+
+use std::time::Duration;
+
+use upkr::{Config, PackOptions};
+
+fn segments(data: &[u8], sizes: &[usize]) -> Vec<Vec<u8>> {
+    let mut pos = 0;
+    sizes
+        .iter()
+        .map(|&size| {
+            pos += size;
+            data[pos - size..pos].to_vec()
+        })
+        .collect()
+}
+
+fn check_round_trip(segments: &[Vec<u8>], config: &Config, options: &PackOptions) {
+    let segment_refs: Vec<&[u8]> = segments.iter().map(|segment| &segment[..]).collect();
+    let packed_data = upkr::pack_multi(&segment_refs, config, options, None);
+    let max_size = segments.iter().map(|segment| segment.len()).sum();
+    assert_eq!(
+        upkr::unpack_multi(&packed_data, config, max_size).unwrap(),
+        segments
+    );
+}
+
+#[test]
+fn round_trip() {
+    let data = common::test_data(30000);
+    // single byte segments, segments matching into earlier ones and a segment larger than
+    // the window
+    let segments = segments(&data, &[1, 1, 1, 100, 3000, 1, 2000, 20000, 500]);
+    let limited_offset = Config {
+        max_offset: 1000,
+        parity_contexts: 2,
+        ..Config::default()
+    };
+    for config in [Config::default(), limited_offset] {
+        for level in [0, 2] {
+            for window_size in [usize::MAX, 4096] {
+                let options = PackOptions {
+                    level,
+                    window_size,
+                    ..Default::default()
+                };
+                check_round_trip(&segments, &config, &options);
+            }
+        }
+    }
+}
+
+#[test]
+fn round_trip_out_of_time() {
+    // the remaining segments are parsed greedily once the time budget is used up
+    let data = common::test_data(10000);
+    let options = PackOptions {
+        level: 9,
+        time_budget: Some(Duration::ZERO),
+        ..Default::default()
+    };
+    check_round_trip(&segments(&data, &[1000; 10]), &Config::default(), &options);
+}
+
+#[test]
+fn round_trip_no_segments() {
+    check_round_trip(&[], &Config::default(), &PackOptions::default());
+}
+
+#[test]
+#[should_panic(expected = "segments can't be empty")]
+fn empty_segment() {
+    // an empty segment marks the end of the stream, so it can't be packed
+    let segments: [&[u8]; 3] = [b"Hello", b"", b"World"];
+    upkr::pack_multi(&segments, &Config::default(), &PackOptions::default(), None);
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use upkr::Config;
+
+#[test]
+fn single_thread_matches_pack() {
+    let config = Config::default();
+    let data = common::test_data(40000);
+    for level in [0, 1, 2] {
+        assert_eq!(
+            upkr::pack_parallel(&data, level, &config, 1, None),
+            upkr::pack(&data, level, &config, None)
+        );
+    }
+}
+
+#[test]
+fn output_is_deterministic() {
+    let config = Config::default();
+    let data = common::test_data(70000);
+    let packed_data = upkr::pack_parallel(&data, 2, &config, 4, None);
+    for _ in 0..4 {
+        assert_eq!(upkr::pack_parallel(&data, 2, &config, 4, None), packed_data);
+    }
+    assert_eq!(
+        upkr::unpack(&packed_data, &config, data.len()).unwrap(),
+        data
+    );
+}
+
+#[test]
+fn round_trip_partial_blocks() {
+    let config = Config {
+        parity_contexts: 2,
+        literal_context_bits: 1,
+        ..Config::default()
+    };
+    // smaller than one block, exactly two blocks and blocks with a remainder
+    for size in [0, 1, 1000, 16 * 1024 - 1, 32 * 1024, 50001] {
+        let data = common::test_data(size);
+        for threads in [2, 3] {
+            let packed_data = upkr::pack_parallel(&data, 2, &config, threads, None);
+            assert_eq!(
+                upkr::unpack(&packed_data, &config, data.len()).unwrap(),
+                data
+            );
+        }
+    }
+}
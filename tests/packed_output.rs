@@ -0,0 +1,17 @@
+use upkr::Config;
+
+// Changes to the internals of the parser must not change its output. The expected files
+// were packed by upkr 0.2.3, before the parser stored its arrivals in a ring buffer.
+#[test]
+fn packed_output_is_unchanged() {
+    let data = include_bytes!("../asm_unpackers/test_data.bin");
+    let expected: [(u8, &[u8]); 3] = [
+        (2, include_bytes!("packed_output/test_data_level2.upk")),
+        (5, include_bytes!("packed_output/test_data_level5.upk")),
+        (9, include_bytes!("packed_output/test_data_level9.upk")),
+    ];
+    for (level, expected) in expected {
+        let packed_data = upkr::pack(data, level, &Config::default(), None);
+        assert!(packed_data == expected, "level {} output changed", level);
+    }
+}
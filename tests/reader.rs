@@ -0,0 +1,88 @@
+mod common;
+
+use std::io::Read;
+
+use upkr::{Config, UnpackError, UnpackReader};
+
+const MAX_SIZE: usize = 1 << 13;
+
+fn read_all(packed_data: &[u8], config: &Config) -> Result<Vec<u8>, UnpackError> {
+    let mut reader = UnpackReader::new(packed_data, config).take(MAX_SIZE as u64 + 1);
+    let mut unpacked_data = vec![];
+    match reader.read_to_end(&mut unpacked_data) {
+        Ok(_) => Ok(unpacked_data),
+        Err(err) => Err(*err
+            .into_inner()
+            .expect("reader errors wrap an UnpackError")
+            .downcast::<UnpackError>()
+            .expect("reader errors wrap an UnpackError")),
+    }
+}
+
+// Checks that `UnpackReader` and `unpack` agree on the result of `packed_data`.
+fn check_same_result(packed_data: &[u8], config: &Config) {
+    let unpack_result = upkr::unpack(packed_data, config, MAX_SIZE);
+    match read_all(packed_data, config) {
+        // the reader has no size limit, reading stopped before the end of the stream
+        Ok(unpacked_data) if unpacked_data.len() > MAX_SIZE => assert!(unpack_result.is_err()),
+        Ok(unpacked_data) => assert_eq!(unpack_result.unwrap(), unpacked_data),
+        Err(err) => assert_eq!(
+            format!("{:?}", unpack_result.unwrap_err()),
+            format!("{:?}", err)
+        ),
+    }
+}
+
+fn test_configs() -> Vec<Config> {
+    let bitstream = Config {
+        use_bitstream: true,
+        bitstream_is_big_endian: true,
+        ..Config::default()
+    };
+    let literal_contexts = Config {
+        literal_context_bits: 2,
+        parity_contexts: 2,
+        ..Config::default()
+    };
+    vec![Config::default(), bitstream, literal_contexts]
+}
+
+#[test]
+fn truncated_streams() {
+    let data = common::test_data(2000);
+    for config in test_configs() {
+        let packed_data = upkr::pack(&data, 2, &config, None);
+        for len in 0..packed_data.len() {
+            check_same_result(&packed_data[..len], &config);
+        }
+    }
+}
+
+#[test]
+fn corrupted_streams() {
+    let data = common::test_data(2000);
+    for config in test_configs() {
+        let packed_data = upkr::pack(&data, 2, &config, None);
+        for pos in 0..packed_data.len() {
+            for mask in [0x01, 0xff] {
+                let mut corrupted = packed_data.clone();
+                corrupted[pos] ^= mask;
+                check_same_result(&corrupted, &config);
+            }
+        }
+    }
+}
+
+#[test]
+fn offset_beyond_window() {
+    let data = common::test_data(2000);
+    let packed_data = upkr::pack(&data, 2, &Config::default(), None);
+    let config = Config {
+        max_offset: 4,
+        ..Config::default()
+    };
+    assert!(matches!(
+        read_all(&packed_data, &config),
+        Err(UnpackError::OffsetBeyondWindow { max_offset: 4, .. })
+    ));
+}